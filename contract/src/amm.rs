@@ -0,0 +1,462 @@
+use async_graphql::{ComplexObject, SimpleObject, Union};
+use linera_sdk::base::{AccountOwner, Amount};
+use serde::{Deserialize, Serialize};
+
+use crate::math::{exp_neg, ln, mul_div_ceil_u128, mul_div_u128, ONE};
+
+/// Fees are expressed in basis points of the traded amount
+pub const BASIS_POINTS: u16 = 10_000;
+
+/// The automated market maker pricing a market, for markets that are not
+/// parimutuel pools
+#[derive(Debug, Clone, Serialize, Deserialize, Union)]
+pub enum AmmState {
+    Lmsr(LmsrState),
+    Cpmm(CpmmPool),
+}
+
+/// A market maker following the logarithmic market scoring rule. Its cost
+/// function is `C(q) = b ln(sum(e^(q_i / b)))`, where `q_i` are the shares
+/// of each outcome sold so far. Every share of the winning outcome pays one
+/// token, and the maker can lose at most its subsidy `C(0) = b ln(n)`.
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+#[graphql(complex)]
+pub struct LmsrState {
+    /// The liquidity parameter `b`
+    pub liquidity: Amount,
+    /// Outstanding shares of each outcome, in the order of the market's outcomes
+    pub shares: Vec<Amount>,
+    /// Who paid the subsidy, and gets back what is left after resolution
+    pub creator: AccountOwner,
+    /// What the creator paid to fund the market maker
+    pub subsidy: Amount,
+}
+
+#[ComplexObject]
+impl LmsrState {
+    /// The price of a share of each outcome, where one token means certainty
+    async fn prices(&self) -> Vec<Amount> {
+        self.current_prices().unwrap_or_default()
+    }
+}
+
+impl LmsrState {
+    /// A market maker with no shares sold, and the subsidy it needs
+    pub fn new(liquidity: Amount, outcome_count: usize, creator: AccountOwner) -> Option<Self> {
+        let mut state = LmsrState {
+            liquidity,
+            shares: vec![Amount::ZERO; outcome_count],
+            creator,
+            subsidy: Amount::ZERO,
+        };
+        let cost = state.cost(&state.shares)?;
+        state.subsidy = Amount::from_attos(cost.checked_add(state.margin())?);
+        Some(state)
+    }
+
+    /// Buy `shares` of an outcome from the market maker, returning their cost
+    pub fn buy(&mut self, outcome: usize, shares: Amount) -> Option<Amount> {
+        let before = self.cost(&self.shares)?;
+        let mut after_shares = self.shares.clone();
+        after_shares[outcome] = after_shares[outcome].try_add(shares).ok()?;
+        let after = self.cost(&after_shares)?;
+        let cost = after.saturating_sub(before).checked_add(self.margin())?;
+        self.shares = after_shares;
+        Some(Amount::from_attos(cost))
+    }
+
+    /// Sell `shares` of an outcome back to the market maker, returning the
+    /// proceeds
+    pub fn sell(&mut self, outcome: usize, shares: Amount) -> Option<Amount> {
+        let before = self.cost(&self.shares)?;
+        let mut after_shares = self.shares.clone();
+        after_shares[outcome] = after_shares[outcome].try_sub(shares).ok()?;
+        let after = self.cost(&after_shares)?;
+        let proceeds = before.saturating_sub(after).saturating_sub(self.margin());
+        self.shares = after_shares;
+        Some(Amount::from_attos(proceeds))
+    }
+
+    /// The price of each outcome, `e^(q_i / b) / sum(e^(q_j / b))`
+    pub fn current_prices(&self) -> Option<Vec<Amount>> {
+        let weights = self.weights(&self.shares)?;
+        let total: u128 = weights.iter().sum();
+        weights
+            .into_iter()
+            .map(|weight| mul_div_u128(weight, ONE, total).map(Amount::from_attos))
+            .collect()
+    }
+
+    /// The cost function in attos. It is computed as
+    /// `max(q) + b ln(sum(e^((q_i - max(q)) / b)))` so that every exponent
+    /// is negative.
+    fn cost(&self, shares: &[Amount]) -> Option<u128> {
+        let highest = shares.iter().map(|shares| u128::from(*shares)).max()?;
+        let total: u128 = self.weights(shares)?.iter().sum();
+        let log = mul_div_u128(u128::from(self.liquidity), ln(total)?, ONE)?;
+        highest.checked_add(log)
+    }
+
+    /// `e^((q_i - max(q)) / b)` for each outcome, in fixed point
+    fn weights(&self, shares: &[Amount]) -> Option<Vec<u128>> {
+        let liquidity = u128::from(self.liquidity);
+        let highest = shares.iter().map(|shares| u128::from(*shares)).max()?;
+        shares
+            .iter()
+            .map(|shares| {
+                let exponent = mul_div_u128(highest - u128::from(*shares), ONE, liquidity)?;
+                Some(exp_neg(exponent))
+            })
+            .collect()
+    }
+
+    /// Charged on every trade, in the maker's favor, to cover the rounding
+    /// of the fixed-point cost function, which is off by at most a few attos
+    /// per token of liquidity
+    fn margin(&self) -> u128 {
+        let outcome_count = self.shares.len() as u128;
+        u128::from(self.liquidity) / ONE * (outcome_count + 16) + 1
+    }
+}
+
+/// A constant-product market maker. The pool holds a reserve of shares of
+/// every outcome, and trades keep the product of the reserves from
+/// decreasing. Collateral paid in mints complete sets, one share of every
+/// outcome per token, and collateral paid out burns them, so the escrow
+/// always covers every share of the winning outcome. Liquidity providers
+/// own the pool in proportion to their LP shares, and fees stay in the
+/// pool, growing the reserves they own.
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+#[graphql(complex)]
+pub struct CpmmPool {
+    /// Shares of each outcome held by the pool, in the order of the market's outcomes
+    pub reserves: Vec<Amount>,
+    pub total_lp_shares: Amount,
+    /// Fee on every trade, in basis points
+    pub fee_bps: u16,
+    /// Fees collected by trades since the pool opened
+    pub fees_collected: Amount,
+    /// The first liquidity provider, who funded the pool at creation
+    pub creator: AccountOwner,
+}
+
+#[ComplexObject]
+impl CpmmPool {
+    /// The price of a share of each outcome, where one token means certainty
+    async fn prices(&self) -> Vec<Amount> {
+        self.current_prices().unwrap_or_default()
+    }
+}
+
+impl CpmmPool {
+    /// A pool funded with `liquidity` complete sets, all owned by the creator
+    pub fn new(
+        liquidity: Amount,
+        outcome_count: usize,
+        fee_bps: u16,
+        creator: AccountOwner,
+    ) -> Self {
+        CpmmPool {
+            reserves: vec![liquidity; outcome_count],
+            total_lp_shares: liquidity,
+            fee_bps,
+            fees_collected: Amount::ZERO,
+            creator,
+        }
+    }
+
+    /// Buy at least `shares` of an outcome for as little collateral as
+    /// possible. Returns the cost and the shares actually received.
+    pub fn buy(&mut self, outcome: usize, shares: Amount) -> Option<(Amount, Amount)> {
+        let shares = u128::from(shares);
+        // What `amount` buys never decreases as `amount` grows, and buying
+        // `shares` never costs more than `shares` plus the fee
+        let mut high = shares.checked_mul(2)?.checked_add(1)?;
+        while self.quote_buy(outcome, high)?.1 < shares {
+            high = high.checked_mul(2)?;
+        }
+        let mut low = 0;
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if self.quote_buy(outcome, mid)?.1 >= shares {
+                high = mid;
+            } else {
+                low = mid + 1;
+            }
+        }
+        let (reserves, received) = self.quote_buy(outcome, low)?;
+        self.reserves = reserves.into_iter().map(Amount::from_attos).collect();
+        let fee = Amount::from_attos(self.fee(low)?);
+        self.fees_collected = self.fees_collected.try_add(fee).ok()?;
+        Some((Amount::from_attos(low), Amount::from_attos(received)))
+    }
+
+    /// Sell `shares` of an outcome for as much collateral as the invariant
+    /// allows, minus the fee. Returns the proceeds.
+    pub fn sell(&mut self, outcome: usize, shares: Amount) -> Option<Amount> {
+        let shares = u128::from(shares);
+        let reserves = self.raw_reserves();
+        let lowest_other = reserves
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| *index != outcome)
+            .map(|(_, reserve)| *reserve)
+            .min()?;
+        // The largest number of complete sets the sold shares can be burnt for
+        let mut low = 0;
+        let mut high = lowest_other.saturating_sub(1).min(reserves[outcome] + shares);
+        while low < high {
+            let mid = high - (high - low) / 2;
+            if self.can_burn(outcome, shares, mid)? {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+        let burnt = low;
+        let fee = self.fee(burnt)?;
+        self.fees_collected = self.fees_collected.try_add(Amount::from_attos(fee)).ok()?;
+        // The fee stays in the pool as complete sets
+        self.reserves = reserves
+            .iter()
+            .enumerate()
+            .map(|(index, reserve)| {
+                let reserve = if index == outcome {
+                    reserve + shares - burnt
+                } else {
+                    reserve - burnt
+                };
+                Amount::from_attos(reserve + fee)
+            })
+            .collect();
+        Some(Amount::from_attos(burnt - fee))
+    }
+
+    /// Add `amount` complete sets to the pool without moving its prices. The
+    /// pool takes all the shares of its largest reserve and the same fraction
+    /// of the others; the rest are returned. A pool every provider left is
+    /// seeded again with the whole sets, at even prices. Returns the LP
+    /// shares minted and the shares of each outcome returned.
+    pub fn add_liquidity(&mut self, amount: Amount) -> Option<(Amount, Vec<Amount>)> {
+        if self.total_lp_shares == Amount::ZERO {
+            for reserve in self.reserves.iter_mut() {
+                *reserve = amount;
+            }
+            self.total_lp_shares = amount;
+            return Some((amount, vec![Amount::ZERO; self.reserves.len()]));
+        }
+        let amount = u128::from(amount);
+        let reserves = self.raw_reserves();
+        let largest = reserves.iter().copied().max()?;
+        let mut returned = Vec::with_capacity(reserves.len());
+        for (index, reserve) in reserves.iter().enumerate() {
+            let added = mul_div_u128(amount, *reserve, largest)?;
+            self.reserves[index] = Amount::from_attos(reserve.checked_add(added)?);
+            returned.push(Amount::from_attos(amount - added));
+        }
+        let minted = mul_div_u128(amount, u128::from(self.total_lp_shares), largest)?;
+        self.total_lp_shares.try_add_assign(Amount::from_attos(minted)).ok()?;
+        Some((Amount::from_attos(minted), returned))
+    }
+
+    /// Burn LP shares for the same fraction of every reserve, returned as
+    /// shares of each outcome
+    pub fn remove_liquidity(&mut self, lp_shares: Amount) -> Option<Vec<Amount>> {
+        let total = u128::from(self.total_lp_shares);
+        let lp_shares = u128::from(lp_shares);
+        if lp_shares > total {
+            return None;
+        }
+        let mut removed = Vec::with_capacity(self.reserves.len());
+        for reserve in self.reserves.iter_mut() {
+            let share = mul_div_u128(u128::from(*reserve), lp_shares, total)?;
+            reserve.try_sub_assign(Amount::from_attos(share)).ok()?;
+            removed.push(Amount::from_attos(share));
+        }
+        self.total_lp_shares = Amount::from_attos(total - lp_shares);
+        Some(removed)
+    }
+
+    /// The price of each outcome, proportional to the inverse of its reserve
+    pub fn current_prices(&self) -> Option<Vec<Amount>> {
+        let reserves = self.raw_reserves();
+        let scale = u128::MAX / reserves.len() as u128;
+        let inverses = reserves
+            .iter()
+            .map(|reserve| scale.checked_div(*reserve))
+            .collect::<Option<Vec<u128>>>()?;
+        let total: u128 = inverses.iter().sum();
+        inverses
+            .into_iter()
+            .map(|inverse| mul_div_u128(inverse, ONE, total).map(Amount::from_attos))
+            .collect()
+    }
+
+    /// The reserves after spending `amount` on an outcome, and the shares
+    /// received. The fee is first added to every reserve, then the rest
+    /// mints complete sets, and the pool keeps just enough of the outcome's
+    /// shares to preserve the product of the reserves.
+    fn quote_buy(&self, outcome: usize, amount: u128) -> Option<(Vec<u128>, u128)> {
+        let fee = self.fee(amount)?;
+        let invested = amount - fee;
+        let before: Vec<u128> = self
+            .raw_reserves()
+            .into_iter()
+            .map(|reserve| reserve.checked_add(fee))
+            .collect::<Option<_>>()?;
+        let mut after = before
+            .iter()
+            .map(|reserve| reserve.checked_add(invested))
+            .collect::<Option<Vec<u128>>>()?;
+        let kept = self.balancing_reserve(outcome, &before, &after)?;
+        let received = after[outcome].checked_sub(kept)?;
+        after[outcome] = kept;
+        Some((after, received))
+    }
+
+    /// Whether selling `shares` of an outcome can burn `sets` complete sets
+    /// without decreasing the product of the reserves
+    fn can_burn(&self, outcome: usize, shares: u128, sets: u128) -> Option<bool> {
+        let before = self.raw_reserves();
+        let after: Vec<u128> = before
+            .iter()
+            .enumerate()
+            .map(|(index, reserve)| {
+                if index == outcome {
+                    reserve + shares - sets
+                } else {
+                    reserve - sets
+                }
+            })
+            .collect();
+        Some(self.balancing_reserve(outcome, &before, &after)? <= after[outcome])
+    }
+
+    /// The smallest reserve of an outcome that keeps the product of the
+    /// reserves when the others go from `before` to `after`, rounded up
+    fn balancing_reserve(&self, outcome: usize, before: &[u128], after: &[u128]) -> Option<u128> {
+        let mut reserve = before[outcome];
+        for index in 0..before.len() {
+            if index != outcome {
+                reserve = mul_div_ceil_u128(reserve, before[index], after[index])?;
+            }
+        }
+        Some(reserve)
+    }
+
+    fn fee(&self, amount: u128) -> Option<u128> {
+        mul_div_ceil_u128(amount, u128::from(self.fee_bps), u128::from(BASIS_POINTS))
+    }
+
+    fn raw_reserves(&self) -> Vec<u128> {
+        self.reserves.iter().map(|reserve| u128::from(*reserve)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use linera_sdk::base::{CryptoHash, Owner};
+
+    fn maker(liquidity: u128, outcome_count: usize) -> LmsrState {
+        let creator = AccountOwner::User(Owner(CryptoHash::from([0; 4])));
+        LmsrState::new(Amount::from_tokens(liquidity), outcome_count, creator).unwrap()
+    }
+
+    #[test]
+    fn test_subsidy_bounds_the_loss() {
+        let mut lmsr = maker(100, 3);
+        // b ln(3) tokens, rounded up
+        assert!(lmsr.subsidy >= Amount::from_attos(109_861_228_866_810_969_139));
+        assert!(lmsr.subsidy < Amount::from_attos(109_861_228_866_810_969_139 + 10_000));
+
+        // Whichever outcome wins, the escrow covers every winning share
+        let mut escrow = lmsr.subsidy;
+        for (outcome, shares) in [(0, 50), (1, 500), (0, 20), (2, 1)] {
+            let cost = lmsr.buy(outcome, Amount::from_tokens(shares)).unwrap();
+            escrow = escrow.try_add(cost).unwrap();
+        }
+        for shares in &lmsr.shares {
+            assert!(*shares <= escrow);
+        }
+    }
+
+    fn pool(liquidity: u128, outcome_count: usize) -> CpmmPool {
+        let creator = AccountOwner::User(Owner(CryptoHash::from([0; 4])));
+        CpmmPool::new(Amount::from_tokens(liquidity), outcome_count, 100, creator)
+    }
+
+    fn product(pool: &CpmmPool) -> f64 {
+        pool.reserves.iter().map(|reserve| u128::from(*reserve) as f64).product()
+    }
+
+    #[test]
+    fn test_cpmm_trades_keep_the_invariant() {
+        let mut pool = pool(1_000, 3);
+        let initial = product(&pool);
+
+        let (cost, received) = pool.buy(2, Amount::from_tokens(100)).unwrap();
+        assert!(received >= Amount::from_tokens(100));
+        // One percent of the cost, rounded up
+        let buy_fee = pool.fees_collected;
+        assert_eq!(u128::from(buy_fee), (u128::from(cost) + 99) / 100);
+        assert!(cost < received);
+        assert!(product(&pool) >= initial);
+        let prices = pool.current_prices().unwrap();
+        assert!(prices[2] > prices[0]);
+
+        // Selling back returns less than was paid, and the fees stay in the pool
+        let proceeds = pool.sell(2, received).unwrap();
+        assert!(proceeds < cost);
+        assert!(product(&pool) > initial);
+        assert!(pool.fees_collected > buy_fee);
+    }
+
+    #[test]
+    fn test_cpmm_liquidity_is_proportional() {
+        let mut pool = pool(1_000, 2);
+        pool.buy(0, Amount::from_tokens(300)).unwrap();
+        let prices = pool.current_prices().unwrap();
+
+        let (minted, returned) = pool.add_liquidity(Amount::from_tokens(500)).unwrap();
+        assert!(minted > Amount::ZERO);
+        // Only the outcome with the smaller reserve is returned
+        assert_eq!(returned[1], Amount::ZERO);
+        assert!(returned[0] > Amount::ZERO);
+        let new_prices = pool.current_prices().unwrap();
+        for (price, new_price) in prices.iter().zip(&new_prices) {
+            let difference = u128::from(*price).abs_diff(u128::from(*new_price));
+            assert!(difference < 1_000);
+        }
+
+        let reserves = pool.reserves.clone();
+        let removed = pool.remove_liquidity(minted).unwrap();
+        for (index, reserve) in reserves.iter().enumerate() {
+            assert_eq!(reserve.try_sub(removed[index]).unwrap(), pool.reserves[index]);
+        }
+
+        // Once every provider left, the pool is seeded again
+        pool.remove_liquidity(pool.total_lp_shares).unwrap();
+        assert_eq!(pool.reserves, [Amount::ZERO, Amount::ZERO]);
+        let (minted, returned) = pool.add_liquidity(Amount::from_tokens(10)).unwrap();
+        assert_eq!(minted, Amount::from_tokens(10));
+        assert_eq!(returned, [Amount::ZERO, Amount::ZERO]);
+        assert_eq!(pool.reserves, [Amount::from_tokens(10), Amount::from_tokens(10)]);
+        assert_eq!(pool.total_lp_shares, Amount::from_tokens(10));
+    }
+
+    #[test]
+    fn test_round_trip_does_not_profit() {
+        let mut lmsr = maker(10, 2);
+        let prices = lmsr.current_prices().unwrap();
+        assert_eq!(prices, vec![Amount::from_attos(ONE / 2); 2]);
+
+        let cost = lmsr.buy(1, Amount::from_tokens(7)).unwrap();
+        let prices = lmsr.current_prices().unwrap();
+        assert!(prices[1] > prices[0]);
+
+        let proceeds = lmsr.sell(1, Amount::from_tokens(7)).unwrap();
+        assert!(proceeds < cost);
+        assert_eq!(lmsr.shares, vec![Amount::ZERO; 2]);
+    }
+}
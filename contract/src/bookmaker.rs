@@ -0,0 +1,113 @@
+use async_graphql::SimpleObject;
+use linera_sdk::base::{AccountOwner, Amount};
+use serde::{Deserialize, Serialize};
+
+use crate::{amm::BASIS_POINTS, math::mul_div};
+
+/// A bookmaker quoting fixed odds on the outcomes of a market. Bets keep the
+/// odds quoted when they were placed, and the bookmaker's reserve covers
+/// what winners are paid beyond the stakes collected.
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+pub struct FixedOddsBook {
+    /// Who quotes the odds, funded the reserve, and keeps what is left after
+    /// resolution
+    pub bookmaker: AccountOwner,
+    /// Decimal odds of each outcome, in basis points: a winning stake pays
+    /// `stake * odds_bps / 10000`, stake included. Zero takes no bets.
+    pub odds_bps: Vec<u32>,
+    /// What the bets on each outcome pay in total if it wins
+    pub liabilities: Vec<Amount>,
+    /// The most the bookmaker may lose on any outcome, beyond the stakes
+    /// collected on all of them
+    pub max_exposure: Amount,
+    /// What the bookmaker funded the market with
+    pub reserve: Amount,
+}
+
+impl FixedOddsBook {
+    /// A book with no odds quoted yet
+    pub fn new(
+        bookmaker: AccountOwner,
+        outcome_count: usize,
+        reserve: Amount,
+        max_exposure: Amount,
+    ) -> Self {
+        FixedOddsBook {
+            bookmaker,
+            odds_bps: vec![0; outcome_count],
+            liabilities: vec![Amount::ZERO; outcome_count],
+            max_exposure,
+            reserve,
+        }
+    }
+
+    /// Whether odds can be quoted: every outcome pays more than its stake,
+    /// or takes no bets
+    pub fn valid_odds(odds_bps: &[u32]) -> bool {
+        odds_bps
+            .iter()
+            .all(|odds| *odds == 0 || *odds > u32::from(BASIS_POINTS))
+    }
+
+    /// What a winning `stake` pays at `odds_bps`, rounded down
+    pub fn payout(stake: Amount, odds_bps: u32) -> Option<Amount> {
+        mul_div(
+            stake,
+            Amount::from_attos(odds_bps.into()),
+            Amount::from_attos(BASIS_POINTS.into()),
+        )
+    }
+
+    /// What the bookmaker loses if an outcome wins, given the stakes
+    /// collected on all outcomes
+    pub fn exposure(&self, outcome: usize, stakes: Amount) -> Amount {
+        self.liabilities[outcome].saturating_sub(stakes)
+    }
+
+    /// The most the bookmaker may lose on an outcome: its cap, and never more
+    /// than its reserve
+    pub fn exposure_limit(&self) -> Amount {
+        self.max_exposure.min(self.reserve)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use linera_sdk::base::{CryptoHash, Owner};
+
+    use super::*;
+
+    fn book() -> FixedOddsBook {
+        let bookmaker = AccountOwner::User(Owner(CryptoHash::from([1u64; 4])));
+        FixedOddsBook::new(bookmaker, 2, Amount::from_tokens(100), Amount::from_tokens(50))
+    }
+
+    #[test]
+    fn test_payout_includes_the_stake() {
+        assert_eq!(
+            FixedOddsBook::payout(Amount::from_tokens(10), 25_000),
+            Some(Amount::from_tokens(25))
+        );
+        // Rounded down to the atto
+        assert_eq!(
+            FixedOddsBook::payout(Amount::from_attos(3), 15_000),
+            Some(Amount::from_attos(4))
+        );
+        assert!(FixedOddsBook::valid_odds(&[0, 10_001, 25_000]));
+        assert!(!FixedOddsBook::valid_odds(&[20_000, 10_000]));
+    }
+
+    #[test]
+    fn test_exposure_is_net_of_all_stakes() {
+        let mut book = book();
+        // 40 tokens on the first outcome at 3.0, 20 on the second at 1.5
+        book.liabilities = vec![Amount::from_tokens(120), Amount::from_tokens(30)];
+        let stakes = Amount::from_tokens(60);
+        assert_eq!(book.exposure(0, stakes), Amount::from_tokens(60));
+        assert_eq!(book.exposure(1, stakes), Amount::ZERO);
+        assert_eq!(book.exposure_limit(), Amount::from_tokens(50));
+
+        book.reserve = Amount::from_tokens(20);
+        assert_eq!(book.exposure_limit(), Amount::from_tokens(20));
+    }
+}
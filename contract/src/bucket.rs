@@ -0,0 +1,143 @@
+use async_graphql::SimpleObject;
+use serde::{Deserialize, Serialize};
+
+/// Most buckets a market may be split into, so that every outcome can be
+/// shown and resolved
+pub const MAX_BUCKETS: usize = 64;
+
+/// How to split a numeric range into buckets, each becoming an outcome. Both
+/// kinds add a bucket below the first cut point and one above the last.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BucketSpec {
+    /// Buckets of `size` from `lower` up to `upper`; the last one is shorter
+    /// if `size` does not divide the range
+    Range { lower: i64, upper: i64, size: i64 },
+    /// Buckets between consecutive cut points, in increasing order
+    CutPoints(Vec<i64>),
+}
+
+impl BucketSpec {
+    /// The cut points between buckets, unless the spec is invalid or makes
+    /// more than `MAX_BUCKETS` buckets
+    pub fn cut_points(&self) -> Option<Vec<i64>> {
+        let cut_points = match self {
+            BucketSpec::Range { lower, upper, size } => {
+                if lower >= upper || *size <= 0 {
+                    return None;
+                }
+                let mut cut_points = vec![*lower];
+                let mut cut = *lower;
+                while cut < *upper {
+                    cut = cut.saturating_add(*size).min(*upper);
+                    cut_points.push(cut);
+                    if cut_points.len() >= MAX_BUCKETS {
+                        return None;
+                    }
+                }
+                cut_points
+            }
+            BucketSpec::CutPoints(cut_points) => cut_points.clone(),
+        };
+        let increasing = cut_points.windows(2).all(|pair| pair[0] < pair[1]);
+        let count = cut_points.len() + 1;
+        (increasing && (2..=MAX_BUCKETS).contains(&count)).then_some(cut_points)
+    }
+}
+
+/// The buckets of a numeric market. Outcome `i` covers the values from cut
+/// point `i - 1` included to cut point `i` excluded.
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+pub struct Buckets {
+    pub cut_points: Vec<i64>,
+    /// The value reported on resolution
+    pub value: Option<i64>,
+}
+
+impl Buckets {
+    pub fn new(cut_points: Vec<i64>) -> Self {
+        Buckets {
+            cut_points,
+            value: None,
+        }
+    }
+
+    /// The index of the bucket a value falls into
+    pub fn bucket_of(&self, value: i64) -> usize {
+        self.cut_points.partition_point(|cut| *cut <= value)
+    }
+
+    /// Names of the buckets, such as "< 50k", "50k to 60k" and ">= 100k"
+    pub fn outcome_names(&self) -> Vec<String> {
+        let cuts = &self.cut_points;
+        let mut names = Vec::with_capacity(cuts.len() + 1);
+        names.push(format!("< {}", short_number(cuts[0])));
+        for pair in cuts.windows(2) {
+            names.push(format!("{} to {}", short_number(pair[0]), short_number(pair[1])));
+        }
+        names.push(format!(">= {}", short_number(cuts[cuts.len() - 1])));
+        names
+    }
+}
+
+/// A number with thousands or millions abbreviated when they are whole
+fn short_number(value: i64) -> String {
+    match value {
+        0 => "0".to_string(),
+        _ if value % 1_000_000 == 0 => format!("{}M", value / 1_000_000),
+        _ if value % 1_000 == 0 => format!("{}k", value / 1_000),
+        _ => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_range_names_and_resolution() {
+        let spec = BucketSpec::Range {
+            lower: 50_000,
+            upper: 100_000,
+            size: 10_000,
+        };
+        let buckets = Buckets::new(spec.cut_points().unwrap());
+        assert_eq!(
+            buckets.outcome_names(),
+            vec![
+                "< 50k",
+                "50k to 60k",
+                "60k to 70k",
+                "70k to 80k",
+                "80k to 90k",
+                "90k to 100k",
+                ">= 100k",
+            ]
+        );
+        assert_eq!(buckets.bucket_of(i64::MIN), 0);
+        assert_eq!(buckets.bucket_of(49_999), 0);
+        // Cut points belong to the bucket above them
+        assert_eq!(buckets.bucket_of(50_000), 1);
+        assert_eq!(buckets.bucket_of(99_999), 5);
+        assert_eq!(buckets.bucket_of(100_000), 6);
+        assert_eq!(buckets.bucket_of(i64::MAX), 6);
+    }
+
+    #[test]
+    fn test_invalid_specs() {
+        let range = |lower, upper, size| BucketSpec::Range { lower, upper, size }.cut_points();
+        // The last bucket is shorter
+        assert_eq!(range(0, 25, 10), Some(vec![0, 10, 20, 25]));
+        assert_eq!(range(10, 10, 1), None);
+        assert_eq!(range(0, 10, 0), None);
+        assert_eq!(range(i64::MIN, i64::MAX, 1), None);
+        assert_eq!(range(i64::MAX - 1, i64::MAX, i64::MAX), Some(vec![i64::MAX - 1, i64::MAX]));
+
+        let cut_points = |cuts: &[i64]| BucketSpec::CutPoints(cuts.to_vec()).cut_points();
+        assert_eq!(cut_points(&[-1_500, 0, 2_000_000]), Some(vec![-1_500, 0, 2_000_000]));
+        assert_eq!(cut_points(&[]), None);
+        assert_eq!(cut_points(&[5, 5]), None);
+        assert_eq!(cut_points(&[7, 3]), None);
+        let names = Buckets::new(vec![-1_500, 0, 2_000_000]).outcome_names();
+        assert_eq!(names, vec!["< -1500", "-1500 to 0", "0 to 2M", ">= 2M"]);
+    }
+}
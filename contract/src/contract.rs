@@ -1,15 +1,50 @@
+use std::collections::BTreeMap;
+
 use linera_sdk::{
-    base::{Account, Amount, Owner, WithContractAbi},
+    base::{
+        Account, AccountOwner, Amount, ApplicationId, ApplicationPermissions, ArithmeticError,
+        ChainId, Owner, WithContractAbi,
+    },
     views::{RootView, View},
     Contract, ContractRuntime,
 };
 
 use crate::{
+    amm::{AmmState, CpmmPool, LmsrState, BASIS_POINTS},
+    bookmaker::FixedOddsBook,
+    bucket::{BucketSpec, Buckets},
     error::CascadeProtocolError,
-    msg::{InstantiationArgument, Message, Operation},
-    state::{Bet, CascadeProtocol, Market, MarketStatus, Outcome},
+    event::{
+        AdminChangedEvent, BetPlacedEvent, CashedOutEvent, ClaimedEvent, CompleteSetChange,
+        CompleteSetEvent, EventPayload, LiquidityChange, LiquidityEvent, ListingChange,
+        ListingEvent, MarketCreatedEvent, MarketResolvedEvent, PositionTransferredEvent,
+        RefundedEvent, TradeSide, TradedEvent,
+    },
+    math::mul_div,
+    migration::{self, LegacyBet, LegacyMarket},
+    msg::{
+        Collateral, InstantiationArgument, MarketMechanism, Message, Operation, OperationResponse,
+        Parameters, SubscriptionTarget,
+    },
+    orderbook::{
+        crossing_priority, lot_cost, match_order, BookSide, Fill, FillKind, Order, OrderSide, LOT,
+    },
+    scalar::ScalarRange,
+    state::{
+        Bet, CascadeProtocol, DustPolicy, Ledger, LiquidityPosition, Listing, Market,
+        MarketStatus, Outcome, PoolSplit, RejectedRequest, Rejection, SharePosition,
+    },
+    token::{FungibleAccount, FungibleOperation, FungibleResponse, FungibleTokenAbi},
 };
 
+/// Where an operation on a market is carried out
+enum Route {
+    /// The operation's message was sent on to the chain that handles it
+    Forwarded(OperationResponse),
+    /// The market is handled on this chain
+    Local,
+}
+
 /// The contract implementation for Cascade Protocol
 pub struct CascadeProtocolContract {
     state: CascadeProtocol,
@@ -24,7 +59,7 @@ impl WithContractAbi for CascadeProtocolContract {
 
 impl Contract for CascadeProtocolContract {
     type Message = Message;
-    type Parameters = ();
+    type Parameters = Parameters;
     type InstantiationArgument = InstantiationArgument;
 
     async fn load(runtime: ContractRuntime<CascadeProtocolContract>) -> Self {
@@ -35,14 +70,23 @@ impl Contract for CascadeProtocolContract {
     }
 
     async fn instantiate(&mut self, argument: InstantiationArgument) {
+        self.runtime
+            .application_parameters()
+            .validate()
+            .expect("Invalid application parameters");
+
         // Set the admin
         self.state.admin.set(Some(argument.admin));
+        self.record_event(EventPayload::AdminChanged(AdminChangedEvent {
+            previous_admin: None,
+            new_admin: argument.admin,
+        }));
         
         // Initialize the ID counter
         self.state.id_counter.set(0);
     }
 
-    async fn execute_operation(&mut self, operation: Operation) -> Self::Message {
+    async fn execute_operation(&mut self, operation: Operation) -> OperationResponse {
         match operation {
             Operation::CreateMarket {
                 question,
@@ -50,50 +94,1138 @@ impl Contract for CascadeProtocolContract {
                 expiry_time,
                 category,
                 parent_id,
+                dedicated_chain,
+                collateral,
+                claim_window,
+                mechanism,
+                buckets,
+                dust_policy,
             } => {
-                self.create_market(question, outcome_names, expiry_time, category, parent_id)
+                self.ensure_market_creator()
                     .await
                     .expect("Failed to create market");
+                let market = self
+                    .create_market(
+                        question,
+                        outcome_names,
+                        expiry_time,
+                        category,
+                        parent_id,
+                        dedicated_chain,
+                        collateral,
+                        claim_window,
+                        mechanism,
+                        buckets,
+                        dust_policy,
+                    )
+                    .await
+                    .expect("Failed to create market");
+                OperationResponse::MarketCreated {
+                    id: market.id,
+                    outcome_ids: market.outcomes.into_iter().map(|o| o.id).collect(),
+                }
             }
             
             Operation::PlaceBet {
                 market_id,
                 outcome_id,
                 amount,
+                min_odds_bps,
             } => {
-                self.place_bet(market_id, outcome_id, amount)
+                let caller = self.caller().expect("Failed to place bet");
+                let collateral = self.stake_collateral(&market_id).await;
+                self.escrow_stake(caller, collateral, amount)
                     .await
                     .expect("Failed to place bet");
+                let reply_to = self.runtime.chain_id();
+                let message = Message::PlaceBet {
+                    owner: caller,
+                    market_id: market_id.clone(),
+                    outcome_id: outcome_id.clone(),
+                    amount,
+                    min_odds_bps,
+                    collateral,
+                    reply_to,
+                };
+                match self.route(&market_id, message, Some((collateral, amount))).await {
+                    Route::Forwarded(response) => response,
+                    Route::Local => {
+                        let bet = self
+                            .place_bet(
+                                caller,
+                                market_id,
+                                outcome_id,
+                                amount,
+                                min_odds_bps,
+                                collateral,
+                                reply_to,
+                            )
+                            .await
+                            .expect("Failed to place bet");
+                        let position = self
+                            .state
+                            .position(caller, &bet.market_id, &bet.outcome_id)
+                            .await
+                            .expect("Failed to load position");
+                        OperationResponse::BetPlaced {
+                            bet_id: bet.id,
+                            position,
+                        }
+                    }
+                }
+            }
+            
+            Operation::BuyShares {
+                market_id,
+                outcome_id,
+                shares,
+                max_cost,
+            } => {
+                let caller = self.caller().expect("Failed to buy shares");
+                let collateral = self.stake_collateral(&market_id).await;
+                self.escrow_stake(caller, collateral, max_cost)
+                    .await
+                    .expect("Failed to buy shares");
+                let reply_to = self.runtime.chain_id();
+                let message = Message::BuyShares {
+                    owner: caller,
+                    market_id: market_id.clone(),
+                    outcome_id: outcome_id.clone(),
+                    shares,
+                    max_cost,
+                    collateral,
+                    reply_to,
+                };
+                match self.route(&market_id, message, Some((collateral, max_cost))).await {
+                    Route::Forwarded(response) => response,
+                    Route::Local => {
+                        let (shares, amount) = self
+                            .buy_shares(
+                                caller, market_id, outcome_id, shares, max_cost, collateral,
+                                reply_to,
+                            )
+                            .await
+                            .expect("Failed to buy shares");
+                        OperationResponse::Traded { shares, amount }
+                    }
+                }
+            }
+            
+            Operation::SellShares {
+                market_id,
+                outcome_id,
+                shares,
+                min_proceeds,
+            } => {
+                let caller = self.caller().expect("Failed to sell shares");
+                let reply_to = self.runtime.chain_id();
+                let message = Message::SellShares {
+                    owner: caller,
+                    market_id: market_id.clone(),
+                    outcome_id: outcome_id.clone(),
+                    shares,
+                    min_proceeds,
+                    reply_to,
+                };
+                match self.route(&market_id, message, None).await {
+                    Route::Forwarded(response) => response,
+                    Route::Local => {
+                        let (shares, amount) = self
+                            .sell_shares(
+                                caller, market_id, outcome_id, shares, min_proceeds, reply_to,
+                            )
+                            .await
+                            .expect("Failed to sell shares");
+                        OperationResponse::Traded { shares, amount }
+                    }
+                }
+            }
+            
+            Operation::TransferPosition {
+                market_id,
+                outcome_id,
+                amount,
+                to,
+                to_chain,
+            } => {
+                let caller = self.caller().expect("Failed to transfer position");
+                let reply_to = self.runtime.chain_id();
+                let message = Message::TransferPosition {
+                    owner: caller,
+                    market_id: market_id.clone(),
+                    outcome_id: outcome_id.clone(),
+                    amount,
+                    to: AccountOwner::User(to),
+                    to_chain,
+                    reply_to,
+                };
+                match self.route(&market_id, message, None).await {
+                    Route::Forwarded(response) => response,
+                    Route::Local => {
+                        let position = self
+                            .transfer_position(
+                                caller,
+                                market_id,
+                                outcome_id,
+                                amount,
+                                AccountOwner::User(to),
+                                to_chain,
+                                reply_to,
+                            )
+                            .await
+                            .expect("Failed to transfer position");
+                        OperationResponse::PositionTransferred { position }
+                    }
+                }
+            }
+            
+            Operation::ListPosition {
+                market_id,
+                outcome_id,
+                amount,
+                price,
+            } => {
+                let caller = self.caller().expect("Failed to list position");
+                let reply_to = self.runtime.chain_id();
+                let message = Message::ListPosition {
+                    owner: caller,
+                    market_id: market_id.clone(),
+                    outcome_id: outcome_id.clone(),
+                    amount,
+                    price,
+                    reply_to,
+                };
+                match self.route(&market_id, message, None).await {
+                    Route::Forwarded(response) => response,
+                    Route::Local => {
+                        let listing = self
+                            .list_position(caller, market_id, outcome_id, amount, price, reply_to)
+                            .await
+                            .expect("Failed to list position");
+                        OperationResponse::Listed {
+                            listing_id: listing.id,
+                        }
+                    }
+                }
+            }
+            
+            Operation::FillListing {
+                market_id,
+                listing_id,
+                max_price,
+            } => {
+                let caller = self.caller().expect("Failed to fill listing");
+                let collateral = self.stake_collateral(&market_id).await;
+                self.escrow_stake(caller, collateral, max_price)
+                    .await
+                    .expect("Failed to fill listing");
+                let reply_to = self.runtime.chain_id();
+                let message = Message::FillListing {
+                    owner: caller,
+                    market_id: market_id.clone(),
+                    listing_id: listing_id.clone(),
+                    max_price,
+                    collateral,
+                    reply_to,
+                };
+                match self.route(&market_id, message, Some((collateral, max_price))).await {
+                    Route::Forwarded(response) => response,
+                    Route::Local => {
+                        let price = self
+                            .fill_listing(
+                                caller, market_id, listing_id, max_price, collateral, reply_to,
+                            )
+                            .await
+                            .expect("Failed to fill listing");
+                        OperationResponse::ListingFilled { price }
+                    }
+                }
+            }
+            
+            Operation::CancelListing {
+                market_id,
+                listing_id,
+            } => {
+                let caller = self.caller().expect("Failed to cancel listing");
+                let reply_to = self.runtime.chain_id();
+                let message = Message::CancelListing {
+                    owner: caller,
+                    market_id: market_id.clone(),
+                    listing_id: listing_id.clone(),
+                    reply_to,
+                };
+                match self.route(&market_id, message, None).await {
+                    Route::Forwarded(response) => response,
+                    Route::Local => {
+                        self.cancel_listing(caller, market_id, listing_id, reply_to)
+                            .await
+                            .expect("Failed to cancel listing");
+                        OperationResponse::Ok
+                    }
+                }
+            }
+            
+            Operation::MintCompleteSet { market_id, amount } => {
+                let caller = self.caller().expect("Failed to mint complete sets");
+                let collateral = self.stake_collateral(&market_id).await;
+                self.escrow_stake(caller, collateral, amount)
+                    .await
+                    .expect("Failed to mint complete sets");
+                let reply_to = self.runtime.chain_id();
+                let message = Message::MintCompleteSet {
+                    owner: caller,
+                    market_id: market_id.clone(),
+                    amount,
+                    collateral,
+                    reply_to,
+                };
+                match self.route(&market_id, message, Some((collateral, amount))).await {
+                    Route::Forwarded(response) => response,
+                    Route::Local => {
+                        let shares = self
+                            .mint_complete_set(caller, market_id, amount, collateral)
+                            .await
+                            .expect("Failed to mint complete sets");
+                        OperationResponse::CompleteSets { shares }
+                    }
+                }
+            }
+            
+            Operation::RedeemCompleteSet { market_id, amount } => {
+                let caller = self.caller().expect("Failed to redeem complete sets");
+                let reply_to = self.runtime.chain_id();
+                let message = Message::RedeemCompleteSet {
+                    owner: caller,
+                    market_id: market_id.clone(),
+                    amount,
+                    reply_to,
+                };
+                match self.route(&market_id, message, None).await {
+                    Route::Forwarded(response) => response,
+                    Route::Local => {
+                        let shares = self
+                            .redeem_complete_set(caller, market_id, amount, reply_to)
+                            .await
+                            .expect("Failed to redeem complete sets");
+                        OperationResponse::CompleteSets { shares }
+                    }
+                }
+            }
+            
+            Operation::AddLiquidity { market_id, amount } => {
+                let caller = self.caller().expect("Failed to add liquidity");
+                let collateral = self.stake_collateral(&market_id).await;
+                self.escrow_stake(caller, collateral, amount)
+                    .await
+                    .expect("Failed to add liquidity");
+                let reply_to = self.runtime.chain_id();
+                let message = Message::AddLiquidity {
+                    owner: caller,
+                    market_id: market_id.clone(),
+                    amount,
+                    collateral,
+                    reply_to,
+                };
+                match self.route(&market_id, message, Some((collateral, amount))).await {
+                    Route::Forwarded(response) => response,
+                    Route::Local => {
+                        let lp_shares = self
+                            .add_liquidity(caller, market_id, amount, collateral, reply_to)
+                            .await
+                            .expect("Failed to add liquidity");
+                        OperationResponse::Liquidity { lp_shares, amount }
+                    }
+                }
+            }
+            
+            Operation::RemoveLiquidity {
+                market_id,
+                lp_shares,
+            } => {
+                let caller = self.caller().expect("Failed to remove liquidity");
+                let reply_to = self.runtime.chain_id();
+                let message = Message::RemoveLiquidity {
+                    owner: caller,
+                    market_id: market_id.clone(),
+                    lp_shares,
+                    reply_to,
+                };
+                match self.route(&market_id, message, None).await {
+                    Route::Forwarded(response) => response,
+                    Route::Local => {
+                        let (lp_shares, amount) = self
+                            .remove_liquidity(caller, market_id, lp_shares, reply_to)
+                            .await
+                            .expect("Failed to remove liquidity");
+                        OperationResponse::Liquidity { lp_shares, amount }
+                    }
+                }
+            }
+            
+            Operation::PlaceLimitOrder {
+                market_id,
+                outcome_id,
+                side,
+                price_bps,
+                shares,
+            } => {
+                let caller = self.caller().expect("Failed to place order");
+                let collateral = self.stake_collateral(&market_id).await;
+                let locked = match side {
+                    OrderSide::Buy => lot_cost(shares, price_bps).expect("Failed to place order"),
+                    OrderSide::Sell => Amount::ZERO,
+                };
+                if locked > Amount::ZERO {
+                    self.escrow_stake(caller, collateral, locked)
+                        .await
+                        .expect("Failed to place order");
+                }
+                let reply_to = self.runtime.chain_id();
+                let message = Message::PlaceLimitOrder {
+                    owner: caller,
+                    market_id: market_id.clone(),
+                    outcome_id: outcome_id.clone(),
+                    side,
+                    price_bps,
+                    shares,
+                    collateral,
+                    reply_to,
+                };
+                match self.route(&market_id, message, Some((collateral, locked))).await {
+                    Route::Forwarded(response) => response,
+                    Route::Local => {
+                        let (order, filled) = self
+                            .place_limit_order(
+                                caller, market_id, outcome_id, side, price_bps, shares, collateral,
+                                reply_to,
+                            )
+                            .await
+                            .expect("Failed to place order");
+                        OperationResponse::OrderPlaced {
+                            order_id: order.id,
+                            filled,
+                            remaining: order.remaining,
+                        }
+                    }
+                }
+            }
+            
+            Operation::CancelOrder {
+                market_id,
+                order_id,
+            } => {
+                let caller = self.caller().expect("Failed to cancel order");
+                let reply_to = self.runtime.chain_id();
+                let message = Message::CancelOrder {
+                    owner: caller,
+                    market_id: market_id.clone(),
+                    order_id: order_id.clone(),
+                    reply_to,
+                };
+                match self.route(&market_id, message, None).await {
+                    Route::Forwarded(response) => response,
+                    Route::Local => {
+                        self.cancel_order(caller, market_id, order_id, reply_to)
+                            .await
+                            .expect("Failed to cancel order");
+                        OperationResponse::Ok
+                    }
+                }
+            }
+            
+            Operation::SetOdds {
+                market_id,
+                odds_bps,
+            } => {
+                let caller = self.caller().expect("Failed to set odds");
+                let reply_to = self.runtime.chain_id();
+                let message = Message::SetOdds {
+                    owner: caller,
+                    market_id: market_id.clone(),
+                    odds_bps: odds_bps.clone(),
+                    reply_to,
+                };
+                match self.route(&market_id, message, None).await {
+                    Route::Forwarded(response) => response,
+                    Route::Local => {
+                        self.set_odds(caller, market_id, odds_bps)
+                            .await
+                            .expect("Failed to set odds");
+                        OperationResponse::Ok
+                    }
+                }
             }
             
             Operation::ResolveMarket {
                 market_id,
                 winning_outcome_id,
             } => {
-                self.resolve_market(market_id, winning_outcome_id)
+                self.ensure_admin().expect("Failed to resolve market");
+                if let Some(host_chain) = self
+                    .host_chain(&market_id)
+                    .await
+                    .expect("Failed to load market chain")
+                {
+                    let message = Message::ResolveMarket {
+                        market_id,
+                        winning_outcome_id,
+                    };
+                    self.forward_to_host(host_chain, message)
+                } else {
+                    self.resolve_market(market_id, winning_outcome_id, None)
+                        .await
+                        .expect("Failed to resolve market");
+                    OperationResponse::Ok
+                }
+            }
+            
+            Operation::ResolveScalarMarket { market_id, value } => {
+                self.ensure_admin().expect("Failed to resolve market");
+                if let Some(host_chain) = self
+                    .host_chain(&market_id)
                     .await
-                    .expect("Failed to resolve market");
+                    .expect("Failed to load market chain")
+                {
+                    let message = Message::ResolveScalarMarket { market_id, value };
+                    self.forward_to_host(host_chain, message)
+                } else {
+                    self.resolve_scalar_market(market_id, value)
+                        .await
+                        .expect("Failed to resolve market");
+                    OperationResponse::Ok
+                }
             }
             
             Operation::ClaimWinnings { market_id } => {
-                self.claim_winnings(market_id)
+                let caller = self.caller().expect("Failed to claim winnings");
+                let reply_to = self.runtime.chain_id();
+                let message = Message::ClaimWinnings {
+                    owner: caller,
+                    market_id: market_id.clone(),
+                    reply_to,
+                };
+                match self.route(&market_id, message, None).await {
+                    Route::Forwarded(response) => response,
+                    Route::Local => {
+                        let (bet_ids, amount) = self
+                            .claim_winnings(caller, market_id, reply_to)
+                            .await
+                            .expect("Failed to claim winnings");
+                        OperationResponse::Claimed { bet_ids, amount }
+                    }
+                }
+            }
+            
+            Operation::CashOut {
+                market_id,
+                bet_id,
+                amount,
+            } => {
+                let caller = self.caller().expect("Failed to cash out");
+                let reply_to = self.runtime.chain_id();
+                let message = Message::CashOut {
+                    owner: caller,
+                    market_id: market_id.clone(),
+                    bet_id: bet_id.clone(),
+                    amount,
+                    reply_to,
+                };
+                match self.route(&market_id, message, None).await {
+                    Route::Forwarded(response) => response,
+                    Route::Local => {
+                        let (_, payout, penalty) = self
+                            .cash_out(caller, market_id, bet_id, amount, reply_to)
+                            .await
+                            .expect("Failed to cash out");
+                        OperationResponse::CashedOut { payout, penalty }
+                    }
+                }
+            }
+            
+            Operation::CancelMarket { market_id } => {
+                self.ensure_admin().expect("Failed to cancel market");
+                let treasury = self.treasury().expect("Failed to cancel market");
+                if let Some(host_chain) = self
+                    .host_chain(&market_id)
                     .await
-                    .expect("Failed to claim winnings");
+                    .expect("Failed to load market chain")
+                {
+                    let message = Message::CancelMarket {
+                        market_id,
+                        treasury,
+                    };
+                    self.forward_to_host(host_chain, message)
+                } else {
+                    self.cancel_market(market_id, treasury)
+                        .await
+                        .expect("Failed to cancel market");
+                    OperationResponse::Ok
+                }
+            }
+            
+            Operation::SweepUnclaimed { market_id } => {
+                if !self.on_app_chain() {
+                    let message = Message::SweepUnclaimed {
+                        market_id,
+                        treasury: None,
+                    };
+                    self.send_to_app_chain(message)
+                } else if let Some(host_chain) = self
+                    .host_chain(&market_id)
+                    .await
+                    .expect("Failed to load market chain")
+                {
+                    let treasury = self.treasury().expect("Failed to sweep unclaimed winnings");
+                    let message = Message::SweepUnclaimed {
+                        market_id,
+                        treasury: Some(treasury),
+                    };
+                    self.forward_to_host(host_chain, message)
+                } else {
+                    let treasury = self.treasury().expect("Failed to sweep unclaimed winnings");
+                    let amount = self
+                        .sweep_unclaimed(market_id, treasury)
+                        .await
+                        .expect("Failed to sweep unclaimed winnings");
+                    OperationResponse::Swept { amount }
+                }
+            }
+            
+            Operation::ExtendClaimDeadline {
+                market_id,
+                claim_deadline,
+            } => {
+                self.ensure_admin().expect("Failed to extend claim deadline");
+                if let Some(host_chain) = self
+                    .host_chain(&market_id)
+                    .await
+                    .expect("Failed to load market chain")
+                {
+                    let message = Message::ExtendClaimDeadline {
+                        market_id,
+                        claim_deadline,
+                    };
+                    self.forward_to_host(host_chain, message)
+                } else {
+                    self.extend_claim_deadline(market_id, claim_deadline)
+                        .await
+                        .expect("Failed to extend claim deadline");
+                    OperationResponse::Ok
+                }
+            }
+            
+            Operation::DistributeWinnings {
+                market_id,
+                batch_size,
+            } => {
+                let message = Message::DistributeWinnings {
+                    market_id: market_id.clone(),
+                    batch_size,
+                };
+                match self.route(&market_id, message, None).await {
+                    Route::Forwarded(response) => response,
+                    Route::Local => {
+                        self.distribute_winnings(market_id, batch_size)
+                            .await
+                            .expect("Failed to distribute winnings")
+                    }
+                }
+            }
+            
+            Operation::Subscribe { target } => {
+                self.send_to_app_chain(Message::Subscribe { target })
+            }
+            
+            Operation::Unsubscribe { target } => {
+                self.send_to_app_chain(Message::Unsubscribe { target })
+            }
+            
+            Operation::TransferAdmin { new_admin } => {
+                self.transfer_admin(new_admin)
+                    .expect("Failed to transfer admin");
+                OperationResponse::Ok
+            }
+            
+            Operation::ImportLegacyMarket { market, bets } => {
+                self.import_legacy_market(market, bets)
+                    .await
+                    .expect("Failed to import legacy market");
+                OperationResponse::Ok
+            }
+            
+            Operation::AllowMarketCreator { application_id } => {
+                self.ensure_admin().expect("Failed to allow market creator");
+                self.state
+                    .allowed_creators
+                    .insert(&application_id)
+                    .expect("Failed to allow market creator");
+                OperationResponse::Ok
+            }
+            
+            Operation::RevokeMarketCreator { application_id } => {
+                self.ensure_admin().expect("Failed to revoke market creator");
+                self.state
+                    .allowed_creators
+                    .remove(&application_id)
+                    .expect("Failed to revoke market creator");
+                OperationResponse::Ok
             }
-        }
-        
-        // Return empty message by default
-        Message::MarketResolved {
-            market_id: String::new(),
-            winning_outcome_id: String::new(),
         }
     }
 
     async fn execute_message(&mut self, message: Message) {
-        // Handle cross-chain messages if needed in the future
+        let origin = self
+            .runtime
+            .message_id()
+            .expect("Incoming message without an ID")
+            .chain_id;
+        // Instructions and read-model updates are only trusted from the
+        // application chain, and ignored when sent from any other
+        let from_app_chain = origin == self.app_chain_id();
+
         match message {
-            Message::MarketResolved { .. } => {
-                // Handle market resolution notification
+            Message::PlaceBet {
+                owner,
+                market_id,
+                outcome_id,
+                amount,
+                min_odds_bps,
+                collateral,
+                reply_to,
+            } => {
+                self.receive_bet(
+                    owner,
+                    reply_to,
+                    market_id,
+                    outcome_id,
+                    amount,
+                    min_odds_bps,
+                    collateral,
+                )
+                .await;
+            }
+
+            Message::BuyShares {
+                owner,
+                market_id,
+                outcome_id,
+                shares,
+                max_cost,
+                collateral,
+                reply_to,
+            } => {
+                self.receive_buy(
+                    owner, reply_to, market_id, outcome_id, shares, max_cost, collateral,
+                )
+                .await;
+            }
+
+            Message::SellShares {
+                owner,
+                market_id,
+                outcome_id,
+                shares,
+                min_proceeds,
+                reply_to,
+            } => {
+                self.receive_sell(owner, reply_to, market_id, outcome_id, shares, min_proceeds)
+                    .await;
+            }
+
+            Message::PlaceLimitOrder {
+                owner,
+                market_id,
+                outcome_id,
+                side,
+                price_bps,
+                shares,
+                collateral,
+                reply_to,
+            } => {
+                self.receive_limit_order(
+                    owner, reply_to, market_id, outcome_id, side, price_bps, shares, collateral,
+                )
+                .await;
+            }
+
+            Message::CancelOrder {
+                owner,
+                market_id,
+                order_id,
+                reply_to,
+            } => {
+                self.receive_cancel_order(owner, reply_to, market_id, order_id)
+                    .await;
+            }
+
+            Message::TransferPosition {
+                owner,
+                market_id,
+                outcome_id,
+                amount,
+                to,
+                to_chain,
+                reply_to,
+            } => {
+                self.receive_transfer(owner, reply_to, market_id, outcome_id, amount, to, to_chain)
+                    .await;
+            }
+
+            Message::ListPosition {
+                owner,
+                market_id,
+                outcome_id,
+                amount,
+                price,
+                reply_to,
+            } => {
+                self.receive_listing(owner, reply_to, market_id, outcome_id, amount, price)
+                    .await;
+            }
+
+            Message::FillListing {
+                owner,
+                market_id,
+                listing_id,
+                max_price,
+                collateral,
+                reply_to,
+            } => {
+                self.receive_fill(owner, reply_to, market_id, listing_id, max_price, collateral)
+                    .await;
+            }
+
+            Message::CancelListing {
+                owner,
+                market_id,
+                listing_id,
+                reply_to,
+            } => {
+                self.receive_cancel_listing(owner, reply_to, market_id, listing_id)
+                    .await;
+            }
+
+            Message::MintCompleteSet {
+                owner,
+                market_id,
+                amount,
+                collateral,
+                reply_to,
+            } => {
+                self.receive_mint(owner, reply_to, market_id, amount, collateral)
+                    .await;
+            }
+
+            Message::RedeemCompleteSet {
+                owner,
+                market_id,
+                amount,
+                reply_to,
+            } => {
+                self.receive_redeem(owner, reply_to, market_id, amount).await;
+            }
+
+            Message::AddLiquidity {
+                owner,
+                market_id,
+                amount,
+                collateral,
+                reply_to,
+            } => {
+                self.receive_add_liquidity(owner, reply_to, market_id, amount, collateral)
+                    .await;
+            }
+
+            Message::RemoveLiquidity {
+                owner,
+                market_id,
+                lp_shares,
+                reply_to,
+            } => {
+                self.receive_remove_liquidity(owner, reply_to, market_id, lp_shares)
+                    .await;
+            }
+
+            Message::ClaimWinnings {
+                owner,
+                market_id,
+                reply_to,
+            } => {
+                self.receive_claim(owner, reply_to, market_id).await;
+            }
+
+            Message::CashOut {
+                owner,
+                market_id,
+                bet_id,
+                amount,
+                reply_to,
+            } => {
+                self.receive_cash_out(owner, reply_to, market_id, bet_id, amount)
+                    .await;
+            }
+
+            // Instructions from the application chain to a chain hosting markets
+            Message::HostMarket { market } => {
+                if from_app_chain {
+                    self.host_market(market).await.expect("Failed to host market");
+                }
+            }
+
+            Message::ResolveMarket {
+                market_id,
+                winning_outcome_id,
+            } => {
+                if from_app_chain {
+                    self.resolve_market(market_id, winning_outcome_id, None)
+                        .await
+                        .expect("Failed to resolve market");
+                }
+            }
+
+            Message::ResolveScalarMarket { market_id, value } => {
+                if from_app_chain {
+                    self.resolve_scalar_market(market_id, value)
+                        .await
+                        .expect("Failed to resolve market");
+                }
+            }
+
+            Message::CancelMarket {
+                market_id,
+                treasury,
+            } => {
+                if from_app_chain {
+                    self.cancel_market(market_id, treasury)
+                        .await
+                        .expect("Failed to cancel market");
+                }
+            }
+
+            Message::SetOdds {
+                owner,
+                market_id,
+                odds_bps,
+                reply_to,
+            } => {
+                self.receive_set_odds(owner, reply_to, market_id, odds_bps)
+                    .await;
+            }
+
+            Message::ExtendClaimDeadline {
+                market_id,
+                claim_deadline,
+            } => {
+                if from_app_chain {
+                    self.extend_claim_deadline(market_id, claim_deadline)
+                        .await
+                        .expect("Failed to extend claim deadline");
+                }
+            }
+
+            // Anyone may trigger a sweep, but only the application chain
+            // names the treasury
+            Message::SweepUnclaimed {
+                market_id,
+                treasury,
+            } => {
+                let treasury = match treasury {
+                    Some(treasury) if from_app_chain => treasury,
+                    _ => self.treasury().expect("Failed to sweep unclaimed winnings"),
+                };
+                if let Some(host_chain) = self
+                    .host_chain(&market_id)
+                    .await
+                    .expect("Failed to load market chain")
+                {
+                    let message = Message::SweepUnclaimed {
+                        market_id,
+                        treasury: Some(treasury),
+                    };
+                    self.forward_to_host(host_chain, message);
+                } else {
+                    self.sweep_unclaimed(market_id, treasury)
+                        .await
+                        .expect("Failed to sweep unclaimed winnings");
+                }
+            }
+
+            // Anyone may trigger a distribution, so the origin is not checked
+            Message::DistributeWinnings {
+                market_id,
+                batch_size,
+            } => {
+                if let Some(host_chain) = self
+                    .host_chain(&market_id)
+                    .await
+                    .expect("Failed to load market chain")
+                {
+                    let message = Message::DistributeWinnings {
+                        market_id,
+                        batch_size,
+                    };
+                    self.forward_to_host(host_chain, message);
+                } else {
+                    self.distribute_winnings(market_id, batch_size)
+                        .await
+                        .expect("Failed to distribute winnings");
+                }
+            }
+
+            // Replies come from the chain hosting the market, which is either
+            // the application chain or one it opened. They only update the
+            // owner's view of their bets, never the bets of markets hosted here.
+            Message::BetAccepted { bet } => {
+                self.state
+                    .update_mirrored_bets(bet.owner, |bets| bets.push(bet))
+                    .await
+                    .expect("Failed to store bet");
+            }
+
+            Message::BetsUpdated {
+                owner,
+                market_id,
+                bets,
+            } => {
+                self.state
+                    .update_mirrored_bets(owner, |owner_bets| {
+                        owner_bets.retain(|bet| bet.market_id != market_id);
+                        owner_bets.extend(bets);
+                    })
+                    .await
+                    .expect("Failed to store bets");
+            }
+
+            Message::CashedOut { bet } => {
+                self.state
+                    .update_mirrored_bets(bet.owner, |bets| {
+                        let same = |b: &Bet| b.market_id == bet.market_id && b.id == bet.id;
+                        if let Some(position) = bets.iter().position(same) {
+                            bets[position] = bet;
+                        }
+                    })
+                    .await
+                    .expect("Failed to store cash-out");
+            }
+
+            Message::Claimed {
+                owner,
+                market_id,
+                bet_ids,
+                amount: _,
+            } => {
+                self.state
+                    .update_mirrored_bets(owner, |bets| {
+                        for bet in bets.iter_mut() {
+                            if bet.market_id == market_id && bet_ids.contains(&bet.id) {
+                                bet.claimed = true;
+                            }
+                        }
+                    })
+                    .await
+                    .expect("Failed to store claim");
+            }
+
+            Message::Rejected { owner, rejection } => {
+                self.state
+                    .add_rejection(owner, rejection)
+                    .await
+                    .expect("Failed to store rejection");
+            }
+
+            Message::Subscribe { target } => {
+                self.subscribe(target, origin)
+                    .await
+                    .expect("Failed to subscribe");
+            }
+
+            Message::Unsubscribe { target } => {
+                self.state
+                    .remove_subscriber(target, origin)
+                    .await
+                    .expect("Failed to unsubscribe");
+            }
+
+            // Updates of the local read-model. The application chain relays
+            // the updates of the chains hosting its markets to subscribers.
+            Message::MarketCreated { market } => {
+                if from_app_chain {
+                    let market_id = market.id.clone();
+                    self.state
+                        .market_replicas
+                        .insert(&market_id, market)
+                        .expect("Failed to store market");
+                }
+            }
+
+            Message::MarketResolved {
+                market_id,
+                winning_outcome_id,
+                value,
+                claim_deadline,
+            } => {
+                let message = Message::MarketResolved {
+                    market_id: market_id.clone(),
+                    winning_outcome_id: winning_outcome_id.clone(),
+                    value,
+                    claim_deadline,
+                };
+                self.receive_market_update(origin, &market_id, message, |market| {
+                    market.status = MarketStatus::Resolved;
+                    market.winning_outcome_id = Some(winning_outcome_id);
+                    market.claim_deadline = claim_deadline;
+                    if let Some(range) = market.scalar.as_mut() {
+                        range.value = value;
+                    }
+                    if let Some(buckets) = market.buckets.as_mut() {
+                        buckets.value = value;
+                    }
+                })
+                .await
+                .expect("Failed to store market");
+            }
+
+            Message::MarketCancelled { market_id } => {
+                let message = Message::MarketCancelled {
+                    market_id: market_id.clone(),
+                };
+                self.receive_market_update(origin, &market_id, message, |market| {
+                    market.status = MarketStatus::Cancelled;
+                })
+                .await
+                .expect("Failed to store market");
+            }
+
+            Message::OddsUpdated {
+                market_id,
+                odds_bps,
+            } => {
+                let message = Message::OddsUpdated {
+                    market_id: market_id.clone(),
+                    odds_bps: odds_bps.clone(),
+                };
+                self.receive_market_update(origin, &market_id, message, |market| {
+                    if let Some(book) = market.fixed_odds.as_mut() {
+                        book.odds_bps = odds_bps;
+                    }
+                })
+                .await
+                .expect("Failed to store market");
+            }
+
+            Message::ClaimDeadlineExtended {
+                market_id,
+                claim_deadline,
+            } => {
+                let message = Message::ClaimDeadlineExtended {
+                    market_id: market_id.clone(),
+                    claim_deadline,
+                };
+                self.receive_market_update(origin, &market_id, message, |market| {
+                    market.claim_deadline = Some(claim_deadline);
+                })
+                .await
+                .expect("Failed to store market");
             }
         }
     }
@@ -105,6 +1237,7 @@ impl Contract for CascadeProtocolContract {
 
 impl CascadeProtocolContract {
     /// Create a new prediction market
+    #[allow(clippy::too_many_arguments)]
     async fn create_market(
         &mut self,
         question: String,
@@ -112,18 +1245,115 @@ impl CascadeProtocolContract {
         expiry_time: u64,
         category: crate::msg::MarketCategory,
         parent_id: Option<String>,
-    ) -> Result<(), CascadeProtocolError> {
+        dedicated_chain: bool,
+        collateral: Option<Collateral>,
+        claim_window: Option<u64>,
+        mechanism: MarketMechanism,
+        buckets: Option<BucketSpec>,
+        dust_policy: DustPolicy,
+    ) -> Result<Market, CascadeProtocolError> {
+        // The application chain routes markets to the chains hosting them
+        if !self.on_app_chain() {
+            return Err(CascadeProtocolError::NotOnAppChain);
+        }
+
+        // Scalar markets have a long and a short side
+        let scalar = match mechanism {
+            MarketMechanism::Scalar { lower, upper } => {
+                if lower >= upper {
+                    return Err(CascadeProtocolError::InvalidBounds);
+                }
+                Some(ScalarRange::new(lower, upper))
+            }
+            _ => None,
+        };
+        // Bucketed markets have an outcome per bucket
+        let buckets = match buckets {
+            Some(_) if scalar.is_some() => return Err(CascadeProtocolError::UnsupportedMechanism),
+            Some(spec) => {
+                let cut_points = spec.cut_points().ok_or(CascadeProtocolError::InvalidBuckets)?;
+                Some(Buckets::new(cut_points))
+            }
+            None => None,
+        };
+        let outcome_names = match (&scalar, &buckets) {
+            (Some(_), _) => ScalarRange::outcome_names(),
+            (None, Some(buckets)) => buckets.outcome_names(),
+            (None, None) => outcome_names,
+        };
+
         // Validate outcome count
         if outcome_names.len() < 2 {
             return Err(CascadeProtocolError::InvalidOutcomeCount);
         }
 
+        // Validate collateral
+        let parameters = self.runtime.application_parameters();
+        let collateral = collateral.unwrap_or(parameters.default_collateral);
+        if !parameters.allows(collateral) {
+            return Err(CascadeProtocolError::UnsupportedCollateral);
+        }
+
         // Validate expiry time
         let current_time = self.runtime.system_time().micros();
         if expiry_time <= current_time {
             return Err(CascadeProtocolError::InvalidExpiryTime);
         }
 
+        // Set up the market maker, funded by the creator
+        let (amm, funding) = match mechanism {
+            MarketMechanism::Parimutuel => (None, Amount::ZERO),
+            MarketMechanism::Lmsr { liquidity } => {
+                if liquidity == Amount::ZERO {
+                    return Err(CascadeProtocolError::InvalidLiquidity);
+                }
+                let creator = self.caller()?;
+                let lmsr = LmsrState::new(liquidity, outcome_names.len(), creator)
+                    .ok_or(CascadeProtocolError::ArithmeticOverflow)?;
+                let subsidy = lmsr.subsidy;
+                (Some(AmmState::Lmsr(lmsr)), subsidy)
+            }
+            MarketMechanism::Cpmm { liquidity, fee_bps } => {
+                if liquidity == Amount::ZERO {
+                    return Err(CascadeProtocolError::InvalidLiquidity);
+                }
+                if fee_bps >= BASIS_POINTS {
+                    return Err(CascadeProtocolError::InvalidFee);
+                }
+                let creator = self.caller()?;
+                let pool = CpmmPool::new(liquidity, outcome_names.len(), fee_bps, creator);
+                (Some(AmmState::Cpmm(pool)), liquidity)
+            }
+            MarketMechanism::OrderBook => (None, Amount::ZERO),
+            MarketMechanism::FixedOdds { reserve, .. } => {
+                if reserve == Amount::ZERO {
+                    return Err(CascadeProtocolError::InvalidLiquidity);
+                }
+                (None, reserve)
+            }
+            MarketMechanism::Scalar { .. } => (None, Amount::ZERO),
+        };
+        // The bookmaker quotes odds once the market exists
+        let fixed_odds = match mechanism {
+            MarketMechanism::FixedOdds {
+                reserve,
+                max_exposure,
+            } => {
+                let bookmaker = self.caller()?;
+                Some(FixedOddsBook::new(bookmaker, outcome_names.len(), reserve, max_exposure))
+            }
+            _ => None,
+        };
+        let funder = match (&amm, &fixed_odds) {
+            (Some(AmmState::Lmsr(lmsr)), _) => Some(lmsr.creator),
+            (Some(AmmState::Cpmm(pool)), _) => Some(pool.creator),
+            (None, Some(book)) => Some(book.bookmaker),
+            (None, None) => None,
+        };
+        if let Some(funder) = funder {
+            self.escrow_stake(funder, collateral, funding).await?;
+        }
+
         // Generate market ID
         let market_id = self.state.generate_id().await?;
 
@@ -133,46 +1363,97 @@ impl CascadeProtocolContract {
             outcomes.push(Outcome {
                 id: format!("{}_{}", market_id, idx),
                 name,
-                total_staked: 0,
+                total_staked: Amount::ZERO,
             });
         }
 
+        let event = MarketCreatedEvent {
+            market_id: market_id.clone(),
+            question: question.clone(),
+            outcome_ids: outcomes.iter().map(|o| o.id.clone()).collect(),
+            category,
+            parent_id: parent_id.clone(),
+        };
+
         // Create market
         let market = Market {
             id: market_id.clone(),
             question,
             outcomes,
-            total_staked: 0,
+            total_staked: funding,
             status: MarketStatus::Active,
             expiry_time,
             winning_outcome_id: None,
             parent_id,
             category,
+            collateral,
+            claim_window: claim_window.or(parameters.claim_window),
+            claim_deadline: None,
+            amm,
+            order_book: mechanism == MarketMechanism::OrderBook,
+            fixed_odds,
+            scalar,
+            buckets,
+            dust_policy,
         };
 
-        // Save market
-        self.state.add_market(market).await?;
+        // Child markets live on the same chain as their parent
+        let parent_chain = match &market.parent_id {
+            Some(parent_id) => self.host_chain(parent_id).await?,
+            None => None,
+        };
+        let host_chain = match parent_chain {
+            Some(chain_id) => Some(chain_id),
+            None if dedicated_chain => Some(self.open_market_chain()),
+            None => None,
+        };
+        // The host keeps the market, its statistics and its status; this
+        // chain only keeps a read-model, updated by the host
+        if let Some(host_chain) = host_chain {
+            self.state.market_chains.insert(&market_id, host_chain)?;
+            self.release(collateral, host_chain, None, funding);
+            self.runtime
+                .prepare_message(Message::HostMarket { market: market.clone() })
+                .send_to(host_chain);
+            self.state.market_replicas.insert(&market_id, market.clone())?;
+        } else {
+            self.state.market_ledgers.insert(&market_id, Ledger::default())?;
+            self.update_ledger(&market_id, collateral, |ledger| ledger.deposit(funding))
+                .await?;
+            self.open_pool(&market)?;
+            self.state.update_protocol_stats(|stats| {
+                stats.total_escrowed = stats.total_escrowed.saturating_add(funding);
+                stats.record_status_change(None, MarketStatus::Active)
+            })?;
+            self.state.add_market(market.clone()).await?;
+        }
 
-        Ok(())
+        // Notify category subscribers
+        self.notify_subscribers(&market, Message::MarketCreated { market: market.clone() })
+            .await?;
+        self.record_event(EventPayload::MarketCreated(event));
+
+        Ok(market)
     }
 
-    /// Place a bet on a market outcome
+    /// Place a bet on a market outcome. The stake must already be held by
+    /// this chain.
+    #[allow(clippy::too_many_arguments)]
     async fn place_bet(
         &mut self,
+        caller: AccountOwner,
         market_id: String,
         outcome_id: String,
-        amount: u64,
-    ) -> Result<(), CascadeProtocolError> {
+        amount: Amount,
+        min_odds_bps: Option<u32>,
+        collateral: Collateral,
+        payout_chain: ChainId,
+    ) -> Result<Bet, CascadeProtocolError> {
         // Validate amount
-        if amount == 0 {
+        if amount == Amount::ZERO {
             return Err(CascadeProtocolError::InvalidBetAmount);
         }
 
-        // Get the caller
-        let caller = self.runtime
-            .authenticated_signer()
-            .ok_or(CascadeProtocolError::Unauthorized)?;
-
         // Load market
         let mut market = self
             .state
@@ -186,6 +1467,16 @@ impl CascadeProtocolContract {
             return Err(CascadeProtocolError::MarketNotActive);
         }
 
+        // Markets that trade shares take no bets
+        if market.trades_shares() {
+            return Err(CascadeProtocolError::UnsupportedMechanism);
+        }
+
+        // Check that the stake is in the market's collateral
+        if market.collateral != collateral {
+            return Err(CascadeProtocolError::CollateralMismatch);
+        }
+
         // Check expiry
         let current_time = self.runtime.system_time().micros();
         if current_time >= market.expiry_time {
@@ -199,19 +1490,51 @@ impl CascadeProtocolContract {
             .position(|o| o.id == outcome_id)
             .ok_or_else(|| CascadeProtocolError::OutcomeNotFound(outcome_id.clone()))?;
 
-        market.outcomes[outcome_idx].total_staked += amount;
-        market.total_staked += amount;
+        market.outcomes[outcome_idx].total_staked.try_add_assign(amount)?;
+        market.total_staked.try_add_assign(amount)?;
 
-        // Transfer tokens from user to contract
-        let app_chain_id = self.runtime.application_id().creation.chain_id;
-        self.runtime.transfer(
-            None,
-            Account::chain(app_chain_id),
-            Amount::from_tokens(amount.into()),
-        );
+        // Fixed-odds bets lock in the quoted odds, within the bookmaker's limits
+        let odds_bps = match market.fixed_odds.as_mut() {
+            Some(book) => {
+                let odds_bps = book.odds_bps[outcome_idx];
+                if odds_bps == 0 {
+                    return Err(CascadeProtocolError::OddsNotQuoted(outcome_id));
+                }
+                if let Some(minimum) = min_odds_bps.filter(|minimum| odds_bps < *minimum) {
+                    return Err(CascadeProtocolError::OddsBelowMinimum {
+                        minimum,
+                        actual: odds_bps,
+                    });
+                }
+                let payout = FixedOddsBook::payout(amount, odds_bps)
+                    .ok_or(CascadeProtocolError::ArithmeticOverflow)?;
+                book.liabilities[outcome_idx].try_add_assign(payout)?;
+                let stakes = market
+                    .outcomes
+                    .iter()
+                    .try_fold(Amount::ZERO, |stakes, o| stakes.try_add(o.total_staked))?;
+                let exposure = book.exposure(outcome_idx, stakes);
+                let limit = book.exposure_limit();
+                if exposure > limit {
+                    return Err(CascadeProtocolError::ExposureExceeded {
+                        limit,
+                        actual: exposure,
+                    });
+                }
+                Some(odds_bps)
+            }
+            None => None,
+        };
 
         // Create bet record
         let bet_id = self.state.generate_id().await?;
+        let event = BetPlacedEvent {
+            bet_id: bet_id.clone(),
+            market_id: market_id.clone(),
+            outcome_id: outcome_id.clone(),
+            owner: caller,
+            amount,
+        };
         let bet = Bet {
             id: bet_id,
             owner: caller,
@@ -219,142 +1542,3796 @@ impl CascadeProtocolContract {
             outcome_id: outcome_id.clone(),
             amount,
             claimed: false,
+            odds_bps,
         };
 
+        // Record the new odds
+        self.state.record_odds_snapshot(&market, current_time).await?;
+
         // Save updated market and bet
+        self.update_ledger(&market_id, collateral, |ledger| ledger.deposit(amount))
+            .await?;
         self.state.update_market(market).await?;
-        self.state.add_bet(bet).await?;
+        self.state.add_bet(bet.clone()).await?;
+        self.state.payout_chains.insert(&caller, payout_chain)?;
 
-        Ok(())
+        // Update user and protocol statistics
+        self.record_trade(caller, amount).await?;
+        self.state.update_protocol_stats(|stats| {
+            stats.total_escrowed = stats.total_escrowed.saturating_add(amount)
+        });
+        self.record_event(EventPayload::BetPlaced(event));
+
+        Ok(bet)
     }
 
-    /// Resolve a market with the winning outcome (Admin only)
-    async fn resolve_market(
+    /// Re-create an active market of the first deployment, with its bets, in
+    /// this application. Stakes are converted from whole tokens to `Amount`,
+    /// and the admin pays the market's whole stake into the escrow. Nothing
+    /// is imported unless the bets add up to the market's stakes.
+    async fn import_legacy_market(
         &mut self,
-        market_id: String,
-        winning_outcome_id: String,
+        market: LegacyMarket,
+        bets: Vec<LegacyBet>,
     ) -> Result<(), CascadeProtocolError> {
-        // Check admin authorization
-        let caller = self.runtime
-            .authenticated_signer()
-            .ok_or(CascadeProtocolError::Unauthorized)?;
+        self.ensure_admin()?;
 
-        let admin = self.state.admin.get();
-        if *admin != Some(caller) {
-            return Err(CascadeProtocolError::Unauthorized);
+        if market.status != MarketStatus::Active {
+            return Err(CascadeProtocolError::MarketNotActive);
+        }
+        if self.state.markets.contains_key(&market.id).await? {
+            return Err(CascadeProtocolError::MarketAlreadyExists(market.id));
         }
 
-        // Load market
-        let mut market = self
+        // Keep generated IDs from colliding with the imported ones
+        let highest_id = bets
+            .iter()
+            .map(|bet| bet.id.as_str())
+            .chain(market.outcomes.iter().map(|outcome| outcome.id.as_str()))
+            .chain(std::iter::once(market.id.as_str()))
+            .filter_map(migration::id_number)
+            .max()
+            .unwrap_or_default();
+        if highest_id > *self.state.id_counter.get() {
+            self.state.id_counter.set(highest_id);
+        }
+
+        let market = Market::from(market);
+        let bets: Vec<Bet> = bets.into_iter().map(Bet::from).collect();
+        migration::validate(&market, &bets)?;
+
+        let admin = (*self.state.admin.get()).ok_or(CascadeProtocolError::Unauthorized)?;
+        self.escrow_stake(AccountOwner::User(admin), market.collateral, market.total_staked)
+            .await?;
+        self.state.market_ledgers.insert(&market.id, Ledger::default())?;
+        self.update_ledger(&market.id, market.collateral, |ledger| {
+            ledger.deposit(market.total_staked)
+        })
+        .await?;
+        self.state.add_market(market.clone()).await?;
+        let total_escrowed = self
             .state
-            .markets
-            .get(&market_id)
-            .await?
-            .ok_or_else(|| CascadeProtocolError::MarketNotFound(market_id.clone()))?;
+            .protocol_stats
+            .get()
+            .total_escrowed
+            .try_add(market.total_staked)?;
+        self.state.update_protocol_stats(|stats| {
+            stats.total_escrowed = total_escrowed;
+            stats.record_status_change(None, MarketStatus::Active)
+        })?;
 
-        // Verify market is active
-        if market.status != MarketStatus::Active {
-            return Err(CascadeProtocolError::MarketNotActive);
+        for bet in bets {
+            self.record_trade(bet.owner, bet.amount).await?;
+            self.state.add_bet(bet).await?;
         }
 
-        // Verify winning outcome exists
-        if !market.outcomes.iter().any(|o| o.id == winning_outcome_id) {
-            return Err(CascadeProtocolError::OutcomeNotFound(winning_outcome_id.clone()));
+        Ok(())
+    }
+
+    /// Buy shares of an outcome from a market's market maker. `max_cost` was
+    /// escrowed, and what is not spent is returned to `payout_chain`. A
+    /// constant-product pool may give slightly more shares than requested.
+    /// Returns the owner's shares of the outcome and the cost.
+    #[allow(clippy::too_many_arguments)]
+    async fn buy_shares(
+        &mut self,
+        owner: AccountOwner,
+        market_id: String,
+        outcome_id: String,
+        shares: Amount,
+        max_cost: Amount,
+        collateral: Collateral,
+        payout_chain: ChainId,
+    ) -> Result<(Amount, Amount), CascadeProtocolError> {
+        if shares == Amount::ZERO {
+            return Err(CascadeProtocolError::InvalidBetAmount);
+        }
+        let (mut market, outcome_idx) = self.tradable_market(&market_id, &outcome_id).await?;
+        if market.collateral != collateral {
+            return Err(CascadeProtocolError::CollateralMismatch);
         }
 
-        // Update market
-        market.status = MarketStatus::Resolved;
-        market.winning_outcome_id = Some(winning_outcome_id);
+        let fees_before = market.amm_fees();
+        let (cost, shares) = match market.amm.as_mut() {
+            Some(AmmState::Lmsr(lmsr)) => lmsr.buy(outcome_idx, shares).map(|cost| (cost, shares)),
+            Some(AmmState::Cpmm(pool)) => pool.buy(outcome_idx, shares),
+            None => return Err(CascadeProtocolError::UnsupportedMechanism),
+        }
+        .ok_or(CascadeProtocolError::ArithmeticOverflow)?;
+        let fee = market.amm_fees().try_sub(fees_before)?;
+        if cost > max_cost {
+            return Err(CascadeProtocolError::SlippageExceeded {
+                limit: max_cost,
+                actual: cost,
+            });
+        }
+        market.outcomes[outcome_idx].total_staked.try_add_assign(cost)?;
+        market.total_staked.try_add_assign(cost)?;
 
-        // Save updated market
+        let position = self
+            .update_share_position(&market, owner, |position| {
+                position.shares[outcome_idx].try_add_assign(shares)?;
+                position.cost_basis.try_add_assign(cost)
+            })
+            .await?;
+
+        // Keep the cost and return the rest
+        self.update_ledger(&market_id, collateral, |ledger| ledger.deposit(cost))
+            .await?;
+        self.pay_out(owner, payout_chain, collateral, max_cost.saturating_sub(cost));
+
+        let current_time = self.runtime.system_time().micros();
+        self.state.record_odds_snapshot(&market, current_time).await?;
         self.state.update_market(market).await?;
+        self.state.payout_chains.insert(&owner, payout_chain)?;
 
-        Ok(())
+        self.record_trade(owner, cost).await?;
+        self.state.update_protocol_stats(|stats| {
+            stats.total_escrowed = stats.total_escrowed.saturating_add(cost);
+            stats.fees_accrued = stats.fees_accrued.saturating_add(fee);
+        });
+        self.record_event(EventPayload::Traded(TradedEvent {
+            market_id,
+            outcome_id,
+            owner,
+            side: TradeSide::Buy,
+            shares,
+            amount: cost,
+        }));
+
+        Ok((position.shares[outcome_idx], cost))
     }
 
-    /// Claim winnings from a resolved market
-    async fn claim_winnings(
+    /// Sell shares of an outcome back to a market's market maker, paying the
+    /// proceeds to `destination`. Returns the owner's remaining shares of the
+    /// outcome and the proceeds.
+    async fn sell_shares(
+        &mut self,
+        owner: AccountOwner,
+        market_id: String,
+        outcome_id: String,
+        shares: Amount,
+        min_proceeds: Amount,
+        destination: ChainId,
+    ) -> Result<(Amount, Amount), CascadeProtocolError> {
+        if shares == Amount::ZERO {
+            return Err(CascadeProtocolError::InvalidBetAmount);
+        }
+        let (mut market, outcome_idx) = self.tradable_market(&market_id, &outcome_id).await?;
+        self.ensure_shares_held(&market_id, owner, outcome_idx, shares)
+            .await?;
+
+        let fees_before = market.amm_fees();
+        let proceeds = match market.amm.as_mut() {
+            Some(AmmState::Lmsr(lmsr)) => lmsr.sell(outcome_idx, shares),
+            Some(AmmState::Cpmm(pool)) => pool.sell(outcome_idx, shares),
+            None => return Err(CascadeProtocolError::UnsupportedMechanism),
+        }
+        .ok_or(CascadeProtocolError::ArithmeticOverflow)?;
+        let fee = market.amm_fees().try_sub(fees_before)?;
+        if proceeds < min_proceeds {
+            return Err(CascadeProtocolError::SlippageExceeded {
+                limit: min_proceeds,
+                actual: proceeds,
+            });
+        }
+        let outcome = &mut market.outcomes[outcome_idx];
+        outcome.total_staked = outcome.total_staked.saturating_sub(proceeds);
+        market.total_staked.try_sub_assign(proceeds)?;
+
+        let position = self
+            .update_share_position(&market, owner, |position| {
+                position.shares[outcome_idx].try_sub_assign(shares)?;
+                position.cost_basis = position.cost_basis.saturating_sub(proceeds);
+                Ok(())
+            })
+            .await?;
+
+        let collateral = market.collateral;
+        self.withdraw(&market_id, collateral, proceeds).await?;
+        self.pay_out(owner, destination, collateral, proceeds);
+
+        let current_time = self.runtime.system_time().micros();
+        self.state.record_odds_snapshot(&market, current_time).await?;
+        self.state.update_market(market).await?;
+
+        self.state.update_protocol_stats(|stats| {
+            stats.total_escrowed = stats.total_escrowed.saturating_sub(proceeds);
+            stats.fees_accrued = stats.fees_accrued.saturating_add(fee);
+        });
+        self.record_event(EventPayload::Traded(TradedEvent {
+            market_id,
+            outcome_id,
+            owner,
+            side: TradeSide::Sell,
+            shares,
+            amount: proceeds,
+        }));
+
+        Ok((position.shares[outcome_idx], proceeds))
+    }
+
+    /// Place a limit order on a market's order book and match it against the
+    /// resting orders, by price and then by time. The cost of a buy order
+    /// must already be held by this chain. Returns the order, with what is
+    /// left of it resting on the book, and the shares filled.
+    #[allow(clippy::too_many_arguments)]
+    async fn place_limit_order(
         &mut self,
+        owner: AccountOwner,
         market_id: String,
+        outcome_id: String,
+        side: OrderSide,
+        price_bps: u16,
+        shares: Amount,
+        collateral: Collateral,
+        payout_chain: ChainId,
+    ) -> Result<(Order, Amount), CascadeProtocolError> {
+        if shares == Amount::ZERO || u128::from(shares) % LOT != 0 {
+            return Err(CascadeProtocolError::InvalidOrderSize);
+        }
+        if price_bps == 0 || price_bps >= BASIS_POINTS {
+            return Err(CascadeProtocolError::InvalidPrice);
+        }
+        let (mut market, outcome_idx) = self.tradable_market(&market_id, &outcome_id).await?;
+        if !market.order_book {
+            return Err(CascadeProtocolError::UnsupportedMechanism);
+        }
+        if market.collateral != collateral {
+            return Err(CascadeProtocolError::CollateralMismatch);
+        }
+
+        // Lock the cost of a buy order, or the shares of a sell order
+        match side {
+            OrderSide::Buy => {
+                let cost = lot_cost(shares, price_bps)
+                    .ok_or(CascadeProtocolError::ArithmeticOverflow)?;
+                self.update_ledger(&market_id, collateral, |ledger| ledger.deposit(cost))
+                    .await?;
+                market.total_staked.try_add_assign(cost)?;
+                self.state.update_protocol_stats(|stats| {
+                    stats.total_escrowed = stats.total_escrowed.saturating_add(cost)
+                });
+            }
+            OrderSide::Sell => {
+                self.ensure_shares_held(&market_id, owner, outcome_idx, shares)
+                    .await?;
+                self.update_share_position(&market, owner, |position| {
+                    position.shares[outcome_idx].try_sub_assign(shares)
+                })
+                .await?;
+            }
+        }
+        self.state.payout_chains.insert(&owner, payout_chain)?;
+
+        let mut order = Order {
+            id: self.state.generate_id().await?,
+            owner,
+            market_id: market_id.clone(),
+            outcome_id,
+            side,
+            price_bps,
+            shares,
+            remaining: shares,
+            created_at: self.runtime.system_time().micros(),
+        };
+        // Only the resting orders priced to cross are read: on the opposite
+        // side, and on the same side for the other outcome of a binary market
+        let binary = market.outcomes.len() == 2;
+        let bound = crossing_priority(&order);
+        let mut sides = vec![side.opposite()];
+        if binary {
+            sides.push(side);
+        }
+        let mut crossing = Vec::new();
+        for side in sides {
+            let side = BookSide {
+                market_id: market_id.clone(),
+                side,
+            };
+            crossing.extend(self.state.crossing_orders(&side, bound).await?);
+        }
+        crossing.sort_by_key(|(key, _)| key.seq());
+        let mut book: Vec<Order> = crossing.into_iter().map(|(_, order)| order).collect();
+        for fill in match_order(&book, &order, binary) {
+            self.settle_fill(&mut market, &order, &book[fill.maker], &fill)
+                .await?;
+            book[fill.maker].remaining.try_sub_assign(fill.shares)?;
+            order.remaining.try_sub_assign(fill.shares)?;
+            self.state.update_order(book[fill.maker].clone()).await?;
+        }
+        if order.remaining > Amount::ZERO {
+            self.state.add_order(order.clone()).await?;
+        }
+        self.state.update_market(market).await?;
+
+        let filled = shares.try_sub(order.remaining)?;
+        Ok((order, filled))
+    }
+
+    /// Settle a fill between an incoming order and a resting one, at the
+    /// resting order's price. Buyers receive their shares and get back what
+    /// they locked beyond that price; sellers are paid.
+    async fn settle_fill(
+        &mut self,
+        market: &mut Market,
+        taker: &Order,
+        maker: &Order,
+        fill: &Fill,
     ) -> Result<(), CascadeProtocolError> {
-        // Get the caller
-        let caller = self.runtime
-            .authenticated_signer()
-            .ok_or(CascadeProtocolError::Unauthorized)?;
+        let shares = fill.shares;
+        let maker_amount =
+            lot_cost(shares, maker.price_bps).ok_or(CascadeProtocolError::ArithmeticOverflow)?;
+        // Mints and merges split a token per share between both sides
+        let taker_amount = match fill.kind {
+            FillKind::Transfer => maker_amount,
+            FillKind::Mint | FillKind::Merge => shares.try_sub(maker_amount)?,
+        };
 
-        // Load market
-        let market = self
+        for (order, amount) in [(taker, taker_amount), (maker, maker_amount)] {
+            let outcome_idx = market
+                .outcomes
+                .iter()
+                .position(|o| o.id == order.outcome_id)
+                .ok_or_else(|| CascadeProtocolError::OutcomeNotFound(order.outcome_id.clone()))?;
+            let (side, released) = match order.side {
+                OrderSide::Buy => {
+                    self.update_share_position(market, order.owner, |position| {
+                        position.shares[outcome_idx].try_add_assign(shares)?;
+                        position.cost_basis.try_add_assign(amount)
+                    })
+                    .await?;
+                    let locked = lot_cost(shares, order.price_bps)
+                        .ok_or(CascadeProtocolError::ArithmeticOverflow)?;
+                    (TradeSide::Buy, locked.try_sub(amount)?)
+                }
+                OrderSide::Sell => {
+                    self.update_share_position(market, order.owner, |position| {
+                        position.cost_basis = position.cost_basis.saturating_sub(amount);
+                        Ok(())
+                    })
+                    .await?;
+                    (TradeSide::Sell, amount)
+                }
+            };
+            self.release_to_trader(market, order.owner, released).await?;
+
+            self.record_trade(order.owner, amount).await?;
+            self.record_event(EventPayload::Traded(TradedEvent {
+                market_id: market.id.clone(),
+                outcome_id: order.outcome_id.clone(),
+                owner: order.owner,
+                side,
+                shares,
+                amount,
+            }));
+        }
+        Ok(())
+    }
+
+    /// Cancel a resting order of the caller, returning what it locks to
+    /// `destination`
+    async fn cancel_order(
+        &mut self,
+        caller: AccountOwner,
+        market_id: String,
+        order_id: String,
+        destination: ChainId,
+    ) -> Result<(), CascadeProtocolError> {
+        let mut market = self
             .state
             .markets
             .get(&market_id)
             .await?
             .ok_or_else(|| CascadeProtocolError::MarketNotFound(market_id.clone()))?;
+        let order = self
+            .state
+            .resting_order(&order_id)
+            .await?
+            .filter(|order| order.market_id == market_id && order.owner == caller)
+            .ok_or(CascadeProtocolError::OrderNotFound(order_id))?;
 
-        // Verify market is resolved
-        if market.status != MarketStatus::Resolved {
-            return Err(CascadeProtocolError::MarketNotResolved);
-        }
-
-        let winning_outcome_id = market
-            .winning_outcome_id
-            .as_ref()
-            .ok_or(CascadeProtocolError::MarketNotResolved)?;
+        self.state.payout_chains.insert(&caller, destination)?;
+        self.unlock_order(&mut market, &order).await?;
+        self.state.remove_order(&order.id).await?;
+        self.state.update_market(market).await?;
+        Ok(())
+    }
 
-        // Find user's bet on the winning outcome
-        let user_bets = self.state.bets_by_owner.get(&caller).await?.unwrap_or_default();
-        
-        let mut winning_bet = None;
-        for bet in user_bets.iter() {
-            if bet.market_id == market_id && bet.outcome_id == *winning_outcome_id && !bet.claimed {
-                winning_bet = Some(bet.clone());
-                break;
+    /// Return the collateral or the shares an order locks to its owner
+    async fn unlock_order(
+        &mut self,
+        market: &mut Market,
+        order: &Order,
+    ) -> Result<(), CascadeProtocolError> {
+        match order.side {
+            OrderSide::Buy => {
+                let locked = order.locked().ok_or(CascadeProtocolError::ArithmeticOverflow)?;
+                self.release_to_trader(market, order.owner, locked).await
+            }
+            OrderSide::Sell => {
+                let outcome_idx = market
+                    .outcomes
+                    .iter()
+                    .position(|o| o.id == order.outcome_id)
+                    .ok_or_else(|| {
+                        CascadeProtocolError::OutcomeNotFound(order.outcome_id.clone())
+                    })?;
+                self.update_share_position(market, order.owner, |position| {
+                    position.shares[outcome_idx].try_add_assign(order.remaining)
+                })
+                .await?;
+                Ok(())
             }
         }
+    }
 
-        let mut bet = winning_bet.ok_or(CascadeProtocolError::BetNotFound)?;
+    /// Pay collateral held for a market's order book to a trader, on the
+    /// chain they last traded from
+    async fn release_to_trader(
+        &mut self,
+        market: &mut Market,
+        owner: AccountOwner,
+        amount: Amount,
+    ) -> Result<(), CascadeProtocolError> {
+        if amount == Amount::ZERO {
+            return Ok(());
+        }
+        let destination = match self.state.payout_chains.get(&owner).await? {
+            Some(chain_id) => chain_id,
+            None => self.app_chain_id(),
+        };
+        self.withdraw(&market.id, market.collateral, amount).await?;
+        self.pay_out(owner, destination, market.collateral, amount);
+        market.total_staked.try_sub_assign(amount)?;
+        self.state.update_protocol_stats(|stats| {
+            stats.total_escrowed = stats.total_escrowed.saturating_sub(amount)
+        });
+        Ok(())
+    }
 
-        // Check if already claimed
-        if bet.claimed {
-            return Err(CascadeProtocolError::AlreadyClaimed);
+    /// Fail unless an owner holds at least `shares` of an outcome of a market
+    async fn ensure_shares_held(
+        &mut self,
+        market_id: &str,
+        owner: AccountOwner,
+        outcome_idx: usize,
+        shares: Amount,
+    ) -> Result<(), CascadeProtocolError> {
+        let held = self
+            .state
+            .share_positions
+            .get(market_id)
+            .await?
+            .unwrap_or_default()
+            .into_iter()
+            .find(|position| position.owner == owner)
+            .map_or(Amount::ZERO, |position| position.shares[outcome_idx]);
+        if held < shares {
+            return Err(CascadeProtocolError::InsufficientShares {
+                required: shares,
+                available: held,
+            });
         }
+        Ok(())
+    }
 
-        // Calculate payout
-        let winning_outcome = market
+    /// Move `amount` of an owner's position on an outcome of an active
+    /// market to another owner: stakes of bets in parimutuel markets, shares
+    /// otherwise. Chains other than this one that mirror the bets of either
+    /// owner are sent their new bets. Returns the owner's remaining position
+    /// on the outcome.
+    #[allow(clippy::too_many_arguments)]
+    async fn transfer_position(
+        &mut self,
+        owner: AccountOwner,
+        market_id: String,
+        outcome_id: String,
+        amount: Amount,
+        to: AccountOwner,
+        to_chain: Option<ChainId>,
+        reply_to: ChainId,
+    ) -> Result<Amount, CascadeProtocolError> {
+        if amount == Amount::ZERO {
+            return Err(CascadeProtocolError::InvalidBetAmount);
+        }
+        if to == owner {
+            return Err(CascadeProtocolError::InvalidRecipient);
+        }
+        let market = self
+            .state
+            .markets
+            .get(&market_id)
+            .await?
+            .ok_or_else(|| CascadeProtocolError::MarketNotFound(market_id.clone()))?;
+        if market.status != MarketStatus::Active {
+            return Err(CascadeProtocolError::MarketNotActive);
+        }
+        let outcome_idx = market
             .outcomes
             .iter()
-            .find(|o| o.id == *winning_outcome_id)
-            .unwrap();
+            .position(|o| o.id == outcome_id)
+            .ok_or_else(|| CascadeProtocolError::OutcomeNotFound(outcome_id.clone()))?;
+        // The sender may name the recipient's chain, but not move a chain
+        // the recipient already uses
+        if let Some(to_chain) = to_chain {
+            if self.state.payout_chains.get(&to).await?.is_none() {
+                self.state.payout_chains.insert(&to, to_chain)?;
+            }
+        }
 
-        let payout = if winning_outcome.total_staked > 0 {
-            (bet.amount as u128 * market.total_staked as u128 / winning_outcome.total_staked as u128) as u64
+        let position = if market.trades_shares() {
+            // The cost basis follows the shares
+            let (position, cost_basis) =
+                self.take_shares(&market, owner, outcome_idx, amount).await?;
+            self.give_shares(&market, to, outcome_idx, amount, cost_basis)
+                .await?;
+            position
         } else {
-            0
+            let position = self
+                .transfer_stake(owner, &market_id, &outcome_id, amount, to)
+                .await?;
+            self.sync_bets(owner, &market_id, reply_to).await?;
+            if let Some(to_chain) = to_chain {
+                self.sync_bets(to, &market_id, to_chain).await?;
+            }
+            position
         };
 
-        if payout == 0 {
-            return Err(CascadeProtocolError::InsufficientFunds {
-                required: 1,
-                available: 0,
-            });
+        self.record_event(EventPayload::PositionTransferred(PositionTransferredEvent {
+            market_id,
+            outcome_id,
+            from: owner,
+            to,
+            amount,
+        }));
+
+        Ok(position)
+    }
+
+    /// Quote new odds for the outcomes of an active fixed-odds market, on
+    /// behalf of its bookmaker. Bets placed earlier keep their odds.
+    async fn set_odds(
+        &mut self,
+        caller: AccountOwner,
+        market_id: String,
+        odds_bps: Vec<u32>,
+    ) -> Result<(), CascadeProtocolError> {
+        let mut market = self
+            .state
+            .markets
+            .get(&market_id)
+            .await?
+            .ok_or_else(|| CascadeProtocolError::MarketNotFound(market_id.clone()))?;
+        if market.status != MarketStatus::Active {
+            return Err(CascadeProtocolError::MarketNotActive);
         }
+        let outcome_count = market.outcomes.len();
+        let book = market
+            .fixed_odds
+            .as_mut()
+            .ok_or(CascadeProtocolError::UnsupportedMechanism)?;
+        if book.bookmaker != caller {
+            return Err(CascadeProtocolError::Unauthorized);
+        }
+        if odds_bps.len() != outcome_count || !FixedOddsBook::valid_odds(&odds_bps) {
+            return Err(CascadeProtocolError::InvalidOdds);
+        }
+        book.odds_bps = odds_bps.clone();
 
-        // Transfer winnings to user
-        let app_chain_id = self.runtime.application_id().creation.chain_id;
-        self.runtime.transfer(
-            Some(caller),
-            Account::chain(app_chain_id),
-            Amount::from_tokens(payout.into()),
-        );
+        let message = Message::OddsUpdated {
+            market_id,
+            odds_bps,
+        };
+        self.notify_subscribers(&market, message).await?;
+        self.state.update_market(market).await?;
+        Ok(())
+    }
 
-        // Mark bet as claimed
-        bet.claimed = true;
-        self.state.update_bet(bet).await?;
+    /// Take `amount` shares of an outcome from an owner, along with the part
+    /// of their cost basis in proportion to all the shares they hold. Returns
+    /// the owner's remaining shares of the outcome and the cost basis taken.
+    async fn take_shares(
+        &mut self,
+        market: &Market,
+        owner: AccountOwner,
+        outcome_idx: usize,
+        amount: Amount,
+    ) -> Result<(Amount, Amount), CascadeProtocolError> {
+        self.ensure_shares_held(&market.id, owner, outcome_idx, amount)
+            .await?;
+        let mut cost_basis = Amount::ZERO;
+        let position = self
+            .update_share_position(market, owner, |position| {
+                let held = position
+                    .shares
+                    .iter()
+                    .try_fold(Amount::ZERO, |held, shares| held.try_add(*shares))?;
+                cost_basis = mul_div(position.cost_basis, amount, held).unwrap_or(Amount::ZERO);
+                position.cost_basis = position.cost_basis.saturating_sub(cost_basis);
+                position.shares[outcome_idx].try_sub_assign(amount)
+            })
+            .await?;
+        Ok((position.shares[outcome_idx], cost_basis))
+    }
 
+    /// Credit an owner with `amount` shares of an outcome, acquired for
+    /// `cost_basis`
+    async fn give_shares(
+        &mut self,
+        market: &Market,
+        owner: AccountOwner,
+        outcome_idx: usize,
+        amount: Amount,
+        cost_basis: Amount,
+    ) -> Result<(), CascadeProtocolError> {
+        self.update_share_position(market, owner, |position| {
+            position.shares[outcome_idx].try_add_assign(amount)?;
+            position.cost_basis.try_add_assign(cost_basis)
+        })
+        .await?;
         Ok(())
     }
-}
 
-// Define the ABI for the application
- 
+    /// List `amount` of an owner's position on an outcome of a tradable
+    /// market for sale at a total `price`. The stake or the shares are moved
+    /// to the application until the listing is filled or cancelled.
+    async fn list_position(
+        &mut self,
+        owner: AccountOwner,
+        market_id: String,
+        outcome_id: String,
+        amount: Amount,
+        price: Amount,
+        reply_to: ChainId,
+    ) -> Result<Listing, CascadeProtocolError> {
+        if amount == Amount::ZERO {
+            return Err(CascadeProtocolError::InvalidBetAmount);
+        }
+        let (market, outcome_idx) = self.tradable_market(&market_id, &outcome_id).await?;
+        let escrow = self.own_account_owner();
+        let cost_basis = if market.trades_shares() {
+            let (_, cost_basis) = self.take_shares(&market, owner, outcome_idx, amount).await?;
+            cost_basis
+        } else {
+            self.transfer_stake(owner, &market_id, &outcome_id, amount, escrow)
+                .await?;
+            self.sync_bets(owner, &market_id, reply_to).await?;
+            Amount::ZERO
+        };
+        self.state.payout_chains.insert(&owner, reply_to)?;
 
- 
+        let listing = Listing {
+            id: self.state.generate_id().await?,
+            seller: owner,
+            market_id: market_id.clone(),
+            outcome_id,
+            amount,
+            price,
+            cost_basis,
+            created_at: self.runtime.system_time().micros(),
+        };
+        self.state
+            .listings
+            .load_entry_mut(&market_id)
+            .await?
+            .insert(&listing.id, listing.clone())?;
+        self.record_listing_event(&listing, owner, ListingChange::Created);
+
+        Ok(listing)
+    }
+
+    /// Buy a listed position with `max_price`, which this chain must already
+    /// hold. The seller is paid the listing's price on the chain they listed
+    /// from, the rest is returned to the buyer on `reply_to`, and the
+    /// position moves to the buyer. Returns the price paid.
+    async fn fill_listing(
+        &mut self,
+        buyer: AccountOwner,
+        market_id: String,
+        listing_id: String,
+        max_price: Amount,
+        collateral: Collateral,
+        reply_to: ChainId,
+    ) -> Result<Amount, CascadeProtocolError> {
+        let listing = self
+            .state
+            .listing(&market_id, &listing_id)
+            .await?
+            .ok_or(CascadeProtocolError::ListingNotFound(listing_id))?;
+        let (market, outcome_idx) = self.tradable_market(&market_id, &listing.outcome_id).await?;
+        if market.collateral != collateral {
+            return Err(CascadeProtocolError::CollateralMismatch);
+        }
+        if listing.seller == buyer {
+            return Err(CascadeProtocolError::InvalidRecipient);
+        }
+        if listing.price > max_price {
+            return Err(CascadeProtocolError::SlippageExceeded {
+                limit: max_price,
+                actual: listing.price,
+            });
+        }
+        self.state
+            .listings
+            .load_entry_mut(&market_id)
+            .await?
+            .remove(&listing.id)?;
+
+        if market.trades_shares() {
+            self.give_shares(&market, buyer, outcome_idx, listing.amount, listing.price)
+                .await?;
+        } else {
+            let escrow = self.own_account_owner();
+            self.transfer_stake(escrow, &market_id, &listing.outcome_id, listing.amount, buyer)
+                .await?;
+            self.sync_bets(buyer, &market_id, reply_to).await?;
+        }
+        self.state.payout_chains.insert(&buyer, reply_to)?;
+
+        // The price goes straight from the buyer to the seller, without
+        // entering the market's escrow
+        let seller_chain = match self.state.payout_chains.get(&listing.seller).await? {
+            Some(chain_id) => chain_id,
+            None => self.app_chain_id(),
+        };
+        self.pay_out(listing.seller, seller_chain, collateral, listing.price);
+        self.pay_out(buyer, reply_to, collateral, max_price.try_sub(listing.price)?);
+
+        self.record_trade(buyer, listing.price).await?;
+        self.record_listing_event(&listing, buyer, ListingChange::Filled);
+
+        Ok(listing.price)
+    }
+
+    /// Take a listing of the caller off sale, returning the position to them
+    async fn cancel_listing(
+        &mut self,
+        caller: AccountOwner,
+        market_id: String,
+        listing_id: String,
+        reply_to: ChainId,
+    ) -> Result<(), CascadeProtocolError> {
+        let market = self
+            .state
+            .markets
+            .get(&market_id)
+            .await?
+            .ok_or_else(|| CascadeProtocolError::MarketNotFound(market_id.clone()))?;
+        let listing = self
+            .state
+            .listing(&market_id, &listing_id)
+            .await?
+            .filter(|listing| listing.seller == caller)
+            .ok_or(CascadeProtocolError::ListingNotFound(listing_id))?;
+
+        self.state.payout_chains.insert(&caller, reply_to)?;
+        self.return_listing(&market, &listing).await?;
+        self.state
+            .listings
+            .load_entry_mut(&market_id)
+            .await?
+            .remove(&listing.id)?;
+        Ok(())
+    }
+
+    /// Take every listing of a market off sale, before it is resolved or
+    /// cancelled
+    async fn close_listings(&mut self, market: &Market) -> Result<(), CascadeProtocolError> {
+        for listing in self.state.market_listings(&market.id).await? {
+            self.return_listing(market, &listing).await?;
+        }
+        self.state.listings.remove_entry(&market.id)?;
+        Ok(())
+    }
+
+    /// Give a listed position back to its seller
+    async fn return_listing(
+        &mut self,
+        market: &Market,
+        listing: &Listing,
+    ) -> Result<(), CascadeProtocolError> {
+        let outcome_idx = market
+            .outcomes
+            .iter()
+            .position(|o| o.id == listing.outcome_id)
+            .ok_or_else(|| CascadeProtocolError::OutcomeNotFound(listing.outcome_id.clone()))?;
+        if market.trades_shares() {
+            self.give_shares(
+                market,
+                listing.seller,
+                outcome_idx,
+                listing.amount,
+                listing.cost_basis,
+            )
+            .await?;
+        } else {
+            let escrow = self.own_account_owner();
+            self.transfer_stake(
+                escrow,
+                &market.id,
+                &listing.outcome_id,
+                listing.amount,
+                listing.seller,
+            )
+            .await?;
+            if let Some(chain_id) = self.state.payout_chains.get(&listing.seller).await? {
+                self.sync_bets(listing.seller, &market.id, chain_id).await?;
+            }
+        }
+        self.record_listing_event(listing, listing.seller, ListingChange::Cancelled);
+        Ok(())
+    }
+
+    /// Record a change to a listing, made by `owner`
+    fn record_listing_event(
+        &mut self,
+        listing: &Listing,
+        owner: AccountOwner,
+        change: ListingChange,
+    ) {
+        self.record_event(EventPayload::Listing(ListingEvent {
+            listing_id: listing.id.clone(),
+            market_id: listing.market_id.clone(),
+            outcome_id: listing.outcome_id.clone(),
+            owner,
+            change,
+            amount: listing.amount,
+            price: listing.price,
+        }));
+    }
+
+    /// Move `amount` of an owner's unclaimed stakes on an outcome to new bets
+    /// of another owner, oldest bets first. Returns the owner's remaining
+    /// stake on the outcome.
+    async fn transfer_stake(
+        &mut self,
+        owner: AccountOwner,
+        market_id: &str,
+        outcome_id: &str,
+        amount: Amount,
+        to: AccountOwner,
+    ) -> Result<Amount, CascadeProtocolError> {
+        let owner_bets = self.state.owner_bets(&owner).await?;
+        let stakes: Vec<Bet> = owner_bets
+            .into_iter()
+            .filter(|bet| {
+                bet.market_id == market_id && bet.outcome_id == outcome_id && !bet.claimed
+            })
+            .collect();
+        let held = stakes
+            .iter()
+            .try_fold(Amount::ZERO, |held, bet| held.try_add(bet.amount))?;
+        if held < amount {
+            return Err(CascadeProtocolError::InsufficientFunds {
+                required: amount,
+                available: held,
+            });
+        }
+
+        let mut left = amount;
+        for mut bet in stakes {
+            if left == Amount::ZERO {
+                break;
+            }
+            let moved = left.min(bet.amount);
+            left.try_sub_assign(moved)?;
+            bet.amount.try_sub_assign(moved)?;
+            if bet.amount == Amount::ZERO {
+                self.state.remove_bet(&bet).await?;
+            } else {
+                self.state.update_bet(bet.clone()).await?;
+            }
+            let received = Bet {
+                id: self.state.generate_id().await?,
+                owner: to,
+                market_id: market_id.to_string(),
+                outcome_id: outcome_id.to_string(),
+                amount: moved,
+                claimed: false,
+                odds_bps: bet.odds_bps,
+            };
+            self.state.add_bet(received).await?;
+        }
+        Ok(held.try_sub(amount)?)
+    }
+
+    /// Send an owner's bets in a market to a chain mirroring them, unless it
+    /// is this one
+    async fn sync_bets(
+        &mut self,
+        owner: AccountOwner,
+        market_id: &str,
+        chain_id: ChainId,
+    ) -> Result<(), CascadeProtocolError> {
+        if chain_id == self.runtime.chain_id() {
+            return Ok(());
+        }
+        let bets = self
+            .state
+            .owner_bets(&owner)
+            .await?
+            .into_iter()
+            .filter(|bet| bet.market_id == market_id)
+            .collect();
+        let message = Message::BetsUpdated {
+            owner,
+            market_id: market_id.to_string(),
+            bets,
+        };
+        self.runtime.prepare_message(message).send_to(chain_id);
+        Ok(())
+    }
+
+    /// Lock `amount` of collateral in an active market that trades shares,
+    /// crediting the owner with `amount` shares of every outcome. The
+    /// collateral must already be held by this chain. Returns the owner's
+    /// shares.
+    async fn mint_complete_set(
+        &mut self,
+        owner: AccountOwner,
+        market_id: String,
+        amount: Amount,
+        collateral: Collateral,
+    ) -> Result<Vec<Amount>, CascadeProtocolError> {
+        if amount == Amount::ZERO {
+            return Err(CascadeProtocolError::InvalidBetAmount);
+        }
+        let mut market = self
+            .state
+            .markets
+            .get(&market_id)
+            .await?
+            .ok_or_else(|| CascadeProtocolError::MarketNotFound(market_id.clone()))?;
+        if market.status != MarketStatus::Active {
+            return Err(CascadeProtocolError::MarketNotActive);
+        }
+        if self.runtime.system_time().micros() >= market.expiry_time {
+            return Err(CascadeProtocolError::MarketExpired);
+        }
+        if !market.trades_shares() {
+            return Err(CascadeProtocolError::UnsupportedMechanism);
+        }
+        if market.collateral != collateral {
+            return Err(CascadeProtocolError::CollateralMismatch);
+        }
+
+        market.total_staked.try_add_assign(amount)?;
+        let position = self
+            .update_share_position(&market, owner, |position| {
+                for shares in position.shares.iter_mut() {
+                    shares.try_add_assign(amount)?;
+                }
+                position.cost_basis.try_add_assign(amount)
+            })
+            .await?;
+        self.update_ledger(&market_id, collateral, |ledger| ledger.deposit(amount))
+            .await?;
+        self.state.update_market(market).await?;
+
+        self.state.update_protocol_stats(|stats| {
+            stats.total_escrowed = stats.total_escrowed.saturating_add(amount)
+        });
+        self.record_event(EventPayload::CompleteSet(CompleteSetEvent {
+            market_id,
+            owner,
+            change: CompleteSetChange::Minted,
+            amount,
+        }));
+
+        Ok(position.shares)
+    }
+
+    /// Burn `amount` shares of every outcome of a market that trades shares,
+    /// paying `amount` of collateral to `destination`. Sets hold a share of
+    /// the winner, so they can be redeemed after resolution too, until the
+    /// claim deadline. Returns the owner's remaining shares.
+    async fn redeem_complete_set(
+        &mut self,
+        owner: AccountOwner,
+        market_id: String,
+        amount: Amount,
+        destination: ChainId,
+    ) -> Result<Vec<Amount>, CascadeProtocolError> {
+        if amount == Amount::ZERO {
+            return Err(CascadeProtocolError::InvalidBetAmount);
+        }
+        let mut market = self
+            .state
+            .markets
+            .get(&market_id)
+            .await?
+            .ok_or_else(|| CascadeProtocolError::MarketNotFound(market_id.clone()))?;
+        if !market.trades_shares() {
+            return Err(CascadeProtocolError::UnsupportedMechanism);
+        }
+        if market.claim_deadline_passed(self.runtime.system_time().micros()) {
+            return Err(CascadeProtocolError::ClaimWindowClosed);
+        }
+        for outcome_idx in 0..market.outcomes.len() {
+            self.ensure_shares_held(&market_id, owner, outcome_idx, amount)
+                .await?;
+        }
+
+        market.total_staked = market.total_staked.saturating_sub(amount);
+        let position = self
+            .update_share_position(&market, owner, |position| {
+                for shares in position.shares.iter_mut() {
+                    shares.try_sub_assign(amount)?;
+                }
+                position.cost_basis = position.cost_basis.saturating_sub(amount);
+                Ok(())
+            })
+            .await?;
+
+        let collateral = market.collateral;
+        let resolved = market.status == MarketStatus::Resolved;
+        self.withdraw(&market_id, collateral, amount).await?;
+        self.pay_out(owner, destination, collateral, amount);
+        self.state.update_market(market).await?;
+
+        self.state.update_protocol_stats(|stats| {
+            stats.total_escrowed = stats.total_escrowed.saturating_sub(amount);
+            if resolved {
+                stats.unclaimed_winnings = stats.unclaimed_winnings.saturating_sub(amount);
+            }
+        });
+        self.record_event(EventPayload::CompleteSet(CompleteSetEvent {
+            market_id,
+            owner,
+            change: CompleteSetChange::Redeemed,
+            amount,
+        }));
+
+        Ok(position.shares)
+    }
+
+    /// Add collateral to a market's constant-product pool. `amount` must
+    /// already be held by this chain. Shares the pool does not take to keep
+    /// its prices are added to the owner's position. Returns the owner's LP
+    /// shares.
+    async fn add_liquidity(
+        &mut self,
+        owner: AccountOwner,
+        market_id: String,
+        amount: Amount,
+        collateral: Collateral,
+        payout_chain: ChainId,
+    ) -> Result<Amount, CascadeProtocolError> {
+        if amount == Amount::ZERO {
+            return Err(CascadeProtocolError::InvalidBetAmount);
+        }
+        let mut market = self
+            .state
+            .markets
+            .get(&market_id)
+            .await?
+            .ok_or_else(|| CascadeProtocolError::MarketNotFound(market_id.clone()))?;
+        if market.status != MarketStatus::Active {
+            return Err(CascadeProtocolError::MarketNotActive);
+        }
+        if self.runtime.system_time().micros() >= market.expiry_time {
+            return Err(CascadeProtocolError::MarketExpired);
+        }
+        if market.collateral != collateral {
+            return Err(CascadeProtocolError::CollateralMismatch);
+        }
+
+        let Some(AmmState::Cpmm(pool)) = market.amm.as_mut() else {
+            return Err(CascadeProtocolError::UnsupportedMechanism);
+        };
+        let (minted, returned) = pool
+            .add_liquidity(amount)
+            .ok_or(CascadeProtocolError::ArithmeticOverflow)?;
+        market.total_staked.try_add_assign(amount)?;
+
+        if returned.iter().any(|shares| *shares > Amount::ZERO) {
+            self.update_share_position(&market, owner, |position| {
+                for (held, shares) in position.shares.iter_mut().zip(returned) {
+                    held.try_add_assign(shares)?;
+                }
+                Ok(())
+            })
+            .await?;
+        }
+        let lp_shares = self
+            .update_liquidity_position(&market_id, owner, |lp_shares| {
+                lp_shares.try_add_assign(minted)
+            })
+            .await?;
+
+        self.update_ledger(&market_id, collateral, |ledger| ledger.deposit(amount))
+            .await?;
+        self.state.update_market(market).await?;
+        self.state.payout_chains.insert(&owner, payout_chain)?;
+
+        self.state.update_protocol_stats(|stats| {
+            stats.total_escrowed = stats.total_escrowed.saturating_add(amount)
+        });
+        self.record_event(EventPayload::Liquidity(LiquidityEvent {
+            market_id,
+            owner,
+            change: LiquidityChange::Added,
+            lp_shares: minted,
+            amount,
+        }));
+
+        Ok(lp_shares)
+    }
+
+    /// Burn LP shares of a market's constant-product pool. The complete sets
+    /// among the shares removed are paid out to `destination`, and the rest
+    /// are added to the owner's position. Returns the owner's remaining LP
+    /// shares and the payout.
+    async fn remove_liquidity(
+        &mut self,
+        owner: AccountOwner,
+        market_id: String,
+        lp_shares: Amount,
+        destination: ChainId,
+    ) -> Result<(Amount, Amount), CascadeProtocolError> {
+        if lp_shares == Amount::ZERO {
+            return Err(CascadeProtocolError::InvalidBetAmount);
+        }
+        let mut market = self
+            .state
+            .markets
+            .get(&market_id)
+            .await?
+            .ok_or_else(|| CascadeProtocolError::MarketNotFound(market_id.clone()))?;
+        if market.claim_deadline_passed(self.runtime.system_time().micros()) {
+            return Err(CascadeProtocolError::ClaimWindowClosed);
+        }
+
+        let held = self
+            .state
+            .liquidity_positions
+            .get(&market_id)
+            .await?
+            .unwrap_or_default()
+            .into_iter()
+            .find(|position| position.owner == owner)
+            .map_or(Amount::ZERO, |position| position.lp_shares);
+        if held < lp_shares {
+            return Err(CascadeProtocolError::InsufficientLiquidity {
+                required: lp_shares,
+                available: held,
+            });
+        }
+
+        let Some(AmmState::Cpmm(pool)) = market.amm.as_mut() else {
+            return Err(CascadeProtocolError::UnsupportedMechanism);
+        };
+        let removed = pool
+            .remove_liquidity(lp_shares)
+            .ok_or(CascadeProtocolError::ArithmeticOverflow)?;
+        let complete_sets = removed.iter().copied().min().unwrap_or(Amount::ZERO);
+        market.total_staked = market.total_staked.saturating_sub(complete_sets);
+
+        if removed.iter().any(|shares| *shares > complete_sets) {
+            self.update_share_position(&market, owner, |position| {
+                for (owned, shares) in position.shares.iter_mut().zip(removed) {
+                    owned.try_add_assign(shares.try_sub(complete_sets)?)?;
+                }
+                Ok(())
+            })
+            .await?;
+        }
+        let remaining = self
+            .update_liquidity_position(&market_id, owner, |owned| owned.try_sub_assign(lp_shares))
+            .await?;
+
+        let collateral = market.collateral;
+        let resolved = market.status == MarketStatus::Resolved;
+        self.withdraw(&market_id, collateral, complete_sets).await?;
+        self.pay_out(owner, destination, collateral, complete_sets);
+        self.state.update_market(market).await?;
+
+        self.state.update_protocol_stats(|stats| {
+            stats.total_escrowed = stats.total_escrowed.saturating_sub(complete_sets);
+            if resolved {
+                stats.unclaimed_winnings = stats.unclaimed_winnings.saturating_sub(complete_sets);
+            }
+        });
+        self.record_event(EventPayload::Liquidity(LiquidityEvent {
+            market_id,
+            owner,
+            change: LiquidityChange::Removed,
+            lp_shares,
+            amount: complete_sets,
+        }));
+
+        Ok((remaining, complete_sets))
+    }
+
+    /// Load an active, unexpired market and find one of its outcomes
+    async fn tradable_market(
+        &mut self,
+        market_id: &str,
+        outcome_id: &str,
+    ) -> Result<(Market, usize), CascadeProtocolError> {
+        let market = self
+            .state
+            .markets
+            .get(market_id)
+            .await?
+            .ok_or_else(|| CascadeProtocolError::MarketNotFound(market_id.to_string()))?;
+        if market.status != MarketStatus::Active {
+            return Err(CascadeProtocolError::MarketNotActive);
+        }
+        if self.runtime.system_time().micros() >= market.expiry_time {
+            return Err(CascadeProtocolError::MarketExpired);
+        }
+        let outcome_idx = market
+            .outcomes
+            .iter()
+            .position(|o| o.id == outcome_id)
+            .ok_or_else(|| CascadeProtocolError::OutcomeNotFound(outcome_id.to_string()))?;
+        Ok((market, outcome_idx))
+    }
+
+    /// Apply an update to an owner's share position in a market, opening it
+    /// if needed, and return the updated position
+    async fn update_share_position(
+        &mut self,
+        market: &Market,
+        owner: AccountOwner,
+        update: impl FnOnce(&mut SharePosition) -> Result<(), ArithmeticError>,
+    ) -> Result<SharePosition, CascadeProtocolError> {
+        let mut positions = self.state.share_positions.get(&market.id).await?.unwrap_or_default();
+        let index = match positions.iter().position(|position| position.owner == owner) {
+            Some(index) => index,
+            None => {
+                positions.push(SharePosition {
+                    id: self.state.generate_id().await?,
+                    owner,
+                    market_id: market.id.clone(),
+                    shares: vec![Amount::ZERO; market.outcomes.len()],
+                    cost_basis: Amount::ZERO,
+                    claimed: false,
+                });
+                positions.len() - 1
+            }
+        };
+        update(&mut positions[index])?;
+        let position = positions[index].clone();
+        self.state.share_positions.insert(&market.id, positions)?;
+        Ok(position)
+    }
+
+    /// Apply an update to an owner's LP shares in a market's pool, opening a
+    /// position if needed, and return the updated LP shares
+    async fn update_liquidity_position(
+        &mut self,
+        market_id: &str,
+        owner: AccountOwner,
+        update: impl FnOnce(&mut Amount) -> Result<(), ArithmeticError>,
+    ) -> Result<Amount, CascadeProtocolError> {
+        let mut positions = self
+            .state
+            .liquidity_positions
+            .get(market_id)
+            .await?
+            .unwrap_or_default();
+        let index = match positions.iter().position(|position| position.owner == owner) {
+            Some(index) => index,
+            None => {
+                positions.push(LiquidityPosition {
+                    owner,
+                    market_id: market_id.to_string(),
+                    lp_shares: Amount::ZERO,
+                });
+                positions.len() - 1
+            }
+        };
+        update(&mut positions[index].lp_shares)?;
+        let lp_shares = positions[index].lp_shares;
+        self.state.liquidity_positions.insert(market_id, positions)?;
+        Ok(lp_shares)
+    }
+
+    /// Give the creator of a constant-product pool the LP shares of the
+    /// liquidity it was funded with
+    fn open_pool(&mut self, market: &Market) -> Result<(), CascadeProtocolError> {
+        if let Some(AmmState::Cpmm(pool)) = &market.amm {
+            let position = LiquidityPosition {
+                owner: pool.creator,
+                market_id: market.id.clone(),
+                lp_shares: pool.total_lp_shares,
+            };
+            self.state.liquidity_positions.insert(&market.id, vec![position])?;
+        }
+        Ok(())
+    }
+
+    /// Pay the caller's shares of the winning outcome of a resolved market
+    /// that trades shares, one token per share
+    async fn claim_shares(
+        &mut self,
+        caller: AccountOwner,
+        market: Market,
+        destination: ChainId,
+    ) -> Result<(Vec<String>, Amount), CascadeProtocolError> {
+        if market.claim_deadline_passed(self.runtime.system_time().micros()) {
+            return Err(CascadeProtocolError::ClaimWindowClosed);
+        }
+        let winning_idx = market
+            .outcomes
+            .iter()
+            .position(|o| Some(&o.id) == market.winning_outcome_id.as_ref())
+            .ok_or(CascadeProtocolError::MarketNotResolved)?;
+
+        let mut positions = self.state.share_positions.get(&market.id).await?.unwrap_or_default();
+        let position = positions
+            .iter_mut()
+            .find(|position| position.owner == caller)
+            .ok_or(CascadeProtocolError::BetNotFound)?;
+        // Shares removed from a pool after a claim can be claimed as well
+        let payout = position.shares[winning_idx];
+        if payout == Amount::ZERO {
+            return Err(if position.claimed {
+                CascadeProtocolError::AlreadyClaimed
+            } else {
+                CascadeProtocolError::NotWinningOutcome
+            });
+        }
+        let gain = payout.saturating_sub(position.cost_basis);
+        position.shares[winning_idx] = Amount::ZERO;
+        position.cost_basis = Amount::ZERO;
+        position.claimed = true;
+        let position_id = position.id.clone();
+        self.state.share_positions.insert(&market.id, positions)?;
+
+        self.withdraw(&market.id, market.collateral, payout).await?;
+        self.pay_out(caller, destination, market.collateral, payout);
+
+        self.state
+            .update_user_stats(caller, |stats| {
+                stats.realized_gains = stats.realized_gains.saturating_add(gain)
+            })
+            .await?;
+        self.state.update_protocol_stats(|stats| {
+            stats.total_escrowed = stats.total_escrowed.saturating_sub(payout);
+            stats.unclaimed_winnings = stats.unclaimed_winnings.saturating_sub(payout);
+        });
+        self.record_event(EventPayload::Claimed(ClaimedEvent {
+            bet_id: position_id.clone(),
+            market_id: market.id,
+            owner: caller,
+            amount: payout,
+        }));
+
+        Ok((vec![position_id], payout))
+    }
+
+    /// Resolve a scalar or bucketed market with the reported value, and
+    /// return the winning outcome: the bucket the value falls into, or the
+    /// side of a scalar market that receives the larger part of the pool.
+    /// Admin authorization is checked by the caller.
+    async fn resolve_scalar_market(
+        &mut self,
+        market_id: String,
+        value: i64,
+    ) -> Result<String, CascadeProtocolError> {
+        let market = self
+            .state
+            .markets
+            .get(&market_id)
+            .await?
+            .ok_or_else(|| CascadeProtocolError::MarketNotFound(market_id.clone()))?;
+        let winning_outcome_id = market
+            .value_outcome(value)
+            .ok_or(CascadeProtocolError::UnsupportedMechanism)?;
+        self.resolve_market(market_id, winning_outcome_id.clone(), Some(value))
+            .await?;
+        Ok(winning_outcome_id)
+    }
+
+    /// Resolve a market with the winning outcome, and the reported value for
+    /// scalar and bucketed markets. Admin authorization is checked by the caller.
+    async fn resolve_market(
+        &mut self,
+        market_id: String,
+        winning_outcome_id: String,
+        value: Option<i64>,
+    ) -> Result<(), CascadeProtocolError> {
+        // Load market
+        let mut market = self
+            .state
+            .markets
+            .get(&market_id)
+            .await?
+            .ok_or_else(|| CascadeProtocolError::MarketNotFound(market_id.clone()))?;
+
+        // Verify market is active
+        if market.status != MarketStatus::Active {
+            return Err(CascadeProtocolError::MarketNotActive);
+        }
+
+        // Scalar and bucketed markets are resolved with a value, and only they are
+        if market.resolves_by_value() != value.is_some() {
+            return Err(CascadeProtocolError::UnsupportedMechanism);
+        }
+        if let Some(range) = market.scalar.as_mut() {
+            range.value = value;
+        }
+        if let Some(buckets) = market.buckets.as_mut() {
+            buckets.value = value;
+        }
+
+        // Verify winning outcome exists
+        let winning_idx = market
+            .outcomes
+            .iter()
+            .position(|o| o.id == winning_outcome_id)
+            .ok_or_else(|| CascadeProtocolError::OutcomeNotFound(winning_outcome_id.clone()))?;
+        if let Some(buckets) = &market.buckets {
+            if value.map(|value| buckets.bucket_of(value)) != Some(winning_idx) {
+                return Err(CascadeProtocolError::OutcomeNotFound(winning_outcome_id));
+            }
+        }
+
+        // Listed positions go back to their sellers, and resting orders are
+        // cancelled, so that every stake and share is in a position
+        self.close_listings(&market).await?;
+        if market.order_book {
+            for order in self.state.clear_orders(&market_id).await? {
+                self.unlock_order(&mut market, &order).await?;
+            }
+        }
+
+        // The winner decides the pool each outcome is paid from
+        market.winning_outcome_id = Some(winning_outcome_id.clone());
+
+        // Tally each bettor's winning and losing stakes
+        let mut positions: BTreeMap<AccountOwner, (Amount, Amount)> = BTreeMap::new();
+        let mut splits = Vec::new();
+        if market.trades_shares() {
+            // Winning shares, and what was paid for losing positions
+            let share_positions = self.state.share_positions.get(&market_id).await?;
+            for position in share_positions.unwrap_or_default() {
+                let winning = position.shares[winning_idx];
+                let losing = if winning > Amount::ZERO {
+                    Amount::ZERO
+                } else {
+                    position.cost_basis
+                };
+                positions.insert(position.owner, (winning, losing));
+            }
+        } else {
+            // Stakes on a paid outcome win, and lose what their part of its
+            // pool does not pay back, as on the losing side of a scalar market
+            let market_bets = self.state.market_bets(&market_id, 0..usize::MAX).await?;
+
+            // The pool paid to each outcome is split between its stakes once,
+            // so that a claim only needs the bet it pays
+            if market.fixed_odds.is_none() {
+                for outcome in &market.outcomes {
+                    let pool = market.payout_pool(&outcome.id);
+                    if pool == Amount::ZERO || outcome.total_staked == Amount::ZERO {
+                        continue;
+                    }
+                    let bets: Vec<(String, Amount)> = market_bets
+                        .iter()
+                        .filter(|bet| bet.outcome_id == outcome.id)
+                        .map(|bet| (bet.id.clone(), bet.amount))
+                        .collect();
+                    let split = PoolSplit::new(outcome.id.clone(), pool, &bets, market.dust_policy)
+                        .ok_or(CascadeProtocolError::ArithmeticOverflow)?;
+                    splits.push(split);
+                }
+            }
+
+            for bet in market_bets.iter() {
+                let (winning, losing) = positions.entry(bet.owner).or_default();
+                let pool = market.payout_pool(&bet.outcome_id);
+                if pool == Amount::ZERO {
+                    losing.try_add_assign(bet.amount)?;
+                    continue;
+                }
+                let outcome_stake = market
+                    .outcomes
+                    .iter()
+                    .find(|o| o.id == bet.outcome_id)
+                    .map_or(Amount::ZERO, |o| o.total_staked);
+                // Fixed-odds bets are paid what they were quoted, as on claim
+                let payout = match bet.odds_bps {
+                    Some(odds_bps) => FixedOddsBook::payout(bet.amount, odds_bps),
+                    None => mul_div(bet.amount, pool, outcome_stake),
+                }
+                .ok_or(CascadeProtocolError::ArithmeticOverflow)?;
+                winning.try_add_assign(bet.amount)?;
+                losing.try_add_assign(bet.amount.saturating_sub(payout))?;
+            }
+        }
+
+        // Winners are owed the whole pool, unless nobody backed the winning
+        // outcome. In markets that trade shares, they are owed one token per
+        // share, including the shares a constant-product pool holds for its LPs.
+        let has_winners = positions.values().any(|(winning, _)| *winning > Amount::ZERO);
+        let winning_shares = positions
+            .values()
+            .try_fold(Amount::ZERO, |owed, (winning, _)| owed.try_add(*winning))?;
+        let owed_to_winners = match &market.amm {
+            Some(AmmState::Cpmm(pool)) => winning_shares.try_add(pool.reserves[winning_idx])?,
+            Some(AmmState::Lmsr(_)) => winning_shares,
+            None if market.order_book => winning_shares,
+            // Fixed-odds bets are owed what they were quoted
+            None => match (&market.fixed_odds, &market.scalar) {
+                (Some(book), _) => book.liabilities[winning_idx],
+                // The sides of a scalar market that were backed are owed the
+                // whole pool
+                (None, Some(_)) => market
+                    .outcomes
+                    .iter()
+                    .try_fold(Amount::ZERO, |owed, o| owed.try_add(market.payout_pool(&o.id)))?,
+                (None, None) if has_winners => market.total_staked,
+                (None, None) => Amount::ZERO,
+            },
+        };
+        // Dust that goes to the treasury is not owed to the winners
+        let owed_to_winners = splits.iter().try_fold(owed_to_winners, |owed, split| {
+            owed.try_sub(split.pool.try_sub(split.owed())?)
+        })?;
+
+        let event = MarketResolvedEvent {
+            market_id: market_id.clone(),
+            winning_outcome_id: winning_outcome_id.clone(),
+            value,
+        };
+        match &market.amm {
+            // What the market maker holds beyond the winning shares goes back
+            // to the creator
+            Some(AmmState::Lmsr(lmsr)) => {
+                self.return_excess(&market, lmsr.creator, owed_to_winners)
+                    .await?;
+            }
+            // Every share was minted with a token of escrow, so nothing is left over
+            Some(AmmState::Cpmm(_)) => {}
+            None => match &market.fixed_odds {
+                // The bookmaker keeps the losing stakes and what is left of its reserve
+                Some(book) => {
+                    self.return_excess(&market, book.bookmaker, owed_to_winners)
+                        .await?;
+                }
+                // Without winners, the pool is no longer owed to anyone
+                None => {
+                    let forgiven = market.total_staked.try_sub(owed_to_winners)?;
+                    self.update_ledger(&market_id, market.collateral, |ledger| {
+                        ledger.forgive(forgiven)
+                    })
+                    .await?;
+                }
+            },
+        }
+
+        // Update market
+        let previous_status = market.status;
+        let current_time = self.runtime.system_time().micros();
+        market.status = MarketStatus::Resolved;
+        market.claim_deadline = market
+            .claim_window
+            .map(|window| current_time.saturating_add(window));
+
+        if !market.trades_shares() && market.fixed_odds.is_none() {
+            self.state.pool_splits.insert(&market_id, splits)?;
+        }
+
+        // Save updated market and notify subscribers
+        let message = Message::MarketResolved {
+            market_id: market_id.clone(),
+            winning_outcome_id: winning_outcome_id.clone(),
+            value,
+            claim_deadline: market.claim_deadline,
+        };
+        self.notify_subscribers(&market, message).await?;
+        self.state.update_market(market).await?;
+        self.state.update_protocol_stats(|stats| {
+            stats.unclaimed_winnings = stats.unclaimed_winnings.saturating_add(owed_to_winners);
+            stats.record_status_change(Some(previous_status), MarketStatus::Resolved)
+        })?;
+        self.record_event(EventPayload::MarketResolved(event));
+
+        // Record wins and realized losses for every bettor
+        for (owner, (winning, losing)) in positions {
+            self.state
+                .update_user_stats(owner, |stats| {
+                    stats.record_result(winning > Amount::ZERO, losing)
+                })
+                .await??;
+        }
+
+        Ok(())
+    }
+
+    /// Pay what a resolved market holds beyond what its winners are owed to
+    /// the owner who funded it, on the application chain
+    async fn return_excess(
+        &mut self,
+        market: &Market,
+        owner: AccountOwner,
+        owed_to_winners: Amount,
+    ) -> Result<(), CascadeProtocolError> {
+        let escrowed = self
+            .state
+            .market_ledgers
+            .get(&market.id)
+            .await?
+            .map_or(Amount::ZERO, |ledger| ledger.escrowed);
+        let excess = escrowed.saturating_sub(owed_to_winners);
+        let app_chain_id = self.app_chain_id();
+        self.withdraw(&market.id, market.collateral, excess).await?;
+        self.pay_out(owner, app_chain_id, market.collateral, excess);
+        self.state.update_protocol_stats(|stats| {
+            stats.total_escrowed = stats.total_escrowed.saturating_sub(excess)
+        });
+        Ok(())
+    }
+
+    /// Claim winnings from a resolved market, paying them to the caller's
+    /// account on `destination`. Returns the IDs of the settled bets and the
+    /// amount paid.
+    async fn claim_winnings(
+        &mut self,
+        caller: AccountOwner,
+        market_id: String,
+        destination: ChainId,
+    ) -> Result<(Vec<String>, Amount), CascadeProtocolError> {
+        // Load market
+        let market = self
+            .state
+            .markets
+            .get(&market_id)
+            .await?
+            .ok_or_else(|| CascadeProtocolError::MarketNotFound(market_id.clone()))?;
+
+        // Bets and share positions on cancelled markets are refunded instead
+        if market.status == MarketStatus::Cancelled {
+            if market.trades_shares() {
+                return self.refund_shares(caller, market, destination).await;
+            }
+            return self
+                .claim_refund(caller, market_id, destination, market.collateral)
+                .await;
+        }
+
+        // Verify market is resolved
+        if market.status != MarketStatus::Resolved {
+            return Err(CascadeProtocolError::MarketNotResolved);
+        }
+
+        // Shares of the winning outcome pay one token each
+        if market.trades_shares() {
+            return self.claim_shares(caller, market, destination).await;
+        }
+
+        // Find user's bet on an outcome that is paid
+        let user_bets = self.state.owner_bets(&caller).await?;
+        
+        let mut winning_bet = None;
+        for bet in user_bets.iter() {
+            if bet.market_id == market_id && market.pays_outcome(&bet.outcome_id) && !bet.claimed {
+                winning_bet = Some(bet.clone());
+                break;
+            }
+        }
+
+        let bet = winning_bet.ok_or(CascadeProtocolError::BetNotFound)?;
+
+        // Check if already claimed
+        if bet.claimed {
+            return Err(CascadeProtocolError::AlreadyClaimed);
+        }
+
+        let bet_ids = vec![bet.id.clone()];
+        let payout = self.pay_winning_bet(&market, bet, destination).await?;
+
+        Ok((bet_ids, payout))
+    }
+
+    /// Pay a winning bet to its owner's account on `destination`, and mark it
+    /// as claimed
+    async fn pay_winning_bet(
+        &mut self,
+        market: &Market,
+        mut bet: Bet,
+        destination: ChainId,
+    ) -> Result<Amount, CascadeProtocolError> {
+        if market.claim_deadline_passed(self.runtime.system_time().micros()) {
+            return Err(CascadeProtocolError::ClaimWindowClosed);
+        }
+
+        // Fixed-odds bets pay what they were quoted, others a share of the pool
+        let mut splits = None;
+        let payout = match bet.odds_bps {
+            Some(odds_bps) => FixedOddsBook::payout(bet.amount, odds_bps)
+                .ok_or(CascadeProtocolError::ArithmeticOverflow)?,
+            None => {
+                let market_splits = self.state.pool_splits.get(&market.id).await?;
+                splits
+                    .insert(market_splits.unwrap_or_default())
+                    .iter_mut()
+                    .find(|split| split.outcome_id == bet.outcome_id)
+                    .ok_or(CascadeProtocolError::NotWinningOutcome)?
+                    .pay(&bet.id, bet.amount)
+                    .ok_or(CascadeProtocolError::ArithmeticOverflow)?
+            }
+        };
+
+        if payout == Amount::ZERO {
+            return Err(CascadeProtocolError::InsufficientFunds {
+                required: Amount::from_attos(1),
+                available: Amount::ZERO,
+            });
+        }
+        // Only a payout that is made counts against the pool
+        if let Some(splits) = splits {
+            self.state.pool_splits.insert(&market.id, splits)?;
+        }
+
+        // Transfer winnings to the owner
+        let owner = bet.owner;
+        self.withdraw(&market.id, market.collateral, payout).await?;
+        self.pay_out(owner, destination, market.collateral, payout);
+
+        // Mark bet as claimed
+        bet.claimed = true;
+        let gain = payout.saturating_sub(bet.amount);
+        let event = ClaimedEvent {
+            bet_id: bet.id.clone(),
+            market_id: market.id.clone(),
+            owner,
+            amount: payout,
+        };
+        self.state.update_bet(bet).await?;
+
+        // Update user and protocol statistics
+        self.state
+            .update_user_stats(owner, |stats| {
+                stats.realized_gains = stats.realized_gains.saturating_add(gain)
+            })
+            .await?;
+        self.state.update_protocol_stats(|stats| {
+            stats.total_escrowed = stats.total_escrowed.saturating_sub(payout);
+            stats.unclaimed_winnings = stats.unclaimed_winnings.saturating_sub(payout);
+        });
+        self.record_event(EventPayload::Claimed(event));
+
+        Ok(payout)
+    }
+
+    /// Cash out `amount` of an active bet's stake. The penalty set by the
+    /// application parameters stays in the pool for the winners, and the rest
+    /// is paid to `destination`. Returns the updated bet, the payout and the
+    /// penalty.
+    async fn cash_out(
+        &mut self,
+        caller: AccountOwner,
+        market_id: String,
+        bet_id: String,
+        amount: Amount,
+        destination: ChainId,
+    ) -> Result<(Bet, Amount, Amount), CascadeProtocolError> {
+        if amount == Amount::ZERO {
+            return Err(CascadeProtocolError::InvalidBetAmount);
+        }
+        let mut market = self
+            .state
+            .markets
+            .get(&market_id)
+            .await?
+            .ok_or_else(|| CascadeProtocolError::MarketNotFound(market_id.clone()))?;
+        if market.status != MarketStatus::Active {
+            return Err(CascadeProtocolError::MarketNotActive);
+        }
+        // The bookmaker's liability of fixed-odds bets cannot be unwound
+        if market.trades_shares() || market.fixed_odds.is_some() {
+            return Err(CascadeProtocolError::UnsupportedMechanism);
+        }
+        let current_time = self.runtime.system_time().micros();
+        if current_time >= market.expiry_time {
+            return Err(CascadeProtocolError::MarketExpired);
+        }
+
+        let mut bet = self
+            .state
+            .bets
+            .get(&bet_id)
+            .await?
+            .filter(|bet| bet.market_id == market_id && bet.owner == caller && !bet.claimed)
+            .ok_or(CascadeProtocolError::BetNotFound)?;
+        if amount > bet.amount {
+            return Err(CascadeProtocolError::InsufficientFunds {
+                required: amount,
+                available: bet.amount,
+            });
+        }
+
+        // The whole amount leaves the outcome, but only the payout leaves the pool
+        let penalty = self
+            .runtime
+            .application_parameters()
+            .cash_out_penalty(amount)
+            .ok_or(CascadeProtocolError::ArithmeticOverflow)?;
+        let payout = market
+            .cash_out(&bet.outcome_id, amount, penalty)
+            .ok_or_else(|| CascadeProtocolError::OutcomeNotFound(bet.outcome_id.clone()))?;
+
+        // A bet cashed out entirely is settled
+        bet.amount.try_sub_assign(amount)?;
+        if bet.amount == Amount::ZERO {
+            bet.claimed = true;
+        }
+
+        self.withdraw(&market_id, market.collateral, payout).await?;
+        self.pay_out(caller, destination, market.collateral, payout);
+
+        self.state.record_odds_snapshot(&market, current_time).await?;
+        self.state.update_market(market).await?;
+        self.state.update_bet(bet.clone()).await?;
+
+        self.state
+            .update_user_stats(caller, |stats| {
+                stats.realized_losses = stats.realized_losses.saturating_add(penalty);
+                stats.cashed_out = stats.cashed_out.saturating_add(amount);
+            })
+            .await?;
+        self.state.update_protocol_stats(|stats| {
+            stats.total_escrowed = stats.total_escrowed.saturating_sub(payout);
+            stats.fees_accrued = stats.fees_accrued.saturating_add(penalty);
+        });
+        self.record_event(EventPayload::CashedOut(CashedOutEvent {
+            bet_id: bet.id.clone(),
+            market_id,
+            outcome_id: bet.outcome_id.clone(),
+            owner: caller,
+            stake: amount,
+            penalty,
+            payout,
+        }));
+
+        Ok((bet, payout, penalty))
+    }
+
+    /// Move what is left in the escrow of a resolved market to the treasury,
+    /// once its claim deadline has passed. Before then, or if winners can
+    /// claim forever, only the escrow beyond what winners are owed is moved,
+    /// such as dust for the treasury or the pool of a market nobody won.
+    /// Returns the amount swept.
+    async fn sweep_unclaimed(
+        &mut self,
+        market_id: String,
+        treasury: AccountOwner,
+    ) -> Result<Amount, CascadeProtocolError> {
+        let market = self
+            .state
+            .markets
+            .get(&market_id)
+            .await?
+            .ok_or_else(|| CascadeProtocolError::MarketNotFound(market_id.clone()))?;
+        if market.status != MarketStatus::Resolved {
+            return Err(CascadeProtocolError::MarketNotResolved);
+        }
+        let deadline_passed = market.claim_deadline_passed(self.runtime.system_time().micros());
+
+        let ledger = self
+            .state
+            .market_ledgers
+            .get(&market_id)
+            .await?
+            .unwrap_or_default();
+        let (amount, unclaimed) = if deadline_passed {
+            (ledger.escrowed, ledger.liability)
+        } else {
+            (ledger.escrowed.saturating_sub(ledger.liability), Amount::ZERO)
+        };
+        if amount == Amount::ZERO && !deadline_passed {
+            return Err(CascadeProtocolError::ClaimWindowOpen);
+        }
+        self.update_ledger(&market_id, market.collateral, |ledger| {
+            ledger.forgive(unclaimed)?;
+            ledger.escrowed.try_sub_assign(amount)
+        })
+        .await?;
+
+        let app_chain_id = self.app_chain_id();
+        self.pay_out(treasury, app_chain_id, market.collateral, amount);
+
+        let total_swept = self
+            .state
+            .swept
+            .get(&market_id)
+            .await?
+            .unwrap_or_default()
+            .try_add(amount)?;
+        self.state.swept.insert(&market_id, total_swept)?;
+        self.state.update_protocol_stats(|stats| {
+            stats.total_swept = stats.total_swept.saturating_add(amount);
+            stats.total_escrowed = stats.total_escrowed.saturating_sub(amount);
+            stats.unclaimed_winnings = stats.unclaimed_winnings.saturating_sub(unclaimed);
+        });
+
+        Ok(amount)
+    }
+
+    /// Push back the claim deadline of a resolved market. Admin authorization
+    /// is checked by the caller.
+    async fn extend_claim_deadline(
+        &mut self,
+        market_id: String,
+        claim_deadline: u64,
+    ) -> Result<(), CascadeProtocolError> {
+        let mut market = self
+            .state
+            .markets
+            .get(&market_id)
+            .await?
+            .ok_or_else(|| CascadeProtocolError::MarketNotFound(market_id.clone()))?;
+        if market.status != MarketStatus::Resolved {
+            return Err(CascadeProtocolError::MarketNotResolved);
+        }
+        // Only a deadline still in the future can be extended; unlimited
+        // windows stay unlimited
+        let current_time = self.runtime.system_time().micros();
+        match market.claim_deadline {
+            Some(deadline) if deadline > current_time && claim_deadline > deadline => {}
+            _ => return Err(CascadeProtocolError::InvalidClaimDeadline),
+        }
+
+        market.claim_deadline = Some(claim_deadline);
+        let message = Message::ClaimDeadlineExtended {
+            market_id,
+            claim_deadline,
+        };
+        self.notify_subscribers(&market, message).await?;
+        self.state.update_market(market).await?;
+        Ok(())
+    }
+
+    /// Pay the winning bets among the next `batch_size` bets of a resolved
+    /// market, resuming from where the previous batch stopped. Each payout
+    /// goes to the chain its owner last bet from.
+    async fn distribute_winnings(
+        &mut self,
+        market_id: String,
+        batch_size: u32,
+    ) -> Result<OperationResponse, CascadeProtocolError> {
+        let market = self
+            .state
+            .markets
+            .get(&market_id)
+            .await?
+            .ok_or_else(|| CascadeProtocolError::MarketNotFound(market_id.clone()))?;
+        if market.status != MarketStatus::Resolved {
+            return Err(CascadeProtocolError::MarketNotResolved);
+        }
+        if market.trades_shares() {
+            return Err(CascadeProtocolError::UnsupportedMechanism);
+        }
+
+        // Only the batch after the cursor is read from the market's log
+        let count = self.state.market_bet_count(&market_id).await?;
+        let start = self
+            .state
+            .distribution_cursors
+            .get(&market_id)
+            .await?
+            .unwrap_or_default() as usize;
+        let end = start.saturating_add(batch_size as usize).min(count);
+        let batch = self.state.market_bets(&market_id, start..end).await?;
+
+        let mut bet_ids = Vec::new();
+        let mut amount = Amount::ZERO;
+        for bet in batch {
+            if bet.claimed || !market.pays_outcome(&bet.outcome_id) {
+                continue;
+            }
+            let destination = self
+                .state
+                .payout_chains
+                .get(&bet.owner)
+                .await?
+                .unwrap_or_else(|| self.runtime.chain_id());
+            let payout = match self
+                .pay_winning_bet(&market, bet.clone(), destination)
+                .await
+            {
+                Ok(payout) => payout,
+                // A share rounded down to nothing is left to be claimed
+                // later, when it may receive the dust
+                Err(CascadeProtocolError::InsufficientFunds { .. }) => continue,
+                Err(error) => return Err(error),
+            };
+            amount.try_add_assign(payout)?;
+            bet_ids.push(bet.id.clone());
+
+            if destination != self.runtime.chain_id() {
+                self.runtime
+                    .prepare_message(Message::Claimed {
+                        owner: bet.owner,
+                        market_id: market_id.clone(),
+                        bet_ids: vec![bet.id],
+                        amount: payout,
+                    })
+                    .send_to(destination);
+            }
+        }
+
+        self.state
+            .distribution_cursors
+            .insert(&market_id, end as u64)?;
+        Ok(OperationResponse::Distributed {
+            bet_ids,
+            amount,
+            remaining: (count - end) as u64,
+        })
+    }
+
+    /// Prepare the refunds of a cancelled market that trades shares. Resting
+    /// orders are unlocked, and every position is owed what was paid for it,
+    /// or its part of the escrow if traders who sold at a profit are owed
+    /// more than is left. The rest goes back to whoever funded the market
+    /// maker, or to `treasury`. Returns the refunds owed.
+    async fn prepare_share_refunds(
+        &mut self,
+        market: &mut Market,
+        treasury: AccountOwner,
+    ) -> Result<Amount, CascadeProtocolError> {
+        if market.order_book {
+            for order in self.state.clear_orders(&market.id).await? {
+                self.unlock_order(market, &order).await?;
+            }
+        }
+
+        let escrowed = self
+            .state
+            .market_ledgers
+            .get(&market.id)
+            .await?
+            .map_or(Amount::ZERO, |ledger| ledger.escrowed);
+        let mut positions = self.state.share_positions.get(&market.id).await?.unwrap_or_default();
+        let paid = positions
+            .iter()
+            .try_fold(Amount::ZERO, |paid, position| paid.try_add(position.cost_basis))?;
+        if paid > escrowed {
+            for position in positions.iter_mut() {
+                position.cost_basis = mul_div(position.cost_basis, escrowed, paid)
+                    .ok_or(CascadeProtocolError::ArithmeticOverflow)?;
+            }
+        }
+        let refunds_owed = positions
+            .iter()
+            .try_fold(Amount::ZERO, |owed, position| owed.try_add(position.cost_basis))?;
+        self.state.share_positions.insert(&market.id, positions)?;
+
+        let excess = escrowed.try_sub(refunds_owed)?;
+        let app_chain_id = self.app_chain_id();
+        self.withdraw(&market.id, market.collateral, excess).await?;
+        match &market.amm {
+            Some(AmmState::Lmsr(lmsr)) => {
+                self.pay_out(lmsr.creator, app_chain_id, market.collateral, excess);
+            }
+            // Liquidity providers share the rest by LP shares, and the last
+            // one receives the dust
+            Some(AmmState::Cpmm(pool)) => {
+                let providers = self
+                    .state
+                    .liquidity_positions
+                    .get(&market.id)
+                    .await?
+                    .unwrap_or_default();
+                let mut left = excess;
+                for (index, provider) in providers.iter().enumerate() {
+                    let share = if index + 1 == providers.len() {
+                        left
+                    } else {
+                        mul_div(excess, provider.lp_shares, pool.total_lp_shares)
+                            .ok_or(CascadeProtocolError::ArithmeticOverflow)?
+                    };
+                    left.try_sub_assign(share)?;
+                    self.pay_out(provider.owner, app_chain_id, market.collateral, share);
+                }
+                self.pay_out(treasury, app_chain_id, market.collateral, left);
+            }
+            None => self.pay_out(treasury, app_chain_id, market.collateral, excess),
+        }
+        self.state.update_protocol_stats(|stats| {
+            stats.total_escrowed = stats.total_escrowed.saturating_sub(excess)
+        });
+        Ok(refunds_owed)
+    }
+
+    /// Refund the caller's position in a cancelled market that trades shares
+    /// what was paid for it
+    async fn refund_shares(
+        &mut self,
+        caller: AccountOwner,
+        market: Market,
+        destination: ChainId,
+    ) -> Result<(Vec<String>, Amount), CascadeProtocolError> {
+        let mut positions = self.state.share_positions.get(&market.id).await?.unwrap_or_default();
+        let position = positions
+            .iter_mut()
+            .find(|position| position.owner == caller && !position.claimed)
+            .ok_or(CascadeProtocolError::BetNotFound)?;
+        let refund = position.cost_basis;
+        for shares in position.shares.iter_mut() {
+            *shares = Amount::ZERO;
+        }
+        position.cost_basis = Amount::ZERO;
+        position.claimed = true;
+        let position_id = position.id.clone();
+        self.state.share_positions.insert(&market.id, positions)?;
+
+        self.withdraw(&market.id, market.collateral, refund).await?;
+        self.pay_out(caller, destination, market.collateral, refund);
+
+        self.state
+            .update_user_stats(caller, |stats| {
+                stats.refunded = stats.refunded.saturating_add(refund)
+            })
+            .await?;
+        self.state.update_protocol_stats(|stats| {
+            stats.total_escrowed = stats.total_escrowed.saturating_sub(refund);
+            stats.unclaimed_winnings = stats.unclaimed_winnings.saturating_sub(refund);
+        });
+        self.record_event(EventPayload::Refunded(RefundedEvent {
+            bet_id: position_id.clone(),
+            market_id: market.id,
+            owner: caller,
+            amount: refund,
+        }));
+
+        Ok((vec![position_id], refund))
+    }
+
+    /// Refund every unclaimed bet of the caller on a cancelled market
+    async fn claim_refund(
+        &mut self,
+        caller: AccountOwner,
+        market_id: String,
+        destination: ChainId,
+        collateral: Collateral,
+    ) -> Result<(Vec<String>, Amount), CascadeProtocolError> {
+        let user_bets = self.state.owner_bets(&caller).await?;
+        let refundable: Vec<Bet> = user_bets
+            .into_iter()
+            .filter(|bet| bet.market_id == market_id && !bet.claimed)
+            .collect();
+
+        if refundable.is_empty() {
+            return Err(CascadeProtocolError::BetNotFound);
+        }
+
+        let mut refund = Amount::ZERO;
+        for bet in refundable.iter() {
+            refund.try_add_assign(bet.amount)?;
+        }
+
+        // Transfer the stakes back to the user
+        self.withdraw(&market_id, collateral, refund).await?;
+        self.pay_out(caller, destination, collateral, refund);
+
+        // Mark bets as claimed
+        let bet_ids = refundable.iter().map(|bet| bet.id.clone()).collect();
+        for mut bet in refundable {
+            bet.claimed = true;
+            let event = RefundedEvent {
+                bet_id: bet.id.clone(),
+                market_id: market_id.clone(),
+                owner: caller,
+                amount: bet.amount,
+            };
+            self.state.update_bet(bet).await?;
+            self.record_event(EventPayload::Refunded(event));
+        }
+
+        self.state
+            .update_user_stats(caller, |stats| {
+                stats.refunded = stats.refunded.saturating_add(refund)
+            })
+            .await?;
+        self.state.update_protocol_stats(|stats| {
+            stats.total_escrowed = stats.total_escrowed.saturating_sub(refund);
+            stats.unclaimed_winnings = stats.unclaimed_winnings.saturating_sub(refund);
+        });
+
+        Ok((bet_ids, refund))
+    }
+
+    /// Cancel an active market so that every bet can be refunded, paying
+    /// cash-out penalties left over to `treasury`. Admin authorization is
+    /// checked by the caller.
+    async fn cancel_market(
+        &mut self,
+        market_id: String,
+        treasury: AccountOwner,
+    ) -> Result<(), CascadeProtocolError> {
+        // Load market
+        let mut market = self
+            .state
+            .markets
+            .get(&market_id)
+            .await?
+            .ok_or_else(|| CascadeProtocolError::MarketNotFound(market_id.clone()))?;
+
+        // Verify market is active
+        if market.status != MarketStatus::Active {
+            return Err(CascadeProtocolError::MarketNotActive);
+        }
+
+        self.close_listings(&market).await?;
+
+        let refunds_owed = if market.trades_shares() {
+            self.prepare_share_refunds(&mut market, treasury).await?
+        } else {
+            // Cash-out penalties were left for the winners; without any, they
+            // go to the treasury. A bookmaker's reserve goes back to the
+            // bookmaker.
+            let refunds_owed = market
+                .outcomes
+                .iter()
+                .try_fold(Amount::ZERO, |total, outcome| total.try_add(outcome.total_staked))?;
+            let penalties = market.total_staked.saturating_sub(refunds_owed);
+            if let Some(book) = &market.fixed_odds {
+                let app_chain_id = self.app_chain_id();
+                self.withdraw(&market_id, market.collateral, penalties).await?;
+                self.pay_out(book.bookmaker, app_chain_id, market.collateral, penalties);
+                self.state.update_protocol_stats(|stats| {
+                    stats.total_escrowed = stats.total_escrowed.saturating_sub(penalties)
+                });
+            } else if penalties > Amount::ZERO {
+                let app_chain_id = self.app_chain_id();
+                self.withdraw(&market_id, market.collateral, penalties).await?;
+                self.pay_out(treasury, app_chain_id, market.collateral, penalties);
+                self.state.update_protocol_stats(|stats| {
+                    stats.total_escrowed = stats.total_escrowed.saturating_sub(penalties);
+                    stats.total_swept = stats.total_swept.saturating_add(penalties);
+                });
+            }
+            refunds_owed
+        };
+
+        let previous_status = market.status;
+        market.total_staked = refunds_owed;
+        market.status = MarketStatus::Cancelled;
+
+        // Save updated market and notify subscribers
+        self.notify_subscribers(&market, Message::MarketCancelled { market_id: market_id.clone() })
+            .await?;
+        self.state.update_market(market).await?;
+        self.state.update_protocol_stats(|stats| {
+            stats.unclaimed_winnings = stats.unclaimed_winnings.saturating_add(refunds_owed);
+            stats.record_status_change(Some(previous_status), MarketStatus::Cancelled)
+        })?;
+
+        Ok(())
+    }
+
+    /// Record a subscription and seed the subscriber's read-model
+    async fn subscribe(
+        &mut self,
+        target: SubscriptionTarget,
+        chain_id: ChainId,
+    ) -> Result<(), CascadeProtocolError> {
+        if let SubscriptionTarget::Market(market_id) = &target {
+            let market = match self.state.markets.get(market_id).await? {
+                Some(market) => Some(market),
+                None => self.state.market_replicas.get(market_id).await?,
+            };
+            let market =
+                market.ok_or_else(|| CascadeProtocolError::MarketNotFound(market_id.clone()))?;
+            self.runtime
+                .prepare_message(Message::MarketCreated { market })
+                .send_to(chain_id);
+        }
+        self.state.add_subscriber(target, chain_id).await?;
+        Ok(())
+    }
+
+    /// Send a message to every chain subscribed to a market or its category.
+    /// Subscriptions are kept on the application chain, so a chain hosting
+    /// markets sends the message there to be passed on.
+    async fn notify_subscribers(
+        &mut self,
+        market: &Market,
+        message: Message,
+    ) -> Result<(), CascadeProtocolError> {
+        if !self.on_app_chain() {
+            let app_chain_id = self.app_chain_id();
+            self.runtime.prepare_message(message).send_to(app_chain_id);
+            return Ok(());
+        }
+        for chain_id in self.state.subscribers_of(market).await? {
+            self.runtime.prepare_message(message.clone()).send_to(chain_id);
+        }
+        Ok(())
+    }
+
+    /// Apply an update of a market to this chain's read-model, if it has
+    /// one. Updates are trusted from the application chain, and on the
+    /// application chain from the chain hosting the market, in which case
+    /// they are passed on to the subscribers.
+    async fn receive_market_update(
+        &mut self,
+        origin: ChainId,
+        market_id: &str,
+        message: Message,
+        update: impl FnOnce(&mut Market),
+    ) -> Result<(), CascadeProtocolError> {
+        let from_host = self.on_app_chain() && self.host_chain(market_id).await? == Some(origin);
+        if !from_host && origin != self.app_chain_id() {
+            return Ok(());
+        }
+        if let Some(mut market) = self.state.market_replicas.get(market_id).await? {
+            update(&mut market);
+            if from_host {
+                self.notify_subscribers(&market, message).await?;
+            }
+            self.state.market_replicas.insert(market_id, market)?;
+        }
+        Ok(())
+    }
+
+    /// Handle a bet sent from another chain, whose stake has been transferred
+    /// along with it. Rejected bets are refunded to the sender. The owner was
+    /// authenticated by this application on the sending chain.
+    #[allow(clippy::too_many_arguments)]
+    async fn receive_bet(
+        &mut self,
+        owner: AccountOwner,
+        reply_to: ChainId,
+        market_id: String,
+        outcome_id: String,
+        amount: Amount,
+        min_odds_bps: Option<u32>,
+        collateral: Collateral,
+    ) {
+        if let Some(host_chain) = self
+            .host_chain(&market_id)
+            .await
+            .expect("Failed to load market chain")
+        {
+            let message = Message::PlaceBet {
+                owner,
+                market_id,
+                outcome_id,
+                amount,
+                min_odds_bps,
+                collateral,
+                reply_to,
+            };
+            self.release(collateral, host_chain, None, amount);
+            self.forward_to_host(host_chain, message);
+            return;
+        }
+
+        match self
+            .place_bet(
+                owner,
+                market_id.clone(),
+                outcome_id,
+                amount,
+                min_odds_bps,
+                collateral,
+                reply_to,
+            )
+            .await
+        {
+            Ok(bet) => {
+                self.runtime
+                    .prepare_message(Message::BetAccepted { bet })
+                    .send_to(reply_to);
+            }
+            Err(error) => {
+                let refund = Some((collateral, amount));
+                self.reject(owner, reply_to, market_id, RejectedRequest::Bet, refund, error);
+            }
+        }
+    }
+
+    /// Handle a purchase of shares sent from another chain, along with
+    /// `max_cost`. Rejected purchases are refunded to the sender.
+    #[allow(clippy::too_many_arguments)]
+    async fn receive_buy(
+        &mut self,
+        owner: AccountOwner,
+        reply_to: ChainId,
+        market_id: String,
+        outcome_id: String,
+        shares: Amount,
+        max_cost: Amount,
+        collateral: Collateral,
+    ) {
+        if let Some(host_chain) = self
+            .host_chain(&market_id)
+            .await
+            .expect("Failed to load market chain")
+        {
+            let message = Message::BuyShares {
+                owner,
+                market_id,
+                outcome_id,
+                shares,
+                max_cost,
+                collateral,
+                reply_to,
+            };
+            self.release(collateral, host_chain, None, max_cost);
+            self.forward_to_host(host_chain, message);
+            return;
+        }
+
+        let result = self
+            .buy_shares(
+                owner,
+                market_id.clone(),
+                outcome_id,
+                shares,
+                max_cost,
+                collateral,
+                reply_to,
+            )
+            .await;
+        if let Err(error) = result {
+            self.reject(
+                owner,
+                reply_to,
+                market_id,
+                RejectedRequest::Buy,
+                Some((collateral, max_cost)),
+                error,
+            );
+        }
+    }
+
+    /// Handle a sale of shares sent from another chain, paying the proceeds
+    /// to the chain it was made from
+    async fn receive_sell(
+        &mut self,
+        owner: AccountOwner,
+        reply_to: ChainId,
+        market_id: String,
+        outcome_id: String,
+        shares: Amount,
+        min_proceeds: Amount,
+    ) {
+        if let Some(host_chain) = self
+            .host_chain(&market_id)
+            .await
+            .expect("Failed to load market chain")
+        {
+            let message = Message::SellShares {
+                owner,
+                market_id,
+                outcome_id,
+                shares,
+                min_proceeds,
+                reply_to,
+            };
+            self.forward_to_host(host_chain, message);
+            return;
+        }
+
+        let result = self
+            .sell_shares(owner, market_id.clone(), outcome_id, shares, min_proceeds, reply_to)
+            .await;
+        if let Err(error) = result {
+            self.reject(owner, reply_to, market_id, RejectedRequest::Sell, None, error);
+        }
+    }
+
+    /// Handle a limit order sent from another chain, along with the cost of a
+    /// buy order. Rejected orders are refunded to the sender.
+    #[allow(clippy::too_many_arguments)]
+    async fn receive_limit_order(
+        &mut self,
+        owner: AccountOwner,
+        reply_to: ChainId,
+        market_id: String,
+        outcome_id: String,
+        side: OrderSide,
+        price_bps: u16,
+        shares: Amount,
+        collateral: Collateral,
+    ) {
+        let locked = match side {
+            OrderSide::Buy => lot_cost(shares, price_bps).unwrap_or(Amount::ZERO),
+            OrderSide::Sell => Amount::ZERO,
+        };
+        if let Some(host_chain) = self
+            .host_chain(&market_id)
+            .await
+            .expect("Failed to load market chain")
+        {
+            let message = Message::PlaceLimitOrder {
+                owner,
+                market_id,
+                outcome_id,
+                side,
+                price_bps,
+                shares,
+                collateral,
+                reply_to,
+            };
+            self.release(collateral, host_chain, None, locked);
+            self.forward_to_host(host_chain, message);
+            return;
+        }
+
+        let result = self
+            .place_limit_order(
+                owner,
+                market_id.clone(),
+                outcome_id,
+                side,
+                price_bps,
+                shares,
+                collateral,
+                reply_to,
+            )
+            .await;
+        if let Err(error) = result {
+            self.reject(
+                owner,
+                reply_to,
+                market_id,
+                RejectedRequest::Order,
+                Some((collateral, locked)),
+                error,
+            );
+        }
+    }
+
+    /// Handle a cancellation of an order sent from another chain
+    async fn receive_cancel_order(
+        &mut self,
+        owner: AccountOwner,
+        reply_to: ChainId,
+        market_id: String,
+        order_id: String,
+    ) {
+        if let Some(host_chain) = self
+            .host_chain(&market_id)
+            .await
+            .expect("Failed to load market chain")
+        {
+            let message = Message::CancelOrder {
+                owner,
+                market_id,
+                order_id,
+                reply_to,
+            };
+            self.forward_to_host(host_chain, message);
+            return;
+        }
+
+        let result = self
+            .cancel_order(owner, market_id.clone(), order_id, reply_to)
+            .await;
+        if let Err(error) = result {
+            self.reject(owner, reply_to, market_id, RejectedRequest::CancelOrder, None, error);
+        }
+    }
+
+    /// Handle a transfer of a position sent from another chain
+    #[allow(clippy::too_many_arguments)]
+    async fn receive_transfer(
+        &mut self,
+        owner: AccountOwner,
+        reply_to: ChainId,
+        market_id: String,
+        outcome_id: String,
+        amount: Amount,
+        to: AccountOwner,
+        to_chain: Option<ChainId>,
+    ) {
+        if let Some(host_chain) = self
+            .host_chain(&market_id)
+            .await
+            .expect("Failed to load market chain")
+        {
+            let message = Message::TransferPosition {
+                owner,
+                market_id,
+                outcome_id,
+                amount,
+                to,
+                to_chain,
+                reply_to,
+            };
+            self.forward_to_host(host_chain, message);
+            return;
+        }
+
+        let result = self
+            .transfer_position(
+                owner,
+                market_id.clone(),
+                outcome_id,
+                amount,
+                to,
+                to_chain,
+                reply_to,
+            )
+            .await;
+        if let Err(error) = result {
+            self.reject(owner, reply_to, market_id, RejectedRequest::Transfer, None, error);
+        }
+    }
+
+    /// Handle a listing of a position sent from another chain
+    async fn receive_listing(
+        &mut self,
+        owner: AccountOwner,
+        reply_to: ChainId,
+        market_id: String,
+        outcome_id: String,
+        amount: Amount,
+        price: Amount,
+    ) {
+        if let Some(host_chain) = self
+            .host_chain(&market_id)
+            .await
+            .expect("Failed to load market chain")
+        {
+            let message = Message::ListPosition {
+                owner,
+                market_id,
+                outcome_id,
+                amount,
+                price,
+                reply_to,
+            };
+            self.forward_to_host(host_chain, message);
+            return;
+        }
+
+        let result = self
+            .list_position(owner, market_id.clone(), outcome_id, amount, price, reply_to)
+            .await;
+        if let Err(error) = result {
+            self.reject(owner, reply_to, market_id, RejectedRequest::List, None, error);
+        }
+    }
+
+    /// Handle a purchase of a listing sent from another chain, along with
+    /// `max_price`. Rejected purchases are refunded to the sender.
+    async fn receive_fill(
+        &mut self,
+        owner: AccountOwner,
+        reply_to: ChainId,
+        market_id: String,
+        listing_id: String,
+        max_price: Amount,
+        collateral: Collateral,
+    ) {
+        if let Some(host_chain) = self
+            .host_chain(&market_id)
+            .await
+            .expect("Failed to load market chain")
+        {
+            let message = Message::FillListing {
+                owner,
+                market_id,
+                listing_id,
+                max_price,
+                collateral,
+                reply_to,
+            };
+            self.release(collateral, host_chain, None, max_price);
+            self.forward_to_host(host_chain, message);
+            return;
+        }
+
+        let result = self
+            .fill_listing(owner, market_id.clone(), listing_id, max_price, collateral, reply_to)
+            .await;
+        if let Err(error) = result {
+            self.reject(
+                owner,
+                reply_to,
+                market_id,
+                RejectedRequest::Fill,
+                Some((collateral, max_price)),
+                error,
+            );
+        }
+    }
+
+    /// Handle a cancellation of a listing sent from another chain
+    async fn receive_cancel_listing(
+        &mut self,
+        owner: AccountOwner,
+        reply_to: ChainId,
+        market_id: String,
+        listing_id: String,
+    ) {
+        if let Some(host_chain) = self
+            .host_chain(&market_id)
+            .await
+            .expect("Failed to load market chain")
+        {
+            let message = Message::CancelListing {
+                owner,
+                market_id,
+                listing_id,
+                reply_to,
+            };
+            self.forward_to_host(host_chain, message);
+            return;
+        }
+
+        let result = self
+            .cancel_listing(owner, market_id.clone(), listing_id, reply_to)
+            .await;
+        if let Err(error) = result {
+            self.reject(owner, reply_to, market_id, RejectedRequest::CancelListing, None, error);
+        }
+    }
+
+    /// Handle new odds sent from the bookmaker's chain
+    async fn receive_set_odds(
+        &mut self,
+        owner: AccountOwner,
+        reply_to: ChainId,
+        market_id: String,
+        odds_bps: Vec<u32>,
+    ) {
+        if let Some(host_chain) = self
+            .host_chain(&market_id)
+            .await
+            .expect("Failed to load market chain")
+        {
+            let message = Message::SetOdds {
+                owner,
+                market_id,
+                odds_bps,
+                reply_to,
+            };
+            self.forward_to_host(host_chain, message);
+            return;
+        }
+
+        if let Err(error) = self.set_odds(owner, market_id.clone(), odds_bps).await {
+            self.reject(owner, reply_to, market_id, RejectedRequest::SetOdds, None, error);
+        }
+    }
+
+    /// Handle a mint of complete sets sent from another chain, along with
+    /// `amount`. Rejected mints are refunded to the sender.
+    async fn receive_mint(
+        &mut self,
+        owner: AccountOwner,
+        reply_to: ChainId,
+        market_id: String,
+        amount: Amount,
+        collateral: Collateral,
+    ) {
+        if let Some(host_chain) = self
+            .host_chain(&market_id)
+            .await
+            .expect("Failed to load market chain")
+        {
+            let message = Message::MintCompleteSet {
+                owner,
+                market_id,
+                amount,
+                collateral,
+                reply_to,
+            };
+            self.release(collateral, host_chain, None, amount);
+            self.forward_to_host(host_chain, message);
+            return;
+        }
+
+        let result = self
+            .mint_complete_set(owner, market_id.clone(), amount, collateral)
+            .await;
+        if let Err(error) = result {
+            self.reject(
+                owner,
+                reply_to,
+                market_id,
+                RejectedRequest::Mint,
+                Some((collateral, amount)),
+                error,
+            );
+        }
+    }
+
+    /// Handle a redemption of complete sets sent from another chain, paying
+    /// out to the chain it was made from
+    async fn receive_redeem(
+        &mut self,
+        owner: AccountOwner,
+        reply_to: ChainId,
+        market_id: String,
+        amount: Amount,
+    ) {
+        if let Some(host_chain) = self
+            .host_chain(&market_id)
+            .await
+            .expect("Failed to load market chain")
+        {
+            let message = Message::RedeemCompleteSet {
+                owner,
+                market_id,
+                amount,
+                reply_to,
+            };
+            self.forward_to_host(host_chain, message);
+            return;
+        }
+
+        let result = self
+            .redeem_complete_set(owner, market_id.clone(), amount, reply_to)
+            .await;
+        if let Err(error) = result {
+            self.reject(owner, reply_to, market_id, RejectedRequest::Redeem, None, error);
+        }
+    }
+
+    /// Handle a deposit of liquidity sent from another chain, along with
+    /// `amount`. Rejected deposits are refunded to the sender.
+    async fn receive_add_liquidity(
+        &mut self,
+        owner: AccountOwner,
+        reply_to: ChainId,
+        market_id: String,
+        amount: Amount,
+        collateral: Collateral,
+    ) {
+        if let Some(host_chain) = self
+            .host_chain(&market_id)
+            .await
+            .expect("Failed to load market chain")
+        {
+            let message = Message::AddLiquidity {
+                owner,
+                market_id,
+                amount,
+                collateral,
+                reply_to,
+            };
+            self.release(collateral, host_chain, None, amount);
+            self.forward_to_host(host_chain, message);
+            return;
+        }
+
+        let result = self
+            .add_liquidity(owner, market_id.clone(), amount, collateral, reply_to)
+            .await;
+        if let Err(error) = result {
+            self.reject(
+                owner,
+                reply_to,
+                market_id,
+                RejectedRequest::AddLiquidity,
+                Some((collateral, amount)),
+                error,
+            );
+        }
+    }
+
+    /// Handle a withdrawal of liquidity sent from another chain, paying out
+    /// to the chain it was made from
+    async fn receive_remove_liquidity(
+        &mut self,
+        owner: AccountOwner,
+        reply_to: ChainId,
+        market_id: String,
+        lp_shares: Amount,
+    ) {
+        if let Some(host_chain) = self
+            .host_chain(&market_id)
+            .await
+            .expect("Failed to load market chain")
+        {
+            let message = Message::RemoveLiquidity {
+                owner,
+                market_id,
+                lp_shares,
+                reply_to,
+            };
+            self.forward_to_host(host_chain, message);
+            return;
+        }
+
+        let result = self
+            .remove_liquidity(owner, market_id.clone(), lp_shares, reply_to)
+            .await;
+        if let Err(error) = result {
+            self.reject(owner, reply_to, market_id, RejectedRequest::RemoveLiquidity, None, error);
+        }
+    }
+
+    /// Handle a cash-out sent from another chain, paying out to the chain it
+    /// was made from
+    async fn receive_cash_out(
+        &mut self,
+        owner: AccountOwner,
+        reply_to: ChainId,
+        market_id: String,
+        bet_id: String,
+        amount: Amount,
+    ) {
+        if let Some(host_chain) = self
+            .host_chain(&market_id)
+            .await
+            .expect("Failed to load market chain")
+        {
+            let message = Message::CashOut {
+                owner,
+                market_id,
+                bet_id,
+                amount,
+                reply_to,
+            };
+            self.forward_to_host(host_chain, message);
+            return;
+        }
+
+        match self
+            .cash_out(owner, market_id.clone(), bet_id, amount, reply_to)
+            .await
+        {
+            Ok((bet, _, _)) => {
+                self.runtime
+                    .prepare_message(Message::CashedOut { bet })
+                    .send_to(reply_to);
+            }
+            Err(error) => {
+                self.reject(owner, reply_to, market_id, RejectedRequest::CashOut, None, error);
+            }
+        }
+    }
+
+    /// Handle a claim sent from another chain, paying out to the chain
+    /// the claim was made from
+    async fn receive_claim(&mut self, owner: AccountOwner, reply_to: ChainId, market_id: String) {
+        if let Some(host_chain) = self
+            .host_chain(&market_id)
+            .await
+            .expect("Failed to load market chain")
+        {
+            let message = Message::ClaimWinnings {
+                owner,
+                market_id,
+                reply_to,
+            };
+            self.forward_to_host(host_chain, message);
+            return;
+        }
+
+        match self.claim_winnings(owner, market_id.clone(), reply_to).await {
+            Ok((bet_ids, amount)) => {
+                let message = Message::Claimed {
+                    owner,
+                    market_id,
+                    bet_ids,
+                    amount,
+                };
+                self.runtime.prepare_message(message).send_to(reply_to);
+            }
+            Err(error) => {
+                self.reject(owner, reply_to, market_id, RejectedRequest::Claim, None, error);
+            }
+        }
+    }
+
+    /// Turn down a request sent from another chain: refund what was sent
+    /// along with it, if anything, and tell the owner why
+    fn reject(
+        &mut self,
+        owner: AccountOwner,
+        reply_to: ChainId,
+        market_id: String,
+        request: RejectedRequest,
+        refund: Option<(Collateral, Amount)>,
+        error: CascadeProtocolError,
+    ) {
+        let refunded = match refund {
+            Some((collateral, amount)) => {
+                self.pay_out(owner, reply_to, collateral, amount);
+                amount
+            }
+            None => Amount::ZERO,
+        };
+        let rejection = Rejection {
+            market_id,
+            request,
+            refunded,
+            reason: error.to_string(),
+        };
+        self.runtime
+            .prepare_message(Message::Rejected { owner, rejection })
+            .send_to(reply_to);
+    }
+
+    /// Start hosting a market created on the application chain
+    async fn host_market(&mut self, market: Market) -> Result<(), CascadeProtocolError> {
+        // The market maker's subsidy, if any, was transferred along
+        let funding = market.total_staked;
+        self.state.market_ledgers.insert(&market.id, Ledger::default())?;
+        self.update_ledger(&market.id, market.collateral, |ledger| ledger.deposit(funding))
+            .await?;
+        self.open_pool(&market)?;
+        self.state.update_protocol_stats(|stats| {
+            stats.total_escrowed = stats.total_escrowed.saturating_add(funding)
+        });
+        self.state.add_market(market).await?;
+        self.state
+            .update_protocol_stats(|stats| stats.record_status_change(None, MarketStatus::Active))?;
+        Ok(())
+    }
+
+    /// Open a microchain dedicated to hosting markets, restricted to this application
+    fn open_market_chain(&mut self) -> ChainId {
+        let ownership = self.runtime.chain_ownership();
+        let application_id = self.runtime.application_id().forget_abi();
+        let permissions = ApplicationPermissions::new_single(application_id);
+        let (_, chain_id) = self.runtime.open_chain(ownership, permissions, Amount::ZERO);
+        chain_id
+    }
+
+    /// The chain hosting a market, if the application chain routes it elsewhere
+    async fn host_chain(
+        &mut self,
+        market_id: &str,
+    ) -> Result<Option<ChainId>, CascadeProtocolError> {
+        Ok(self.state.market_chains.get(market_id).await?)
+    }
+
+    /// Send an operation's message on to the chain that handles its market,
+    /// along with any stake escrowed for it: other chains send it to the
+    /// application chain, which passes it on to the market's own chain
+    async fn route(
+        &mut self,
+        market_id: &str,
+        message: Message,
+        stake: Option<(Collateral, Amount)>,
+    ) -> Route {
+        if !self.on_app_chain() {
+            // The stake was escrowed on the application chain already
+            return Route::Forwarded(self.send_to_app_chain(message));
+        }
+        let host_chain = self
+            .host_chain(market_id)
+            .await
+            .expect("Failed to load market chain");
+        match host_chain {
+            Some(host_chain) => {
+                if let Some((collateral, amount)) = stake {
+                    self.release(collateral, host_chain, None, amount);
+                }
+                Route::Forwarded(self.forward_to_host(host_chain, message))
+            }
+            None => Route::Local,
+        }
+    }
+
+    /// Pass a message on to the chain hosting a market
+    fn forward_to_host(&mut self, host_chain: ChainId, message: Message) -> OperationResponse {
+        self.runtime
+            .prepare_message(message)
+            .with_authentication()
+            .send_to(host_chain);
+        OperationResponse::Forwarded {
+            chain_id: host_chain,
+        }
+    }
+
+    /// The owner acting in the current operation: the calling application if
+    /// there is one, otherwise the signer
+    fn caller(&mut self) -> Result<AccountOwner, CascadeProtocolError> {
+        if let Some(application_id) = self.runtime.authenticated_caller_id() {
+            return Ok(AccountOwner::Application(application_id));
+        }
+        self.runtime
+            .authenticated_signer()
+            .map(AccountOwner::User)
+            .ok_or(CascadeProtocolError::Unauthorized)
+    }
+
+    /// Check that markets may be created: users always can, applications
+    /// only if they are on the allow-list
+    async fn ensure_market_creator(&mut self) -> Result<(), CascadeProtocolError> {
+        if let Some(application_id) = self.runtime.authenticated_caller_id() {
+            if !self.state.allowed_creators.contains(&application_id).await? {
+                return Err(CascadeProtocolError::Unauthorized);
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that the signer of the operation is the admin. Admin operations
+    /// cannot be performed by other applications.
+    fn ensure_admin(&mut self) -> Result<(), CascadeProtocolError> {
+        if self.runtime.authenticated_caller_id().is_some() {
+            return Err(CascadeProtocolError::Unauthorized);
+        }
+        let caller = self.runtime
+            .authenticated_signer()
+            .ok_or(CascadeProtocolError::Unauthorized)?;
+
+        let admin = self.state.admin.get();
+        if *admin != Some(caller) {
+            return Err(CascadeProtocolError::Unauthorized);
+        }
+        Ok(())
+    }
+
+    /// The collateral a stake for a market is sent in: the market's own if
+    /// this chain hosts it or subscribed to it, otherwise the default one
+    async fn stake_collateral(&mut self, market_id: &str) -> Collateral {
+        let mut market = self.state.markets.get(market_id).await.expect("Failed to load market");
+        if market.is_none() {
+            market = self
+                .state
+                .market_replicas
+                .get(market_id)
+                .await
+                .expect("Failed to load market");
+        }
+        match market {
+            Some(market) => market.collateral,
+            None => self.runtime.application_parameters().default_collateral,
+        }
+    }
+
+    /// Record a bet or a trade in its owner's and the protocol's statistics.
+    /// Owners are counted as bettors on their first one.
+    async fn record_trade(
+        &mut self,
+        owner: AccountOwner,
+        volume: Amount,
+    ) -> Result<(), CascadeProtocolError> {
+        let first_trade = self
+            .state
+            .user_stats
+            .get(&owner)
+            .await?
+            .is_none_or(|stats| stats.bet_count == 0);
+        self.state
+            .update_user_stats(owner, |stats| stats.record_trade(volume))
+            .await??;
+        self.state
+            .update_protocol_stats(|stats| stats.record_trade(volume, first_trade))?;
+        Ok(())
+    }
+
+    /// Move a stake from the owner's account to the application's escrow on
+    /// the application chain. Fungible tokens are escrowed in the
+    /// application's own account, and native tokens, which only users and
+    /// chains can hold, in the balance of the chain.
+    async fn escrow_stake(
+        &mut self,
+        owner: AccountOwner,
+        collateral: Collateral,
+        amount: Amount,
+    ) -> Result<(), CascadeProtocolError> {
+        let app_chain_id = self.app_chain_id();
+        match (collateral, owner) {
+            (Collateral::Native, AccountOwner::User(owner)) => {
+                self.runtime
+                    .transfer(Some(owner), Account::chain(app_chain_id), amount)
+            }
+            (Collateral::Native, AccountOwner::Application(_)) => {
+                return Err(CascadeProtocolError::ApplicationPayment);
+            }
+            (Collateral::Token(token), AccountOwner::User(_)) => {
+                let target_account = FungibleAccount {
+                    chain_id: app_chain_id,
+                    owner: self.own_account_owner(),
+                };
+                self.call_token(
+                    token,
+                    FungibleOperation::Transfer {
+                        owner,
+                        amount,
+                        target_account,
+                    },
+                );
+            }
+            (Collateral::Token(token), AccountOwner::Application(_)) => {
+                self.take_deposit(token, owner, amount).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Take a stake from the tokens a calling application transferred to
+    /// this application's account on the current chain. An application
+    /// cannot be debited by another one, so it pays by transferring the
+    /// stake in the same transaction, before calling. What the account holds
+    /// beyond the escrow of the markets hosted here is the deposit; the stake
+    /// is sent on to the escrow on the application chain and any excess is
+    /// returned.
+    async fn take_deposit(
+        &mut self,
+        token: ApplicationId,
+        payer: AccountOwner,
+        amount: Amount,
+    ) -> Result<(), CascadeProtocolError> {
+        let collateral = Collateral::Token(token);
+        let escrowed = self
+            .state
+            .collateral_ledgers
+            .get(&collateral)
+            .await?
+            .unwrap_or_default()
+            .escrowed;
+        let owner = self.own_account_owner();
+        let balance = match self.runtime.call_application(
+            true,
+            token.with_abi::<FungibleTokenAbi>(),
+            &FungibleOperation::Balance { owner },
+        ) {
+            FungibleResponse::Balance(balance) => balance,
+            _ => Amount::ZERO,
+        };
+        let deposited = balance.saturating_sub(escrowed);
+        let excess = deposited
+            .try_sub(amount)
+            .map_err(|_| CascadeProtocolError::InsufficientDeposit {
+                required: amount,
+                deposited,
+            })?;
+
+        let chain_id = self.runtime.chain_id();
+        self.release(collateral, chain_id, Some(payer), excess);
+        let app_chain_id = self.app_chain_id();
+        if chain_id != app_chain_id {
+            self.release(collateral, app_chain_id, None, amount);
+        }
+        Ok(())
+    }
+
+    /// Apply a change to the ledger of a market and to the totals of its
+    /// collateral. Only the chain holding the market's stakes has a ledger.
+    async fn update_ledger(
+        &mut self,
+        market_id: &str,
+        collateral: Collateral,
+        update: impl Fn(&mut Ledger) -> Result<(), ArithmeticError>,
+    ) -> Result<(), CascadeProtocolError> {
+        let Some(mut ledger) = self.state.market_ledgers.get(market_id).await? else {
+            return Ok(());
+        };
+        let mut totals = self
+            .state
+            .collateral_ledgers
+            .get(&collateral)
+            .await?
+            .unwrap_or_default();
+        update(&mut ledger)?;
+        update(&mut totals)?;
+        self.state.market_ledgers.insert(market_id, ledger)?;
+        self.state.collateral_ledgers.insert(&collateral, totals)?;
+        Ok(())
+    }
+
+    /// Book a payout or refund against a market's escrow, refusing to pay
+    /// out stakes that belong to other markets
+    async fn withdraw(
+        &mut self,
+        market_id: &str,
+        collateral: Collateral,
+        amount: Amount,
+    ) -> Result<(), CascadeProtocolError> {
+        if let Some(ledger) = self.state.market_ledgers.get(market_id).await? {
+            if ledger.escrowed < amount {
+                return Err(CascadeProtocolError::InsufficientFunds {
+                    required: amount,
+                    available: ledger.escrowed,
+                });
+            }
+        }
+        self.update_ledger(market_id, collateral, |ledger| ledger.withdraw(amount))
+            .await
+    }
+
+    /// Pay an amount held by the application to an owner's account on a chain
+    fn pay_out(
+        &mut self,
+        owner: AccountOwner,
+        chain_id: ChainId,
+        collateral: Collateral,
+        amount: Amount,
+    ) {
+        self.release(collateral, chain_id, Some(owner), amount);
+    }
+
+    /// Move an amount out of the application's escrow on this chain, to an
+    /// owner's account or, without an owner, to the escrow on another chain.
+    /// Native tokens owed to an application go to the balance of its chain.
+    fn release(
+        &mut self,
+        collateral: Collateral,
+        chain_id: ChainId,
+        owner: Option<AccountOwner>,
+        amount: Amount,
+    ) {
+        if amount == Amount::ZERO {
+            return;
+        }
+        match collateral {
+            Collateral::Native => {
+                let owner = match owner {
+                    Some(AccountOwner::User(owner)) => Some(owner),
+                    Some(AccountOwner::Application(_)) | None => None,
+                };
+                self.runtime.transfer(None, Account { chain_id, owner }, amount)
+            }
+            Collateral::Token(token) => {
+                let escrow = self.own_account_owner();
+                let target_account = FungibleAccount {
+                    chain_id,
+                    owner: owner.unwrap_or(escrow),
+                };
+                self.call_token(
+                    token,
+                    FungibleOperation::Transfer {
+                        owner: escrow,
+                        amount,
+                        target_account,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Call a fungible token application, forwarding the signer so that
+    /// users' tokens can be moved
+    fn call_token(&mut self, token: ApplicationId, operation: FungibleOperation) {
+        self.runtime
+            .call_application(true, token.with_abi::<FungibleTokenAbi>(), &operation);
+    }
+
+    /// The owner paid what is swept from markets: the treasury of the
+    /// parameters, or else the admin, who is only known on the application
+    /// chain
+    fn treasury(&mut self) -> Result<AccountOwner, CascadeProtocolError> {
+        match self.runtime.application_parameters().treasury {
+            Some(treasury) => Ok(treasury),
+            None => self
+                .state
+                .admin
+                .get()
+                .map(AccountOwner::User)
+                .ok_or(CascadeProtocolError::TreasuryUnknown),
+        }
+    }
+
+    /// This application as an account owner, holding token escrow
+    fn own_account_owner(&mut self) -> AccountOwner {
+        AccountOwner::Application(self.runtime.application_id().forget_abi())
+    }
+
+    /// Whether this is the chain that created the application
+    fn on_app_chain(&mut self) -> bool {
+        self.runtime.chain_id() == self.app_chain_id()
+    }
+
+    /// Send a message to the chain that created the application
+    fn send_to_app_chain(&mut self, message: Message) -> OperationResponse {
+        let app_chain_id = self.app_chain_id();
+        self.runtime
+            .prepare_message(message)
+            .with_authentication()
+            .send_to(app_chain_id);
+        OperationResponse::Forwarded {
+            chain_id: app_chain_id,
+        }
+    }
+
+    /// The chain that created the application and holds the markets
+    fn app_chain_id(&mut self) -> ChainId {
+        self.runtime.application_id().creation.chain_id
+    }
+
+    /// Hand the admin role over to another owner (Admin only)
+    fn transfer_admin(&mut self, new_admin: Owner) -> Result<(), CascadeProtocolError> {
+        self.ensure_admin()?;
+        let previous_admin = *self.state.admin.get();
+        self.state.admin.set(Some(new_admin));
+        self.record_event(EventPayload::AdminChanged(AdminChangedEvent {
+            previous_admin,
+            new_admin,
+        }));
+
+        Ok(())
+    }
+
+    /// Append an event to the log, stamped with the current block time
+    fn record_event(&mut self, payload: EventPayload) {
+        let timestamp = self.runtime.system_time().micros();
+        self.state.append_event(timestamp, payload);
+    }
+}
+
+// Define the ABI for the application
+ 
+
+ 
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, collections::BTreeMap, rc::Rc};
+
+    use linera_sdk::{
+        base::{BlockHeight, BytecodeId, CryptoHash, MessageId, Timestamp},
+        util::BlockingWait,
+    };
+
+    use super::*;
+    use crate::msg::MarketCategory;
+
+    /// Token balances by chain and owner, kept by the mocked token application
+    type Balances = Rc<RefCell<BTreeMap<(ChainId, AccountOwner), Amount>>>;
+
+    const HOUR: u64 = 3_600_000_000;
+
+    fn application_id(n: u64) -> ApplicationId {
+        let hash = CryptoHash::from([n; 4]);
+        ApplicationId {
+            bytecode_id: BytecodeId::new(hash, hash),
+            creation: MessageId {
+                chain_id: ChainId::root(0),
+                height: BlockHeight(n),
+                index: 0,
+            },
+        }
+    }
+
+    fn user(n: u64) -> Owner {
+        Owner(CryptoHash::from([n; 4]))
+    }
+
+    fn tokens(amount: u128) -> Amount {
+        Amount::from_tokens(amount)
+    }
+
+    fn admin() -> Owner {
+        user(0)
+    }
+
+    fn token() -> ApplicationId {
+        application_id(1)
+    }
+
+    /// The account this application holds its escrow in
+    fn escrow() -> AccountOwner {
+        AccountOwner::Application(application_id(0))
+    }
+
+    fn balance(balances: &Balances, owner: AccountOwner) -> Amount {
+        let balances = balances.borrow();
+        balances.get(&(ChainId::root(0), owner)).copied().unwrap_or_default()
+    }
+
+    fn move_tokens(balances: &Balances, from: AccountOwner, to: FungibleAccount, amount: Amount) {
+        let mut balances = balances.borrow_mut();
+        let source = balances.entry((ChainId::root(0), from)).or_default();
+        *source = source.try_sub(amount).expect("Not enough tokens");
+        balances.entry((to.chain_id, to.owner)).or_default().try_add_assign(amount).unwrap();
+    }
+
+    /// The contract, instantiated on the application chain with markets in
+    /// a token whose balances the mocked token application keeps
+    fn contract(balances: &Balances) -> CascadeProtocolContract {
+        let parameters = Parameters {
+            default_collateral: Collateral::Token(token()),
+            allowed_tokens: vec![token()],
+            treasury: None,
+            claim_window: None,
+            cash_out_penalty_bps: 0,
+        };
+        let ledger = balances.clone();
+        let runtime = ContractRuntime::new()
+            .with_application_parameters(parameters)
+            .with_application_id(application_id(0).with_abi())
+            .with_chain_id(ChainId::root(0))
+            .with_authenticated_signer(admin())
+            .with_authenticated_caller_id(None)
+            .with_system_time(Timestamp::from(0))
+            .with_call_application_handler(move |_, _, operation| {
+                let response = match bcs::from_bytes(&operation).unwrap() {
+                    FungibleOperation::Balance { owner } => {
+                        FungibleResponse::Balance(balance(&ledger, owner))
+                    }
+                    FungibleOperation::Transfer {
+                        owner,
+                        amount,
+                        target_account,
+                    } => {
+                        move_tokens(&ledger, owner, target_account, amount);
+                        FungibleResponse::Ok
+                    }
+                    operation => panic!("Unexpected token operation: {operation:?}"),
+                };
+                bcs::to_bytes(&response).unwrap()
+            });
+        let mut contract = CascadeProtocolContract::load(runtime).blocking_wait();
+        contract
+            .instantiate(InstantiationArgument { admin: admin() })
+            .blocking_wait();
+        contract
+    }
+
+    /// Create a market expiring in an hour, and return its ID and outcome IDs
+    fn create_market(
+        contract: &mut CascadeProtocolContract,
+        outcome_names: &[&str],
+        mechanism: MarketMechanism,
+        dust_policy: DustPolicy,
+    ) -> (String, Vec<String>) {
+        let operation = Operation::CreateMarket {
+            question: "Will it rain?".to_string(),
+            outcome_names: outcome_names.iter().map(|name| name.to_string()).collect(),
+            expiry_time: HOUR,
+            category: MarketCategory::Other,
+            parent_id: None,
+            dedicated_chain: false,
+            collateral: None,
+            claim_window: None,
+            mechanism,
+            buckets: None,
+            dust_policy,
+        };
+        match contract.execute_operation(operation).blocking_wait() {
+            OperationResponse::MarketCreated { id, outcome_ids } => (id, outcome_ids),
+            response => panic!("Unexpected response: {response:?}"),
+        }
+    }
+
+    /// Act as a user signing the next operations
+    fn sign_as(contract: &mut CascadeProtocolContract, owner: Owner) {
+        contract.runtime.set_authenticated_signer(owner);
+        contract.runtime.set_authenticated_caller_id(None);
+    }
+
+    fn bet(contract: &mut CascadeProtocolContract, market_id: &str, outcome_id: &str, stake: u128) {
+        let operation = Operation::PlaceBet {
+            market_id: market_id.to_string(),
+            outcome_id: outcome_id.to_string(),
+            amount: tokens(stake),
+            min_odds_bps: None,
+        };
+        let response = contract.execute_operation(operation).blocking_wait();
+        assert!(matches!(response, OperationResponse::BetPlaced { .. }), "{response:?}");
+    }
+
+    fn claim(contract: &mut CascadeProtocolContract, market_id: &str) -> Amount {
+        let operation = Operation::ClaimWinnings {
+            market_id: market_id.to_string(),
+        };
+        match contract.execute_operation(operation).blocking_wait() {
+            OperationResponse::Claimed { amount, .. } => amount,
+            response => panic!("Unexpected response: {response:?}"),
+        }
+    }
+
+    /// Expire a market and resolve it as the admin
+    fn resolve(contract: &mut CascadeProtocolContract, market_id: &str, outcome_id: &str) {
+        sign_as(contract, admin());
+        contract.runtime.set_system_time(Timestamp::from(HOUR + 1));
+        let operation = Operation::ResolveMarket {
+            market_id: market_id.to_string(),
+            winning_outcome_id: outcome_id.to_string(),
+        };
+        contract.execute_operation(operation).blocking_wait();
+    }
+
+    #[test]
+    fn test_application_pays_by_deposit_and_claims() {
+        let balances = Balances::default();
+        let caller = AccountOwner::Application(application_id(2));
+        for owner in [AccountOwner::User(user(1)), caller] {
+            balances.borrow_mut().insert((ChainId::root(0), owner), tokens(100));
+        }
+        let mut contract = contract(&balances);
+        let (market_id, outcome_ids) = create_market(
+            &mut contract,
+            &["Yes", "No"],
+            MarketMechanism::Parimutuel,
+            DustPolicy::LastClaimer,
+        );
+
+        sign_as(&mut contract, user(1));
+        bet(&mut contract, &market_id, &outcome_ids[0], 30);
+
+        // The application transfers 15 tokens to the escrow before betting
+        // 10: the stake is kept and the excess returned
+        let deposit = FungibleAccount {
+            chain_id: ChainId::root(0),
+            owner: escrow(),
+        };
+        move_tokens(&balances, caller, deposit, tokens(15));
+        contract.runtime.set_authenticated_caller_id(application_id(2));
+        bet(&mut contract, &market_id, &outcome_ids[1], 10);
+        assert_eq!(balance(&balances, caller), tokens(90));
+        assert_eq!(balance(&balances, escrow()), tokens(40));
+
+        // The escrow of the market is not a deposit
+        let result = contract
+            .escrow_stake(caller, Collateral::Token(token()), tokens(1))
+            .blocking_wait();
+        assert!(matches!(
+            result,
+            Err(CascadeProtocolError::InsufficientDeposit { deposited, .. })
+                if deposited == Amount::ZERO
+        ));
+        // Applications cannot pay in native tokens
+        let result = contract
+            .escrow_stake(caller, Collateral::Native, tokens(1))
+            .blocking_wait();
+        assert!(matches!(result, Err(CascadeProtocolError::ApplicationPayment)));
+
+        resolve(&mut contract, &market_id, &outcome_ids[1]);
+        contract.runtime.set_authenticated_caller_id(application_id(2));
+        let amount = claim(&mut contract, &market_id);
+        assert_eq!(amount, tokens(40));
+        assert_eq!(balance(&balances, caller), tokens(130));
+        assert_eq!(balance(&balances, escrow()), Amount::ZERO);
+    }
+
+    #[test]
+    fn test_sweep_without_deadline_moves_what_nobody_is_owed() {
+        let balances = Balances::default();
+        for n in 1..=3 {
+            let owner = AccountOwner::User(user(n));
+            balances.borrow_mut().insert((ChainId::root(0), owner), tokens(10));
+        }
+        let mut contract = contract(&balances);
+        let sweep = |contract: &mut CascadeProtocolContract, market_id: &str| {
+            sign_as(contract, admin());
+            let treasury = AccountOwner::User(admin());
+            contract.sweep_unclaimed(market_id.to_string(), treasury).blocking_wait()
+        };
+
+        // Shares of the pool of 4 tokens are rounded down by 1/3 and 2/3 of
+        // an atto, which leaves an atto of dust for the treasury
+        let (dusty, outcome_ids) = create_market(
+            &mut contract,
+            &["Yes", "No"],
+            MarketMechanism::Parimutuel,
+            DustPolicy::Treasury,
+        );
+        for (n, amount) in [(1, 1), (2, 2)] {
+            sign_as(&mut contract, user(n));
+            bet(&mut contract, &dusty, &outcome_ids[0], amount);
+        }
+        sign_as(&mut contract, user(3));
+        bet(&mut contract, &dusty, &outcome_ids[1], 1);
+        resolve(&mut contract, &dusty, &outcome_ids[0]);
+
+        // Nobody backed the winner of the other market, so its pool is owed to nobody
+        contract.runtime.set_system_time(Timestamp::from(0));
+        let (unwon, outcome_ids) = create_market(
+            &mut contract,
+            &["Yes", "No"],
+            MarketMechanism::Parimutuel,
+            DustPolicy::LastClaimer,
+        );
+        sign_as(&mut contract, user(3));
+        bet(&mut contract, &unwon, &outcome_ids[0], 5);
+        resolve(&mut contract, &unwon, &outcome_ids[1]);
+
+        // Neither market has a claim deadline, but both can be swept
+        assert_eq!(sweep(&mut contract, &dusty).unwrap(), Amount::from_attos(1));
+        assert_eq!(sweep(&mut contract, &unwon).unwrap(), tokens(5));
+        let swept = tokens(5).try_add(Amount::from_attos(1)).unwrap();
+        assert_eq!(balance(&balances, AccountOwner::User(admin())), swept);
+        // What the winners are owed stays until they claim it
+        assert!(matches!(
+            sweep(&mut contract, &dusty),
+            Err(CascadeProtocolError::ClaimWindowOpen)
+        ));
+        for n in [1, 2] {
+            sign_as(&mut contract, user(n));
+            claim(&mut contract, &dusty);
+        }
+        assert_eq!(balance(&balances, escrow()), Amount::ZERO);
+    }
+
+    #[test]
+    fn test_fixed_odds_winner_stats_match_the_payout() {
+        let balances = Balances::default();
+        for n in 0..=2 {
+            let owner = AccountOwner::User(user(n));
+            balances.borrow_mut().insert((ChainId::root(0), owner), tokens(100));
+        }
+        let mut contract = contract(&balances);
+        let mechanism = MarketMechanism::FixedOdds {
+            reserve: tokens(50),
+            max_exposure: tokens(50),
+        };
+        let (market_id, outcome_ids) =
+            create_market(&mut contract, &["Yes", "No"], mechanism, DustPolicy::LastClaimer);
+        let operation = Operation::SetOdds {
+            market_id: market_id.clone(),
+            odds_bps: vec![25_000, 15_000],
+        };
+        contract.execute_operation(operation).blocking_wait();
+
+        sign_as(&mut contract, user(1));
+        bet(&mut contract, &market_id, &outcome_ids[0], 10);
+        sign_as(&mut contract, user(2));
+        bet(&mut contract, &market_id, &outcome_ids[1], 10);
+        resolve(&mut contract, &market_id, &outcome_ids[0]);
+
+        // The winner is paid at the quoted odds, and gains what is paid
+        // beyond the stake, losing nothing
+        sign_as(&mut contract, user(1));
+        let paid = claim(&mut contract, &market_id);
+        assert_eq!(paid, tokens(25));
+        let stats = |contract: &CascadeProtocolContract, n| {
+            let owner = AccountOwner::User(user(n));
+            contract.state.user_stats.get(&owner).blocking_wait().unwrap().unwrap()
+        };
+        let winner = stats(&contract, 1);
+        assert_eq!(winner.realized_gains, paid.saturating_sub(tokens(10)));
+        assert_eq!(winner.realized_losses, Amount::ZERO);
+        assert_eq!((winner.markets_won, winner.markets_lost), (1, 0));
+        let loser = stats(&contract, 2);
+        assert_eq!(loser.realized_losses, tokens(10));
+        assert_eq!((loser.markets_won, loser.markets_lost), (0, 1));
+    }
+
+    #[test]
+    fn test_unbacked_scalar_side_leaves_the_pool_to_the_other() {
+        let balances = Balances::default();
+        for n in 1..=2 {
+            let owner = AccountOwner::User(user(n));
+            balances.borrow_mut().insert((ChainId::root(0), owner), tokens(10));
+        }
+        let mut contract = contract(&balances);
+        let mechanism = MarketMechanism::Scalar { lower: 0, upper: 100 };
+        let (market_id, outcome_ids) =
+            create_market(&mut contract, &[], mechanism, DustPolicy::LastClaimer);
+
+        // Only the long side is backed
+        for (n, stake) in [(1, 6), (2, 4)] {
+            sign_as(&mut contract, user(n));
+            bet(&mut contract, &market_id, &outcome_ids[0], stake);
+        }
+        sign_as(&mut contract, admin());
+        contract.runtime.set_system_time(Timestamp::from(HOUR + 1));
+        let operation = Operation::ResolveScalarMarket {
+            market_id: market_id.clone(),
+            value: 25,
+        };
+        contract.execute_operation(operation).blocking_wait();
+
+        // A quarter of the pool is the part of the long side, but the short
+        // side has nobody to pay its part to, so the long side is paid it all
+        for (n, stake) in [(1, 6), (2, 4)] {
+            sign_as(&mut contract, user(n));
+            assert_eq!(claim(&mut contract, &market_id), tokens(stake));
+            assert_eq!(balance(&balances, AccountOwner::User(user(n))), tokens(10));
+        }
+        assert_eq!(balance(&balances, escrow()), Amount::ZERO);
+    }
+}
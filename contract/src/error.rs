@@ -1,3 +1,4 @@
+use linera_sdk::base::{Amount, ArithmeticError};
 use thiserror::Error;
 
 /// Custom error types for the Cascade Protocol application
@@ -19,11 +20,17 @@ pub enum CascadeProtocolError {
     OutcomeNotFound(String),
     
     #[error("Insufficient funds: required {required}, available {available}")]
-    InsufficientFunds { required: u64, available: u64 },
+    InsufficientFunds { required: Amount, available: Amount },
     
     #[error("Unauthorized: only admin can perform this operation")]
     Unauthorized,
     
+    #[error("The treasury is only known on the application chain")]
+    TreasuryUnknown,
+    
+    #[error("Markets can only be created on the application chain")]
+    NotOnAppChain,
+    
     #[error("Bet already claimed")]
     AlreadyClaimed,
     
@@ -42,6 +49,90 @@ pub enum CascadeProtocolError {
     #[error("User did not bet on the winning outcome")]
     NotWinningOutcome,
     
+    #[error("Collateral is not allowed for markets")]
+    UnsupportedCollateral,
+    
+    #[error("Stake was sent in a different collateral than the market's")]
+    CollateralMismatch,
+    
+    #[error("Arithmetic overflow in amount computation")]
+    ArithmeticOverflow,
+    
+    #[error("The claim deadline of this market has passed")]
+    ClaimWindowClosed,
+    
+    #[error("The claim deadline of this market has not passed, and its escrow is all owed")]
+    ClaimWindowOpen,
+    
+    #[error("Invalid claim deadline: must be later than the current one")]
+    InvalidClaimDeadline,
+    
+    #[error("This operation is not supported by the market's pricing mechanism")]
+    UnsupportedMechanism,
+    
+    #[error("Invalid liquidity: must be greater than 0")]
+    InvalidLiquidity,
+    
+    #[error("Invalid recipient: a position cannot be transferred to its owner")]
+    InvalidRecipient,
+    
+    #[error("Invalid price: must be between 1 and 9999 basis points")]
+    InvalidPrice,
+    
+    #[error("Invalid order size: must be a positive number of whole lots")]
+    InvalidOrderSize,
+    
+    #[error("Order not found: {0}")]
+    OrderNotFound(String),
+    
+    #[error("Listing not found: {0}")]
+    ListingNotFound(String),
+    
+    #[error("Invalid odds: must be above 10000 basis points, or 0, for every outcome")]
+    InvalidOdds,
+    
+    #[error("No odds are quoted for outcome: {0}")]
+    OddsNotQuoted(String),
+    
+    #[error("Odds below the minimum: minimum {minimum}, actual {actual} basis points")]
+    OddsBelowMinimum { minimum: u32, actual: u32 },
+    
+    #[error("Bookmaker exposure beyond the limit: limit {limit}, actual {actual}")]
+    ExposureExceeded { limit: Amount, actual: Amount },
+    
+    #[error("Invalid bounds: the lower bound must be below the upper bound")]
+    InvalidBounds,
+    
+    #[error("Invalid buckets: cut points must increase and make at most 64 buckets")]
+    InvalidBuckets,
+    
+    #[error("Invalid fee: must be below 10000 basis points")]
+    InvalidFee,
+    
+    #[error("Invalid cash-out penalty: must be at most 10000 basis points")]
+    InvalidPenalty,
+    
+    #[error("Not enough LP shares: required {required}, available {available}")]
+    InsufficientLiquidity { required: Amount, available: Amount },
+    
+    #[error("Price moved beyond the limit: limit {limit}, actual {actual}")]
+    SlippageExceeded { limit: Amount, actual: Amount },
+    
+    #[error("Not enough shares: required {required}, available {available}")]
+    InsufficientShares { required: Amount, available: Amount },
+    
+    #[error("Market already exists: {0}")]
+    MarketAlreadyExists(String),
+    
+    #[error("Applications can only pay in fungible tokens; positions can be transferred to them")]
+    ApplicationPayment,
+    
+    #[error("Deposit too small: required {required}, deposited {deposited}")]
+    InsufficientDeposit { required: Amount, deposited: Amount },
+    
+    #[error("Invalid legacy market: {0}")]
+    InvalidImport(String),
+    
     #[error("View error: {0}")]
     ViewError(#[from] linera_views::views::ViewError),
     
@@ -50,3 +141,9 @@ pub enum CascadeProtocolError {
     
  
 }
+
+impl From<ArithmeticError> for CascadeProtocolError {
+    fn from(_: ArithmeticError) -> Self {
+        CascadeProtocolError::ArithmeticOverflow
+    }
+}
@@ -0,0 +1,288 @@
+use async_graphql::{Enum, InputObject, SimpleObject, Union};
+use linera_sdk::base::{AccountOwner, Amount, Owner};
+use serde::{Deserialize, Serialize};
+
+use crate::msg::MarketCategory;
+
+/// An entry of the append-only event log
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+#[graphql(name = "Event")]
+pub struct Event {
+    /// Position in the log, starting at 0 and increasing by one per event
+    pub seq: u64,
+    pub timestamp: u64,
+    pub payload: EventPayload,
+}
+
+/// A page of the event log
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+pub struct EventPage {
+    pub events: Vec<Event>,
+    /// The last sequence number scanned, to pass as `after` for the next
+    /// page. It may be past the last event returned when a filter skipped
+    /// the events that followed.
+    pub cursor: Option<u64>,
+    /// Whether the log holds events past the cursor
+    pub has_more: bool,
+}
+
+/// What happened, with the data an indexer needs to follow along
+#[derive(Debug, Clone, Serialize, Deserialize, Union)]
+pub enum EventPayload {
+    MarketCreated(MarketCreatedEvent),
+    BetPlaced(BetPlacedEvent),
+    MarketResolved(MarketResolvedEvent),
+    Claimed(ClaimedEvent),
+    Refunded(RefundedEvent),
+    CashedOut(CashedOutEvent),
+    Traded(TradedEvent),
+    Liquidity(LiquidityEvent),
+    CompleteSet(CompleteSetEvent),
+    PositionTransferred(PositionTransferredEvent),
+    Listing(ListingEvent),
+    AdminChanged(AdminChangedEvent),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+pub struct MarketCreatedEvent {
+    pub market_id: String,
+    pub question: String,
+    pub outcome_ids: Vec<String>,
+    pub category: MarketCategory,
+    pub parent_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+pub struct BetPlacedEvent {
+    pub bet_id: String,
+    pub market_id: String,
+    pub outcome_id: String,
+    pub owner: AccountOwner,
+    pub amount: Amount,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+pub struct MarketResolvedEvent {
+    pub market_id: String,
+    /// For scalar markets, the side that receives the larger part of the pool
+    pub winning_outcome_id: String,
+    /// The value reported for a scalar or bucketed market
+    pub value: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+pub struct ClaimedEvent {
+    pub bet_id: String,
+    pub market_id: String,
+    pub owner: AccountOwner,
+    pub amount: Amount,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+pub struct RefundedEvent {
+    pub bet_id: String,
+    pub market_id: String,
+    pub owner: AccountOwner,
+    pub amount: Amount,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+pub struct CashedOutEvent {
+    pub bet_id: String,
+    pub market_id: String,
+    pub outcome_id: String,
+    pub owner: AccountOwner,
+    /// The part of the stake cashed out
+    pub stake: Amount,
+    pub penalty: Amount,
+    pub payout: Amount,
+}
+
+/// Whether shares were bought from or sold to a market maker
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Enum)]
+pub enum TradeSide {
+    Buy,
+    Sell,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+pub struct TradedEvent {
+    pub market_id: String,
+    pub outcome_id: String,
+    pub owner: AccountOwner,
+    pub side: TradeSide,
+    pub shares: Amount,
+    /// What the buyer paid or the seller received
+    pub amount: Amount,
+}
+
+/// Whether liquidity was added to or removed from a pool
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Enum)]
+pub enum LiquidityChange {
+    Added,
+    Removed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+pub struct LiquidityEvent {
+    pub market_id: String,
+    pub owner: AccountOwner,
+    pub change: LiquidityChange,
+    pub lp_shares: Amount,
+    /// Collateral paid in, or paid out for the complete sets removed
+    pub amount: Amount,
+}
+
+/// Whether complete sets were minted or redeemed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Enum)]
+pub enum CompleteSetChange {
+    Minted,
+    Redeemed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+pub struct CompleteSetEvent {
+    pub market_id: String,
+    pub owner: AccountOwner,
+    pub change: CompleteSetChange,
+    /// Sets minted or redeemed, which is also the collateral locked or returned
+    pub amount: Amount,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+pub struct PositionTransferredEvent {
+    pub market_id: String,
+    pub outcome_id: String,
+    pub from: AccountOwner,
+    pub to: AccountOwner,
+    /// The stake or the shares transferred
+    pub amount: Amount,
+}
+
+/// Whether a listing was created, bought or taken off sale
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Enum)]
+pub enum ListingChange {
+    Created,
+    Filled,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+pub struct ListingEvent {
+    pub listing_id: String,
+    pub market_id: String,
+    pub outcome_id: String,
+    /// The seller, or the buyer of a filled listing
+    pub owner: AccountOwner,
+    pub change: ListingChange,
+    /// The stake or the shares listed
+    pub amount: Amount,
+    pub price: Amount,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+pub struct AdminChangedEvent {
+    /// None when the admin is first set at instantiation
+    pub previous_admin: Option<Owner>,
+    pub new_admin: Owner,
+}
+
+/// Event kinds, used for filtering
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Enum)]
+pub enum EventKind {
+    MarketCreated,
+    BetPlaced,
+    MarketResolved,
+    Claimed,
+    Refunded,
+    CashedOut,
+    Traded,
+    Liquidity,
+    CompleteSet,
+    PositionTransferred,
+    Listing,
+    AdminChanged,
+}
+
+impl EventPayload {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            EventPayload::MarketCreated(_) => EventKind::MarketCreated,
+            EventPayload::BetPlaced(_) => EventKind::BetPlaced,
+            EventPayload::MarketResolved(_) => EventKind::MarketResolved,
+            EventPayload::Claimed(_) => EventKind::Claimed,
+            EventPayload::Refunded(_) => EventKind::Refunded,
+            EventPayload::CashedOut(_) => EventKind::CashedOut,
+            EventPayload::Traded(_) => EventKind::Traded,
+            EventPayload::Liquidity(_) => EventKind::Liquidity,
+            EventPayload::CompleteSet(_) => EventKind::CompleteSet,
+            EventPayload::PositionTransferred(_) => EventKind::PositionTransferred,
+            EventPayload::Listing(_) => EventKind::Listing,
+            EventPayload::AdminChanged(_) => EventKind::AdminChanged,
+        }
+    }
+
+    /// The market this event is about, if any
+    pub fn market_id(&self) -> Option<&str> {
+        match self {
+            EventPayload::MarketCreated(event) => Some(&event.market_id),
+            EventPayload::BetPlaced(event) => Some(&event.market_id),
+            EventPayload::MarketResolved(event) => Some(&event.market_id),
+            EventPayload::Claimed(event) => Some(&event.market_id),
+            EventPayload::Refunded(event) => Some(&event.market_id),
+            EventPayload::CashedOut(event) => Some(&event.market_id),
+            EventPayload::Traded(event) => Some(&event.market_id),
+            EventPayload::Liquidity(event) => Some(&event.market_id),
+            EventPayload::CompleteSet(event) => Some(&event.market_id),
+            EventPayload::PositionTransferred(event) => Some(&event.market_id),
+            EventPayload::Listing(event) => Some(&event.market_id),
+            EventPayload::AdminChanged(_) => None,
+        }
+    }
+
+    /// The owner this event is about, if any
+    pub fn owner(&self) -> Option<AccountOwner> {
+        match self {
+            EventPayload::BetPlaced(event) => Some(event.owner),
+            EventPayload::Claimed(event) => Some(event.owner),
+            EventPayload::Refunded(event) => Some(event.owner),
+            EventPayload::CashedOut(event) => Some(event.owner),
+            EventPayload::Traded(event) => Some(event.owner),
+            EventPayload::Liquidity(event) => Some(event.owner),
+            EventPayload::CompleteSet(event) => Some(event.owner),
+            EventPayload::PositionTransferred(event) => Some(event.from),
+            EventPayload::Listing(event) => Some(event.owner),
+            EventPayload::AdminChanged(event) => Some(AccountOwner::User(event.new_admin)),
+            EventPayload::MarketCreated(_) | EventPayload::MarketResolved(_) => None,
+        }
+    }
+}
+
+/// Criteria an event must match to be returned; unset fields match everything
+#[derive(Debug, Clone, Default, InputObject)]
+pub struct EventFilter {
+    pub kinds: Option<Vec<EventKind>>,
+    pub market_id: Option<String>,
+    pub owner: Option<AccountOwner>,
+}
+
+impl EventFilter {
+    pub fn matches(&self, event: &Event) -> bool {
+        if let Some(kinds) = &self.kinds {
+            if !kinds.contains(&event.payload.kind()) {
+                return false;
+            }
+        }
+        if let Some(market_id) = &self.market_id {
+            if event.payload.market_id() != Some(market_id.as_str()) {
+                return false;
+            }
+        }
+        if let Some(owner) = &self.owner {
+            if event.payload.owner() != Some(*owner) {
+                return false;
+            }
+        }
+        true
+    }
+}
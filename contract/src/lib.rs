@@ -1,28 +1,55 @@
+mod amm;
+mod bookmaker;
+mod bucket;
 mod contract;
 mod error;
+mod event;
+mod math;
+mod migration;
 mod msg;
+mod orderbook;
+mod scalar;
 mod service;
 mod state;
+mod token;
 
+#[cfg(target_arch = "wasm32")]
 use linera_sdk::{Contract, Service};
 
 pub use contract::CascadeProtocolContract;
 pub use error::CascadeProtocolError;
-pub use msg::{InstantiationArgument, Message, Operation};
+pub use amm::{AmmState, CpmmPool, LmsrState};
+pub use bookmaker::FixedOddsBook;
+pub use bucket::{BucketSpec, Buckets};
+pub use event::{
+    AdminChangedEvent, BetPlacedEvent, CashedOutEvent, ClaimedEvent, CompleteSetChange,
+    CompleteSetEvent, Event, EventFilter, EventKind, EventPage, EventPayload, LiquidityChange,
+    LiquidityEvent, ListingChange, ListingEvent, MarketCreatedEvent, MarketResolvedEvent,
+    PositionTransferredEvent, RefundedEvent, TradeSide, TradedEvent,
+};
+pub use migration::{LegacyBet, LegacyMarket, LegacyOutcome};
+pub use orderbook::{Order, OrderBookDepth, OrderSide, PriceLevel};
+pub use scalar::ScalarRange;
+pub use msg::{
+    Collateral, InstantiationArgument, MarketMechanism, Message, Operation, OperationResponse,
+    Parameters, SubscriptionTarget,
+};
 pub use service::CascadeProtocolService;
 pub use state::CascadeProtocol;
 
 // Register the contract implementation
+#[cfg(target_arch = "wasm32")]
 linera_sdk::contract!(CascadeProtocolContract);
 
 // Register the service implementation
+#[cfg(target_arch = "wasm32")]
 linera_sdk::service!(CascadeProtocolService);
 
 pub struct CascadeProtocolAbi;
 
 impl linera_sdk::abi::ContractAbi for CascadeProtocolAbi {
     type Operation = msg::Operation;
-    type Response = msg::Message;
+    type Response = msg::OperationResponse;
 }
 
 impl linera_sdk::abi::ServiceAbi for CascadeProtocolAbi {
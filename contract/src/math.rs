@@ -0,0 +1,164 @@
+use linera_sdk::base::Amount;
+
+/// `amount * numerator / denominator`, rounded down to the atto. The product
+/// is computed on 256 bits, so only a quotient that does not fit in an
+/// `Amount`, or a zero denominator, fails.
+pub fn mul_div(amount: Amount, numerator: Amount, denominator: Amount) -> Option<Amount> {
+    mul_div_u128(
+        u128::from(amount),
+        u128::from(numerator),
+        u128::from(denominator),
+    )
+    .map(Amount::from_attos)
+}
+
+/// One in the 18-decimal fixed-point format used below, which is also the
+/// number of attos in a token
+pub const ONE: u128 = 1_000_000_000_000_000_000;
+
+/// ln(2) in fixed point
+const LN_2: u128 = 693_147_180_559_945_309;
+
+/// e^(-x) in fixed point, for a fixed-point `x`, rounded down
+pub fn exp_neg(x: u128) -> u128 {
+    // e^(-x) = e^(-r) / 2^k, with x = k ln(2) + r and 0 <= r < ln(2)
+    let k = x / LN_2;
+    if k >= 64 {
+        return 0;
+    }
+    let r = x % LN_2;
+
+    // e^r by its Taylor series, whose terms fall quickly below an atto
+    let mut term = ONE;
+    let mut sum = ONE;
+    for n in 1..40 {
+        term = term * r / (n * ONE);
+        if term == 0 {
+            break;
+        }
+        sum += term;
+    }
+    (ONE * ONE / sum) >> k
+}
+
+/// ln(y) in fixed point, for a fixed-point `y` of at least one, rounded down
+pub fn ln(y: u128) -> Option<u128> {
+    if y < ONE {
+        return None;
+    }
+    // ln(y) = k ln(2) + ln(m), with y = 2^k m and 1 <= m < 2
+    let k = (y / ONE).ilog2();
+    let m = mul_div_u128(y, 1, 1 << k)?;
+
+    // ln(m) = 2 atanh(z) = 2 (z + z^3/3 + z^5/5 + ...), with z < 1/3
+    let z = mul_div_u128(m - ONE, ONE, m + ONE)?;
+    let z_squared = z * z / ONE;
+    let mut power = z;
+    let mut sum = 0;
+    let mut n = 1;
+    while power > 0 {
+        sum += power / n;
+        power = power * z_squared / ONE;
+        n += 2;
+    }
+    Some(u128::from(k) * LN_2 + 2 * sum)
+}
+
+/// `a * b / c` rounded down, without intermediate overflow
+pub fn mul_div_u128(a: u128, b: u128, c: u128) -> Option<u128> {
+    if c == 0 {
+        return None;
+    }
+    let (high, low) = widening_mul(a, b);
+    if high >= c {
+        // The quotient would not fit in 128 bits
+        return None;
+    }
+
+    // Long division of the 256-bit product, one bit at a time
+    let mut remainder = high;
+    let mut quotient = 0u128;
+    for bit in (0..128).rev() {
+        let carry = remainder >> 127;
+        remainder = (remainder << 1) | ((low >> bit) & 1);
+        quotient <<= 1;
+        if carry == 1 || remainder >= c {
+            remainder = remainder.wrapping_sub(c);
+            quotient |= 1;
+        }
+    }
+    Some(quotient)
+}
+
+/// `a * b / c` rounded up, without intermediate overflow
+pub fn mul_div_ceil_u128(a: u128, b: u128, c: u128) -> Option<u128> {
+    let quotient = mul_div_u128(a, b, c)?;
+    let (high, low) = widening_mul(quotient, c);
+    let (a_b_high, a_b_low) = widening_mul(a, b);
+    if (high, low) == (a_b_high, a_b_low) {
+        Some(quotient)
+    } else {
+        quotient.checked_add(1)
+    }
+}
+
+/// The full 256-bit product of two `u128`, as (high, low) halves
+fn widening_mul(a: u128, b: u128) -> (u128, u128) {
+    const MASK: u128 = u64::MAX as u128;
+    let (a_high, a_low) = (a >> 64, a & MASK);
+    let (b_high, b_low) = (b >> 64, b & MASK);
+
+    let low_low = a_low * b_low;
+    let high_low = a_high * b_low;
+    let low_high = a_low * b_high;
+    let high_high = a_high * b_high;
+
+    let middle = (low_low >> 64) + (high_low & MASK) + (low_high & MASK);
+    let low = (middle << 64) | (low_low & MASK);
+    let high = high_high + (high_low >> 64) + (low_high >> 64) + (middle >> 64);
+    (high, low)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mul_div_small_values() {
+        assert_eq!(mul_div_u128(10, 3, 4), Some(7));
+        assert_eq!(mul_div_u128(0, 3, 4), Some(0));
+        assert_eq!(mul_div_u128(1, 1, 0), None);
+    }
+
+    #[test]
+    fn test_mul_div_without_intermediate_overflow() {
+        // Both factors are well beyond what a u128 product can hold
+        let a = u128::MAX / 3;
+        assert_eq!(mul_div_u128(a, 6, 6), Some(a));
+        assert_eq!(mul_div_u128(u128::MAX, u128::MAX, u128::MAX), Some(u128::MAX));
+        assert_eq!(mul_div_u128(u128::MAX, 2, 3), Some(u128::MAX / 3 * 2));
+    }
+
+    #[test]
+    fn test_mul_div_quotient_overflow() {
+        assert_eq!(mul_div_u128(u128::MAX, 2, 1), None);
+    }
+
+    #[test]
+    fn test_exp_and_ln() {
+        let close = |actual: u128, expected: f64| {
+            (actual as f64 / ONE as f64 - expected).abs() < 1e-12
+        };
+        assert_eq!(exp_neg(0), ONE);
+        assert!(close(exp_neg(ONE), (-1.0f64).exp()));
+        assert!(close(exp_neg(ONE / 3), (-1.0f64 / 3.0).exp()));
+        assert!(close(exp_neg(25 * ONE), (-25.0f64).exp()));
+        assert_eq!(exp_neg(100 * ONE), 0);
+
+        assert_eq!(ln(ONE), Some(0));
+        assert_eq!(ln(ONE - 1), None);
+        assert!(close(ln(2 * ONE).unwrap(), 2.0f64.ln()));
+        assert!(close(ln(3 * ONE).unwrap(), 3.0f64.ln()));
+        assert!(close(ln(1_000 * ONE + 7).unwrap(), 1000.0f64.ln()));
+    }
+}
@@ -0,0 +1,142 @@
+use linera_sdk::base::{AccountOwner, Amount, Owner};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::CascadeProtocolError,
+    msg::{Collateral, MarketCategory},
+    state::{Bet, DustPolicy, Market, MarketStatus, Outcome},
+};
+
+// Layouts of the first deployment, where stakes were whole native tokens
+// stored as `u64`. Converting multiplies by 10^18 attos per token, so no
+// precision is lost and no dust is created.
+
+/// A market as stored before fractional amounts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LegacyMarket {
+    pub id: String,
+    pub question: String,
+    pub outcomes: Vec<LegacyOutcome>,
+    pub total_staked: u64,
+    pub status: MarketStatus,
+    pub expiry_time: u64,
+    pub winning_outcome_id: Option<String>,
+    pub parent_id: Option<String>,
+    pub category: MarketCategory,
+}
+
+/// An outcome as stored before fractional amounts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LegacyOutcome {
+    pub id: String,
+    pub name: String,
+    pub total_staked: u64,
+}
+
+/// A bet as stored before fractional amounts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LegacyBet {
+    pub id: String,
+    pub owner: Owner,
+    pub market_id: String,
+    pub outcome_id: String,
+    pub amount: u64,
+    pub claimed: bool,
+}
+
+impl From<LegacyMarket> for Market {
+    fn from(market: LegacyMarket) -> Self {
+        Market {
+            id: market.id,
+            question: market.question,
+            outcomes: market.outcomes.into_iter().map(Outcome::from).collect(),
+            total_staked: Amount::from_tokens(market.total_staked.into()),
+            status: market.status,
+            expiry_time: market.expiry_time,
+            winning_outcome_id: market.winning_outcome_id,
+            parent_id: market.parent_id,
+            category: market.category,
+            // Legacy markets could only hold native tokens
+            collateral: Collateral::Native,
+            claim_window: None,
+            claim_deadline: None,
+            amm: None,
+            order_book: false,
+            fixed_odds: None,
+            scalar: None,
+            buckets: None,
+            dust_policy: DustPolicy::LastClaimer,
+        }
+    }
+}
+
+impl From<LegacyOutcome> for Outcome {
+    fn from(outcome: LegacyOutcome) -> Self {
+        Outcome {
+            id: outcome.id,
+            name: outcome.name,
+            total_staked: Amount::from_tokens(outcome.total_staked.into()),
+        }
+    }
+}
+
+impl From<LegacyBet> for Bet {
+    fn from(bet: LegacyBet) -> Self {
+        Bet {
+            id: bet.id,
+            owner: AccountOwner::User(bet.owner),
+            market_id: bet.market_id,
+            outcome_id: bet.outcome_id,
+            amount: Amount::from_tokens(bet.amount.into()),
+            claimed: bet.claimed,
+            // Legacy bets were all parimutuel
+            odds_bps: None,
+        }
+    }
+}
+
+/// Check that an imported market and its bets are consistent: every bet is
+/// an unclaimed stake on one of the market's outcomes, the bets on each
+/// outcome add up to its stake, and the outcomes add up to the market's.
+pub fn validate(market: &Market, bets: &[Bet]) -> Result<(), CascadeProtocolError> {
+    let invalid = |reason: String| Err(CascadeProtocolError::InvalidImport(reason));
+    let mut outcome_totals = vec![Amount::ZERO; market.outcomes.len()];
+    for (index, bet) in bets.iter().enumerate() {
+        if bet.market_id != market.id {
+            return invalid(format!("bet {} belongs to market {}", bet.id, bet.market_id));
+        }
+        if bet.claimed {
+            return invalid(format!("bet {} is already claimed", bet.id));
+        }
+        if bets[..index].iter().any(|other| other.id == bet.id) {
+            return invalid(format!("bet {} appears twice", bet.id));
+        }
+        let Some(outcome) = market.outcomes.iter().position(|o| o.id == bet.outcome_id) else {
+            return invalid(format!("bet {} is on unknown outcome {}", bet.id, bet.outcome_id));
+        };
+        outcome_totals[outcome].try_add_assign(bet.amount)?;
+    }
+
+    let mut total = Amount::ZERO;
+    for (outcome, bet_total) in market.outcomes.iter().zip(outcome_totals) {
+        if outcome.total_staked != bet_total {
+            return invalid(format!(
+                "outcome {} has a stake of {} but bets of {}",
+                outcome.id, outcome.total_staked, bet_total
+            ));
+        }
+        total.try_add_assign(outcome.total_staked)?;
+    }
+    if market.total_staked != total {
+        return invalid(format!(
+            "market has a stake of {} but outcomes of {}",
+            market.total_staked, total
+        ));
+    }
+    Ok(())
+}
+
+/// The numeric part of an ID generated by `CascadeProtocol::generate_id`
+pub fn id_number(id: &str) -> Option<u64> {
+    id.strip_prefix("id_")?.parse().ok()
+}
@@ -1,7 +1,76 @@
-use linera_sdk::base::Owner;
+use linera_sdk::base::{AccountOwner, Amount, ApplicationId, ChainId, Owner};
 use async_graphql::Enum;
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    amm::BASIS_POINTS,
+    bucket::BucketSpec,
+    error::CascadeProtocolError,
+    math::mul_div,
+    migration::{LegacyBet, LegacyMarket},
+    orderbook::OrderSide,
+    state::{Bet, DustPolicy, Market, Rejection},
+};
+
+/// The asset the stakes of a market are denominated in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Collateral {
+    /// The native token of the chains
+    Native,
+    /// A fungible token application
+    Token(ApplicationId),
+}
+
+async_graphql::scalar!(Collateral);
+
+/// Application parameters, fixed when the application is created
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Parameters {
+    /// Collateral of markets that do not choose one
+    pub default_collateral: Collateral,
+    /// Token applications markets may use as collateral
+    pub allowed_tokens: Vec<ApplicationId>,
+    /// Owner of the treasury, paid on the application chain. Unset means the
+    /// admin.
+    #[serde(default)]
+    pub treasury: Option<AccountOwner>,
+    /// How long winners have to claim after resolution, in microseconds, for
+    /// markets that do not choose. Unset means forever.
+    pub claim_window: Option<u64>,
+    /// Part of a stake cashed out before resolution that stays in the pool,
+    /// in basis points. Unset means no penalty.
+    #[serde(default)]
+    pub cash_out_penalty_bps: u16,
+}
+
+impl Parameters {
+    /// Check the parameters the application was created with
+    pub fn validate(&self) -> Result<(), CascadeProtocolError> {
+        if self.cash_out_penalty_bps > BASIS_POINTS {
+            return Err(CascadeProtocolError::InvalidPenalty);
+        }
+        Ok(())
+    }
+
+    /// Whether markets may be denominated in the given collateral
+    pub fn allows(&self, collateral: Collateral) -> bool {
+        match collateral {
+            Collateral::Native => true,
+            Collateral::Token(token) => self.allowed_tokens.contains(&token),
+        }
+    }
+
+    /// The penalty for cashing out `stake`: `stake * cash_out_penalty_bps /
+    /// 10000`, rounded down
+    pub fn cash_out_penalty(&self, stake: Amount) -> Option<Amount> {
+        mul_div(
+            stake,
+            Amount::from_attos(self.cash_out_penalty_bps.into()),
+            Amount::from_attos(BASIS_POINTS.into()),
+        )
+    }
+}
+
 /// Initialization argument for the application
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InstantiationArgument {
@@ -9,6 +78,35 @@ pub struct InstantiationArgument {
     pub admin: Owner,
 }
 
+/// How a market prices its outcomes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MarketMechanism {
+    /// Stakes are pooled and split between the winners
+    Parimutuel,
+    /// Shares are bought from and sold to a market maker following the
+    /// logarithmic market scoring rule with liquidity `b`. The creator funds
+    /// its subsidy of `b ln(n)`.
+    Lmsr { liquidity: Amount },
+    /// Shares are traded with a constant-product pool, funded by the creator
+    /// with `liquidity` and then by any liquidity provider, who earn a fee of
+    /// `fee_bps` basis points on every trade
+    Cpmm { liquidity: Amount, fee_bps: u16 },
+    /// Shares are traded between users through a limit order book. In binary
+    /// markets, crossing bids or asks of both outcomes mint or merge complete
+    /// sets.
+    OrderBook,
+    /// Bets are taken at fixed odds quoted by the creator, who funds a
+    /// `reserve` for the payouts. Bets that would make the creator lose more
+    /// than `max_exposure`, or more than the reserve, on any outcome are
+    /// rejected.
+    FixedOdds { reserve: Amount, max_exposure: Amount },
+    /// Stakes go long or short on a numeric value between `lower` and
+    /// `upper`. The outcome names given are replaced by "Long" and "Short",
+    /// and the pool is split linearly between both sides by the value
+    /// reported on resolution.
+    Scalar { lower: i64, upper: i64 },
+}
+
 /// Market category classification
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Enum)]
 pub enum MarketCategory {
@@ -20,6 +118,15 @@ pub enum MarketCategory {
     Other,
 }
 
+/// What a chain can subscribe to in order to receive market updates
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SubscriptionTarget {
+    /// A single market
+    Market(String),
+    /// Every market of a category, including ones created later
+    Category(MarketCategory),
+}
+
 /// Operations that can be performed on the contract (Write operations)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Operation {
@@ -31,13 +138,138 @@ pub enum Operation {
         category: MarketCategory,
         /// Optional parent market ID for cascading markets
         parent_id: Option<String>,
+        /// Host the market on a new microchain. Child markets always live on
+        /// the chain of their parent.
+        #[serde(default)]
+        dedicated_chain: bool,
+        /// Collateral of the market, or the default one from the parameters
+        collateral: Option<Collateral>,
+        /// Claim window of the market, or the default one from the parameters
+        claim_window: Option<u64>,
+        /// How the market prices its outcomes
+        mechanism: MarketMechanism,
+        /// Generate the outcomes from buckets of a numeric range instead of
+        /// `outcome_names`, and resolve the market with a value
+        #[serde(default)]
+        buckets: Option<BucketSpec>,
+        /// Who receives the dust of the pool when it is split between winners
+        #[serde(default)]
+        dust_policy: DustPolicy,
     },
     
     /// Place a bet on a specific outcome
     PlaceBet {
         market_id: String,
         outcome_id: String,
-        amount: u64,
+        amount: Amount,
+        /// Reject the bet if the fixed odds of the outcome have fallen below
+        /// this, in basis points
+        #[serde(default)]
+        min_odds_bps: Option<u32>,
+    },
+    
+    /// Buy shares of an outcome from a market's market maker, paying at most
+    /// `max_cost`
+    BuyShares {
+        market_id: String,
+        outcome_id: String,
+        shares: Amount,
+        max_cost: Amount,
+    },
+    
+    /// Sell shares of an outcome back to a market's market maker, for at
+    /// least `min_proceeds`
+    SellShares {
+        market_id: String,
+        outcome_id: String,
+        shares: Amount,
+        min_proceeds: Amount,
+    },
+    
+    /// Give `amount` of a position on an outcome to another owner: stakes of
+    /// bets in parimutuel markets, shares otherwise. With `to_chain`, the
+    /// recipient's chain is told about the bets they received, and becomes
+    /// the one their payouts go to unless they already have one.
+    TransferPosition {
+        market_id: String,
+        outcome_id: String,
+        amount: Amount,
+        to: Owner,
+        to_chain: Option<ChainId>,
+    },
+    
+    /// Offer `amount` of a position on an outcome for sale at a total
+    /// `price`. The position is held by the application until the listing is
+    /// filled or cancelled.
+    ListPosition {
+        market_id: String,
+        outcome_id: String,
+        amount: Amount,
+        price: Amount,
+    },
+    
+    /// Buy a listed position, paying at most `max_price` to its seller
+    FillListing {
+        market_id: String,
+        listing_id: String,
+        max_price: Amount,
+    },
+    
+    /// Take a listing of the caller off sale, returning the position
+    CancelListing {
+        market_id: String,
+        listing_id: String,
+    },
+    
+    /// Lock `amount` of collateral in a market that trades shares, for
+    /// `amount` shares of every outcome
+    MintCompleteSet {
+        market_id: String,
+        amount: Amount,
+    },
+    
+    /// Burn `amount` shares of every outcome of a market for `amount` of
+    /// collateral
+    RedeemCompleteSet {
+        market_id: String,
+        amount: Amount,
+    },
+    
+    /// Deposit collateral into a market's constant-product pool for LP shares
+    AddLiquidity {
+        market_id: String,
+        amount: Amount,
+    },
+    
+    /// Burn LP shares for a part of a market's constant-product pool
+    RemoveLiquidity {
+        market_id: String,
+        lp_shares: Amount,
+    },
+    
+    /// Post a limit order for shares of an outcome on a market's order book,
+    /// at a price in basis points of a token per share. Buy orders lock their
+    /// cost, sell orders the shares.
+    PlaceLimitOrder {
+        market_id: String,
+        outcome_id: String,
+        side: OrderSide,
+        price_bps: u16,
+        shares: Amount,
+    },
+    
+    /// Cancel a resting order, unlocking what it holds
+    CancelOrder {
+        market_id: String,
+        order_id: String,
+    },
+    
+    /// Quote new odds for the outcomes of a fixed-odds market, in basis
+    /// points, where zero takes no bets. Bets placed earlier keep their odds.
+    /// (Bookmaker only)
+    SetOdds {
+        market_id: String,
+        odds_bps: Vec<u32>,
     },
     
     /// Resolve a market with the winning outcome (Admin only)
@@ -46,18 +278,464 @@ pub enum Operation {
         winning_outcome_id: String,
     },
     
+    /// Resolve a scalar or bucketed market with the value it was about
+    /// (Admin only)
+    ResolveScalarMarket {
+        market_id: String,
+        value: i64,
+    },
+    
     /// Claim winnings from a resolved market
     ClaimWinnings {
         market_id: String,
     },
+    
+    /// Take `amount` of a bet's stake back before resolution, minus a
+    /// penalty that stays in the pool
+    CashOut {
+        market_id: String,
+        bet_id: String,
+        amount: Amount,
+    },
+    
+    /// Cancel an active market so that every bet or share position can be
+    /// refunded (Admin only)
+    CancelMarket {
+        market_id: String,
+    },
+    
+    /// Move what winners of a resolved market left unclaimed to the treasury,
+    /// once its claim deadline has passed. Before then, or without a
+    /// deadline, only what is owed to nobody is moved.
+    SweepUnclaimed {
+        market_id: String,
+    },
+    
+    /// Push back the claim deadline of a resolved market (Admin only)
+    ExtendClaimDeadline {
+        market_id: String,
+        claim_deadline: u64,
+    },
+    
+    /// Pay the winning bets of a resolved market to their owners, walking at
+    /// most `batch_size` bets from where the previous call stopped. Anyone
+    /// can call it, as many times as the market needs.
+    DistributeWinnings {
+        market_id: String,
+        batch_size: u32,
+    },
+    
+    /// Subscribe the current chain to updates about a market or category
+    Subscribe {
+        target: SubscriptionTarget,
+    },
+    
+    /// Stop receiving updates about a market or category
+    Unsubscribe {
+        target: SubscriptionTarget,
+    },
+    
+    /// Hand the admin role over to another owner (Admin only)
+    TransferAdmin {
+        new_admin: Owner,
+    },
+    
+    /// Import an active market and its bets from a deployment that predates
+    /// fractional amounts (Admin only)
+    ImportLegacyMarket {
+        market: LegacyMarket,
+        bets: Vec<LegacyBet>,
+    },
+    
+    /// Allow an application to create markets (Admin only)
+    AllowMarketCreator {
+        application_id: ApplicationId,
+    },
+    
+    /// Revoke an application's permission to create markets (Admin only)
+    RevokeMarketCreator {
+        application_id: ApplicationId,
+    },
+}
+
+/// Result of an operation, returned to the caller
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OperationResponse {
+    /// The operation succeeded and has nothing to report
+    Ok,
+    
+    /// A market was created
+    MarketCreated {
+        id: String,
+        outcome_ids: Vec<String>,
+    },
+    
+    /// A bet was recorded
+    BetPlaced {
+        bet_id: String,
+        /// The caller's total stake on the outcome, including this bet
+        position: Amount,
+    },
+    
+    /// Shares were bought or sold
+    Traded {
+        /// The caller's shares of the outcome after the trade
+        shares: Amount,
+        /// What the caller paid or received
+        amount: Amount,
+    },
+    
+    /// A limit order was matched, and what is left of it rests on the book
+    OrderPlaced {
+        order_id: String,
+        filled: Amount,
+        remaining: Amount,
+    },
+    
+    /// Part of a position was transferred
+    PositionTransferred {
+        /// The caller's remaining position on the outcome
+        position: Amount,
+    },
+    
+    /// A position was listed for sale
+    Listed {
+        listing_id: String,
+    },
+    
+    /// A listing was bought
+    ListingFilled {
+        /// What the caller paid the seller
+        price: Amount,
+    },
+    
+    /// Complete sets were minted or redeemed
+    CompleteSets {
+        /// The caller's shares of each outcome after the change
+        shares: Vec<Amount>,
+    },
+    
+    /// Liquidity was added or removed
+    Liquidity {
+        /// The caller's LP shares after the change
+        lp_shares: Amount,
+        /// What the caller paid or received
+        amount: Amount,
+    },
+    
+    /// Winnings or refunds were paid to the caller
+    Claimed {
+        bet_ids: Vec<String>,
+        amount: Amount,
+    },
+    
+    /// Part of a bet was cashed out
+    CashedOut {
+        /// What was paid to the caller
+        payout: Amount,
+        /// What stayed in the pool
+        penalty: Amount,
+    },
+    
+    /// Unclaimed winnings were moved to the treasury
+    Swept {
+        amount: Amount,
+    },
+    
+    /// A batch of winning bets was paid out
+    Distributed {
+        bet_ids: Vec<String>,
+        amount: Amount,
+        /// Bets of the market that are still to be walked
+        remaining: u64,
+    },
+    
+    /// The request was sent to another chain; its result arrives as a message
+    Forwarded {
+        chain_id: ChainId,
+    },
 }
 
 /// Cross-chain messages for inter-application communication
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Message {
+    /// A bet placed from a user's chain. The stake is transferred to the
+    /// application chain alongside this message.
+    PlaceBet {
+        owner: AccountOwner,
+        market_id: String,
+        outcome_id: String,
+        amount: Amount,
+        #[serde(default)]
+        min_odds_bps: Option<u32>,
+        /// The asset the stake was sent in, checked against the market's
+        collateral: Collateral,
+        /// The chain to send the result and any refund to
+        reply_to: ChainId,
+    },
+    
+    /// A purchase of shares from a user's chain. `max_cost` is transferred to
+    /// the application chain alongside this message, and what is not spent
+    /// is returned.
+    BuyShares {
+        owner: AccountOwner,
+        market_id: String,
+        outcome_id: String,
+        shares: Amount,
+        max_cost: Amount,
+        collateral: Collateral,
+        reply_to: ChainId,
+    },
+    
+    /// A sale of shares from a user's chain, paid to `reply_to`
+    SellShares {
+        owner: AccountOwner,
+        market_id: String,
+        outcome_id: String,
+        shares: Amount,
+        min_proceeds: Amount,
+        reply_to: ChainId,
+    },
+    
+    /// A limit order from a user's chain, with the cost of a buy order
+    /// transferred alongside
+    PlaceLimitOrder {
+        owner: AccountOwner,
+        market_id: String,
+        outcome_id: String,
+        side: OrderSide,
+        price_bps: u16,
+        shares: Amount,
+        collateral: Collateral,
+        reply_to: ChainId,
+    },
+    
+    /// A cancellation of an order from a user's chain
+    CancelOrder {
+        owner: AccountOwner,
+        market_id: String,
+        order_id: String,
+        reply_to: ChainId,
+    },
+    
+    /// A transfer of a position made from a user's chain
+    TransferPosition {
+        owner: AccountOwner,
+        market_id: String,
+        outcome_id: String,
+        amount: Amount,
+        to: AccountOwner,
+        to_chain: Option<ChainId>,
+        reply_to: ChainId,
+    },
+    
+    /// A listing of a position made from a user's chain
+    ListPosition {
+        owner: AccountOwner,
+        market_id: String,
+        outcome_id: String,
+        amount: Amount,
+        price: Amount,
+        reply_to: ChainId,
+    },
+    
+    /// A purchase of a listing from a user's chain. `max_price` is
+    /// transferred to the application chain alongside this message, and what
+    /// is not paid to the seller is returned.
+    FillListing {
+        owner: AccountOwner,
+        market_id: String,
+        listing_id: String,
+        max_price: Amount,
+        collateral: Collateral,
+        reply_to: ChainId,
+    },
+    
+    /// A cancellation of a listing from a user's chain
+    CancelListing {
+        owner: AccountOwner,
+        market_id: String,
+        listing_id: String,
+        reply_to: ChainId,
+    },
+    
+    /// A mint of complete sets from a user's chain, with the collateral
+    /// transferred alongside
+    MintCompleteSet {
+        owner: AccountOwner,
+        market_id: String,
+        amount: Amount,
+        collateral: Collateral,
+        reply_to: ChainId,
+    },
+    
+    /// A redemption of complete sets from a user's chain, paid to `reply_to`
+    RedeemCompleteSet {
+        owner: AccountOwner,
+        market_id: String,
+        amount: Amount,
+        reply_to: ChainId,
+    },
+    
+    /// A deposit of liquidity from a user's chain, transferred alongside
+    AddLiquidity {
+        owner: AccountOwner,
+        market_id: String,
+        amount: Amount,
+        collateral: Collateral,
+        reply_to: ChainId,
+    },
+    
+    /// A withdrawal of liquidity from a user's chain, paid to `reply_to`
+    RemoveLiquidity {
+        owner: AccountOwner,
+        market_id: String,
+        lp_shares: Amount,
+        reply_to: ChainId,
+    },
+    
+    /// New odds quoted from the bookmaker's chain
+    SetOdds {
+        owner: AccountOwner,
+        market_id: String,
+        odds_bps: Vec<u32>,
+        reply_to: ChainId,
+    },
+    
+    /// A claim of winnings or refunds made from a user's chain
+    ClaimWinnings {
+        owner: AccountOwner,
+        market_id: String,
+        /// The chain to send the result and the payout to
+        reply_to: ChainId,
+    },
+    
+    /// A cash-out made from a user's chain
+    CashOut {
+        owner: AccountOwner,
+        market_id: String,
+        bet_id: String,
+        amount: Amount,
+        /// The chain to send the result and the payout to
+        reply_to: ChainId,
+    },
+    
+    /// Sent by the application chain to a chain it opened, which becomes the
+    /// host of the market
+    HostMarket {
+        market: Market,
+    },
+    
+    /// Resolution of a market, forwarded to the chain hosting it
+    ResolveMarket {
+        market_id: String,
+        winning_outcome_id: String,
+    },
+    
+    /// Resolution of a scalar or bucketed market with the value it was
+    /// about, forwarded to the chain hosting it
+    ResolveScalarMarket {
+        market_id: String,
+        value: i64,
+    },
+    
+    /// Cancellation of a market, forwarded to the chain hosting it with the
+    /// treasury paid any cash-out penalties left over
+    CancelMarket {
+        market_id: String,
+        treasury: AccountOwner,
+    },
+    
+    /// A sweep of unclaimed winnings, sent to the application chain, which
+    /// forwards it to the chain hosting the market with the treasury to pay
+    SweepUnclaimed {
+        market_id: String,
+        #[serde(default)]
+        treasury: Option<AccountOwner>,
+    },
+    
+    /// A new claim deadline, forwarded to the chain hosting the market
+    ExtendClaimDeadline {
+        market_id: String,
+        claim_deadline: u64,
+    },
+    
+    /// Notify other chains about a new claim deadline
+    ClaimDeadlineExtended {
+        market_id: String,
+        claim_deadline: u64,
+    },
+    
+    /// Notify other chains about new odds of a fixed-odds market
+    OddsUpdated {
+        market_id: String,
+        odds_bps: Vec<u32>,
+    },
+    
+    /// A distribution of winnings, forwarded to the chain hosting the market
+    DistributeWinnings {
+        market_id: String,
+        batch_size: u32,
+    },
+    
+    /// A bet sent from a user's chain was recorded
+    BetAccepted {
+        bet: Bet,
+    },
+    
+    /// The bets of an owner in a market changed on the chain hosting it, e.g.
+    /// after a transfer
+    BetsUpdated {
+        owner: AccountOwner,
+        market_id: String,
+        bets: Vec<Bet>,
+    },
+    
+    /// A bet was cashed out, and the payout sent to the receiving chain
+    CashedOut {
+        bet: Bet,
+    },
+    
+    /// Winnings or refunds were paid to the owner's account on the receiving chain
+    Claimed {
+        owner: AccountOwner,
+        market_id: String,
+        bet_ids: Vec<String>,
+        amount: Amount,
+    },
+    
+    /// A bet or claim sent from a user's chain failed; any stake was refunded
+    Rejected {
+        owner: AccountOwner,
+        rejection: Rejection,
+    },
+    
     /// Notify other chains about market resolution
     MarketResolved {
         market_id: String,
         winning_outcome_id: String,
+        value: Option<i64>,
+        claim_deadline: Option<u64>,
+    },
+    
+    /// Notify other chains about a new market. Also sent to a chain that
+    /// subscribes to an existing market, to seed its read-model.
+    MarketCreated {
+        market: Market,
+    },
+    
+    /// Notify other chains that a market was cancelled
+    MarketCancelled {
+        market_id: String,
+    },
+    
+    /// Ask the application chain to send updates about a target to the sender
+    Subscribe {
+        target: SubscriptionTarget,
+    },
+    
+    /// Ask the application chain to stop sending updates about a target
+    Unsubscribe {
+        target: SubscriptionTarget,
     },
 }
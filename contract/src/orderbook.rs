@@ -0,0 +1,354 @@
+use async_graphql::{Enum, SimpleObject};
+use linera_sdk::base::{AccountOwner, Amount};
+use serde::{Deserialize, Serialize};
+
+use crate::amm::BASIS_POINTS;
+
+/// Prices are quoted in basis points of a token per share, so order sizes
+/// are whole lots of this many attos, and every fill costs an exact amount
+pub const LOT: u128 = BASIS_POINTS as u128;
+
+/// Which side of the book an order is on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Enum)]
+pub enum OrderSide {
+    Buy,
+    Sell,
+}
+
+impl OrderSide {
+    pub fn opposite(self) -> Self {
+        match self {
+            OrderSide::Buy => OrderSide::Sell,
+            OrderSide::Sell => OrderSide::Buy,
+        }
+    }
+}
+
+/// A resting limit order for shares of an outcome. Buy orders lock the cost
+/// of their remaining shares, sell orders lock the remaining shares.
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+pub struct Order {
+    pub id: String,
+    pub owner: AccountOwner,
+    pub market_id: String,
+    pub outcome_id: String,
+    pub side: OrderSide,
+    /// Limit price per share, in basis points of a token
+    pub price_bps: u16,
+    pub shares: Amount,
+    /// Shares not filled yet
+    pub remaining: Amount,
+    pub created_at: u64,
+}
+
+impl Order {
+    /// Collateral locked by the order: the cost of its remaining shares for
+    /// a buy order, nothing for a sell order
+    pub fn locked(&self) -> Option<Amount> {
+        match self.side {
+            OrderSide::Buy => lot_cost(self.remaining, self.price_bps),
+            OrderSide::Sell => Some(Amount::ZERO),
+        }
+    }
+}
+
+/// One side of a market's order book
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BookSide {
+    pub market_id: String,
+    pub side: OrderSide,
+}
+
+impl BookSide {
+    pub fn of(order: &Order) -> Self {
+        BookSide {
+            market_id: order.market_id.clone(),
+            side: order.side,
+        }
+    }
+}
+
+/// Where a resting order is kept on its side of the book. Keys serialize
+/// best price first, then oldest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OrderKey {
+    /// How far the price is from the best possible one, as big-endian bytes
+    priority: [u8; 2],
+    /// The order's number on this chain, as big-endian bytes
+    seq: [u8; 8],
+}
+
+impl OrderKey {
+    pub fn new(side: OrderSide, price_bps: u16, seq: u64) -> Self {
+        // The best bid is the highest, the best ask the lowest
+        let priority = match side {
+            OrderSide::Buy => BASIS_POINTS - price_bps,
+            OrderSide::Sell => price_bps,
+        };
+        OrderKey {
+            priority: priority.to_be_bytes(),
+            seq: seq.to_be_bytes(),
+        }
+    }
+
+    pub fn priority(&self) -> u16 {
+        u16::from_be_bytes(self.priority)
+    }
+
+    pub fn seq(&self) -> u64 {
+        u64::from_be_bytes(self.seq)
+    }
+}
+
+/// The highest priority of a resting order that can cross with `taker`.
+/// This bound holds on both sides of the book: the opposite side, for the
+/// same outcome, and the same side, for the other outcome of a binary
+/// market.
+pub fn crossing_priority(taker: &Order) -> u16 {
+    match taker.side {
+        OrderSide::Buy => taker.price_bps,
+        OrderSide::Sell => BASIS_POINTS - taker.price_bps,
+    }
+}
+
+/// How a fill settles
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FillKind {
+    /// A buyer pays a seller for shares of the same outcome
+    Transfer,
+    /// Buyers of both outcomes of a binary market pay one token per share
+    /// between them, minting a complete set
+    Mint,
+    /// Sellers of both outcomes of a binary market burn a complete set and
+    /// split its token
+    Merge,
+}
+
+/// A match between an incoming order and a resting one, at the resting
+/// order's price
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fill {
+    /// Index of the resting order in the book
+    pub maker: usize,
+    pub shares: Amount,
+    pub kind: FillKind,
+}
+
+/// The cost of `shares` at `price_bps`, exact for whole lots
+pub fn lot_cost(shares: Amount, price_bps: u16) -> Option<Amount> {
+    let lots = u128::from(shares) / LOT;
+    lots.checked_mul(price_bps.into()).map(Amount::from_attos)
+}
+
+/// Match an incoming order against a book, by best price and then by time,
+/// which must be the order of the book. Mints and merges against the other
+/// outcome only happen in binary markets. Returns the fills, in order.
+pub fn match_order(book: &[Order], taker: &Order, binary: bool) -> Vec<Fill> {
+    // What each resting order offers the taker, as a price for the
+    // taker's outcome
+    let mut candidates: Vec<(usize, u16, FillKind)> = book
+        .iter()
+        .enumerate()
+        .filter(|(_, maker)| maker.owner != taker.owner)
+        .filter_map(|(index, maker)| {
+            let same_outcome = maker.outcome_id == taker.outcome_id;
+            let (price, kind) = match (taker.side, maker.side) {
+                (OrderSide::Buy, OrderSide::Sell) if same_outcome => {
+                    (maker.price_bps, FillKind::Transfer)
+                }
+                (OrderSide::Sell, OrderSide::Buy) if same_outcome => {
+                    (maker.price_bps, FillKind::Transfer)
+                }
+                (OrderSide::Buy, OrderSide::Buy) if binary && !same_outcome => {
+                    (BASIS_POINTS - maker.price_bps, FillKind::Mint)
+                }
+                (OrderSide::Sell, OrderSide::Sell) if binary && !same_outcome => {
+                    (BASIS_POINTS - maker.price_bps, FillKind::Merge)
+                }
+                _ => return None,
+            };
+            let crosses = match taker.side {
+                OrderSide::Buy => price <= taker.price_bps,
+                OrderSide::Sell => price >= taker.price_bps,
+            };
+            crosses.then_some((index, price, kind))
+        })
+        .collect();
+    // The sort is stable, so orders at the same price keep their time priority
+    match taker.side {
+        OrderSide::Buy => candidates.sort_by_key(|(_, price, _)| *price),
+        OrderSide::Sell => candidates.sort_by_key(|(_, price, _)| BASIS_POINTS - *price),
+    }
+
+    let mut remaining = taker.remaining;
+    let mut fills = Vec::new();
+    for (maker, _, kind) in candidates {
+        if remaining == Amount::ZERO {
+            break;
+        }
+        let shares = remaining.min(book[maker].remaining);
+        remaining = remaining.saturating_sub(shares);
+        fills.push(Fill {
+            maker,
+            shares,
+            kind,
+        });
+    }
+    fills
+}
+
+/// The shares offered at one price
+#[derive(Debug, Clone, PartialEq, Eq, SimpleObject)]
+pub struct PriceLevel {
+    pub price_bps: u16,
+    pub shares: Amount,
+    pub orders: u32,
+}
+
+/// The bids and asks for shares of an outcome, best prices first
+#[derive(Debug, Clone, SimpleObject)]
+pub struct OrderBookDepth {
+    pub outcome_id: String,
+    pub bids: Vec<PriceLevel>,
+    pub asks: Vec<PriceLevel>,
+}
+
+impl OrderBookDepth {
+    /// Aggregate the orders for an outcome by price
+    pub fn of_outcome(book: &[Order], outcome_id: &str) -> Self {
+        let levels = |side: OrderSide| {
+            let mut levels: Vec<PriceLevel> = Vec::new();
+            for order in book.iter().filter(|o| o.outcome_id == outcome_id && o.side == side) {
+                match levels.iter_mut().find(|level| level.price_bps == order.price_bps) {
+                    Some(level) => {
+                        level.shares = level.shares.saturating_add(order.remaining);
+                        level.orders += 1;
+                    }
+                    None => levels.push(PriceLevel {
+                        price_bps: order.price_bps,
+                        shares: order.remaining,
+                        orders: 1,
+                    }),
+                }
+            }
+            levels
+        };
+        let mut bids = levels(OrderSide::Buy);
+        bids.sort_by_key(|level| BASIS_POINTS - level.price_bps);
+        let mut asks = levels(OrderSide::Sell);
+        asks.sort_by_key(|level| level.price_bps);
+        OrderBookDepth {
+            outcome_id: outcome_id.to_string(),
+            bids,
+            asks,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use linera_sdk::base::{CryptoHash, Owner};
+
+    use super::*;
+
+    fn order(
+        id: &str,
+        owner: u8,
+        outcome: &str,
+        side: OrderSide,
+        price_bps: u16,
+        lots: u128,
+    ) -> Order {
+        let shares = Amount::from_attos(lots * LOT);
+        Order {
+            id: id.to_string(),
+            owner: AccountOwner::User(Owner(CryptoHash::from([u64::from(owner); 4]))),
+            market_id: "m".to_string(),
+            outcome_id: outcome.to_string(),
+            side,
+            price_bps,
+            shares,
+            remaining: shares,
+            created_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_price_time_priority() {
+        let book = vec![
+            order("a", 1, "yes", OrderSide::Sell, 6_000, 5),
+            order("b", 2, "yes", OrderSide::Sell, 5_500, 5),
+            order("c", 3, "yes", OrderSide::Sell, 5_500, 5),
+            order("d", 4, "yes", OrderSide::Sell, 7_000, 5),
+        ];
+        let taker = order("t", 9, "yes", OrderSide::Buy, 6_000, 12);
+        let fills = match_order(&book, &taker, true);
+        let lots = |lots: u128| Amount::from_attos(lots * LOT);
+        assert_eq!(
+            fills,
+            vec![
+                Fill { maker: 1, shares: lots(5), kind: FillKind::Transfer },
+                Fill { maker: 2, shares: lots(5), kind: FillKind::Transfer },
+                Fill { maker: 0, shares: lots(2), kind: FillKind::Transfer },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mint_and_merge_against_the_other_outcome() {
+        let book = vec![
+            order("a", 1, "no", OrderSide::Buy, 3_000, 5),
+            order("b", 2, "yes", OrderSide::Sell, 7_500, 5),
+            order("c", 3, "no", OrderSide::Sell, 2_000, 5),
+        ];
+        // Buying yes at 0.70 crosses a bid for no at 0.30, but not the ask at 0.75
+        let buyer = order("t", 9, "yes", OrderSide::Buy, 7_000, 10);
+        let fills = match_order(&book, &buyer, true);
+        assert_eq!(fills.len(), 1);
+        assert_eq!((fills[0].maker, fills[0].kind), (0, FillKind::Mint));
+        // Mints pay exactly one token per share between both sides
+        let shares = fills[0].shares;
+        let total = lot_cost(shares, 7_000)
+            .unwrap()
+            .try_add(lot_cost(shares, 3_000).unwrap())
+            .unwrap();
+        assert_eq!(total, shares);
+        // Nothing crosses in a market with more outcomes
+        assert!(match_order(&book, &buyer, false).is_empty());
+
+        // Selling yes at 0.80 merges with an ask for no at 0.20
+        let seller = order("t", 9, "yes", OrderSide::Sell, 8_000, 10);
+        let fills = match_order(&book, &seller, true);
+        assert_eq!(fills.len(), 1);
+        assert_eq!((fills[0].maker, fills[0].kind), (2, FillKind::Merge));
+    }
+
+    #[test]
+    fn test_keys_sort_best_price_first_then_oldest() {
+        let sorted = |side: OrderSide, orders: &[(u16, u64)]| -> Vec<(u16, u64)> {
+            let mut keys: Vec<OrderKey> = orders
+                .iter()
+                .map(|(price_bps, seq)| OrderKey::new(side, *price_bps, *seq))
+                .collect();
+            keys.sort_by_key(|key| bcs::to_bytes(key).unwrap());
+            keys.iter().map(|key| (key.priority(), key.seq())).collect()
+        };
+        // Bids rank from the highest price
+        assert_eq!(
+            sorted(OrderSide::Buy, &[(4_000, 1), (6_000, 256), (6_000, 2), (500, 3)]),
+            [(4_000, 2), (4_000, 256), (6_000, 1), (9_500, 3)]
+        );
+        // Asks rank from the lowest price
+        assert_eq!(
+            sorted(OrderSide::Sell, &[(4_000, 1), (260, 2), (4_000, 0)]),
+            [(260, 2), (4_000, 0), (4_000, 1)]
+        );
+
+        // A bid for yes at 0.70 crosses asks for yes up to 0.70, and bids
+        // for no from 0.30, which all have a priority of at most 7000
+        let taker = order("t", 9, "yes", OrderSide::Buy, 7_000, 1);
+        assert_eq!(crossing_priority(&taker), 7_000);
+        assert_eq!(OrderKey::new(OrderSide::Buy, 3_000, 0).priority(), 7_000);
+        assert_eq!(OrderKey::new(OrderSide::Sell, 7_000, 0).priority(), 7_000);
+    }
+}
@@ -0,0 +1,93 @@
+use async_graphql::SimpleObject;
+use linera_sdk::base::Amount;
+use serde::{Deserialize, Serialize};
+
+use crate::math::mul_div;
+
+/// The range of a scalar market, whose two outcomes go long and short on a
+/// numeric value. The pool is split linearly between both sides according to
+/// where the reported value falls in the range, clamped at the bounds.
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+pub struct ScalarRange {
+    pub lower: i64,
+    pub upper: i64,
+    /// The value reported on resolution
+    pub value: Option<i64>,
+}
+
+impl ScalarRange {
+    /// Index of the outcome going long
+    pub const LONG: usize = 0;
+    /// Index of the outcome going short
+    pub const SHORT: usize = 1;
+
+    pub fn new(lower: i64, upper: i64) -> Self {
+        ScalarRange {
+            lower,
+            upper,
+            value: None,
+        }
+    }
+
+    /// The names of the long and short outcomes
+    pub fn outcome_names() -> Vec<String> {
+        vec!["Long".to_string(), "Short".to_string()]
+    }
+
+    /// Split `pool` between the long and short sides for a value: the long
+    /// side gets `(value - lower) / (upper - lower)` of it, rounded down, and
+    /// the short side the rest
+    pub fn split(&self, pool: Amount, value: i64) -> Option<(Amount, Amount)> {
+        let range = i128::from(self.upper) - i128::from(self.lower);
+        let position = i128::from(value.clamp(self.lower, self.upper)) - i128::from(self.lower);
+        let long = mul_div(
+            pool,
+            Amount::from_attos(u128::try_from(position).ok()?),
+            Amount::from_attos(u128::try_from(range).ok()?),
+        )?;
+        Some((long, pool.try_sub(long).ok()?))
+    }
+
+    /// What the bets on a side share between them, once a value is reported
+    pub fn side_pool(&self, pool: Amount, outcome: usize) -> Amount {
+        let Some((long, short)) = self.value.and_then(|value| self.split(pool, value)) else {
+            return Amount::ZERO;
+        };
+        match outcome {
+            Self::LONG => long,
+            _ => short,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_is_linear_and_clamped() {
+        let range = ScalarRange::new(20_000, 60_000);
+        let pool = Amount::from_tokens(100);
+        let split = |value| range.split(pool, value).unwrap();
+        assert_eq!(split(30_000), (Amount::from_tokens(25), Amount::from_tokens(75)));
+        assert_eq!(split(60_000), (pool, Amount::ZERO));
+        assert_eq!(split(5_000), (Amount::ZERO, pool));
+        assert_eq!(split(i64::MAX), (pool, Amount::ZERO));
+    }
+
+    #[test]
+    fn test_split_adds_up_to_the_pool() {
+        let range = ScalarRange::new(i64::MIN, i64::MAX);
+        let pool = Amount::from_attos(1_000_000_007);
+        for value in [i64::MIN, -3, 0, 7, i64::MAX / 3] {
+            let (long, short) = range.split(pool, value).unwrap();
+            assert_eq!(long.try_add(short).unwrap(), pool);
+        }
+        let range = ScalarRange {
+            value: Some(0),
+            ..range
+        };
+        let long = range.side_pool(pool, ScalarRange::LONG);
+        assert!(long <= range.side_pool(pool, ScalarRange::SHORT));
+    }
+}
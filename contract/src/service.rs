@@ -1,7 +1,10 @@
 use async_graphql::{EmptyMutation, EmptySubscription, Request, Response, Schema};
 use linera_sdk::{base::WithServiceAbi, views::View, Service, ServiceRuntime};
 
-use crate::state::CascadeProtocol;
+use crate::{
+    msg::Parameters,
+    state::{CascadeProtocol, ChainBalance},
+};
 
 /// The service implementation for Cascade Protocol (GraphQL queries)
 pub struct CascadeProtocolService {
@@ -15,13 +18,16 @@ impl WithServiceAbi for CascadeProtocolService {
 }
 
 impl Service for CascadeProtocolService {
-    type Parameters = ();
+    type Parameters = Parameters;
 
     async fn new(runtime: ServiceRuntime<CascadeProtocolService>) -> Self {
         let state = CascadeProtocol::load(runtime.root_view_storage_context())
             .await
             .expect("failed to load state");
-        let schema = Schema::build(state, EmptyMutation, EmptySubscription).finish();
+        let schema = Schema::build(state, EmptyMutation, EmptySubscription)
+            .data(ChainBalance(runtime.chain_balance()))
+            .data(runtime.application_parameters())
+            .finish();
         CascadeProtocolService { schema }
     }
 
@@ -37,10 +43,10 @@ impl Service for CascadeProtocolService {
 mod tests {
     use super::*;
     use crate::{
-        state::{Market, MarketStatus, Outcome},
-        msg::MarketCategory,
+        state::{DustPolicy, Market, MarketStatus, Outcome},
+        msg::{Collateral, MarketCategory},
     };
-    use linera_sdk::base::Owner;
+    use linera_sdk::base::{Amount, Owner};
 
     // Note: These are placeholder tests. In a real implementation,
     // you would need to set up proper test fixtures with mock runtime.
@@ -54,20 +60,29 @@ mod tests {
                 Outcome {
                     id: "test_1_0".to_string(),
                     name: "Yes".to_string(),
-                    total_staked: 1000,
+                    total_staked: Amount::from_tokens(1000),
                 },
                 Outcome {
                     id: "test_1_1".to_string(),
                     name: "No".to_string(),
-                    total_staked: 2000,
+                    total_staked: Amount::from_tokens(2000),
                 },
             ],
-            total_staked: 3000,
+            total_staked: Amount::from_tokens(3000),
             status: MarketStatus::Active,
             expiry_time: 1234567890,
             winning_outcome_id: None,
             parent_id: None,
             category: MarketCategory::Crypto,
+            collateral: Collateral::Native,
+            claim_window: None,
+            claim_deadline: None,
+            amm: None,
+            order_book: false,
+            fixed_odds: None,
+            scalar: None,
+            buckets: None,
+            dust_policy: DustPolicy::LastClaimer,
         };
 
         // Test that the market can be serialized/deserialized
@@ -88,20 +103,29 @@ mod tests {
                 Outcome {
                     id: "test_1_0".to_string(),
                     name: "Outcome A".to_string(),
-                    total_staked: 1000,
+                    total_staked: Amount::from_tokens(1000),
                 },
                 Outcome {
                     id: "test_1_1".to_string(),
                     name: "Outcome B".to_string(),
-                    total_staked: 3000,
+                    total_staked: Amount::from_tokens(3000),
                 },
             ],
-            total_staked: 4000,
+            total_staked: Amount::from_tokens(4000),
             status: MarketStatus::Active,
             expiry_time: 1234567890,
             winning_outcome_id: None,
             parent_id: None,
             category: MarketCategory::Other,
+            collateral: Collateral::Native,
+            claim_window: None,
+            claim_deadline: None,
+            amm: None,
+            order_book: false,
+            fixed_odds: None,
+            scalar: None,
+            buckets: None,
+            dust_policy: DustPolicy::LastClaimer,
         };
 
         // Outcome A has 1000/4000 staked, so odds should be 4.0
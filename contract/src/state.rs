@@ -1,14 +1,28 @@
-use async_graphql::{Enum, Object, SimpleObject};
-use linera_sdk::base::Owner;
+use std::ops::Range;
+
+use async_graphql::{ComplexObject, Context, Enum, Object, SimpleObject};
+use linera_sdk::base::{AccountOwner, Amount, ApplicationId, ArithmeticError, ChainId, Owner};
 use linera_views::{
+    collection_view::CollectionView,
+    log_view::LogView,
     map_view::MapView,
     register_view::RegisterView,
+    set_view::SetView,
     views::{RootView, ViewError},
 };
 use linera_sdk::views::ViewStorageContext;
 use serde::{Deserialize, Serialize};
 
-use crate::msg::MarketCategory;
+use crate::{
+    amm::{AmmState, CpmmPool},
+    bookmaker::FixedOddsBook,
+    bucket::Buckets,
+    event::{Event, EventFilter, EventPage, EventPayload},
+    math::mul_div,
+    msg::{Collateral, MarketCategory, Parameters, SubscriptionTarget},
+    orderbook::{BookSide, Order, OrderBookDepth, OrderKey, OrderSide},
+    scalar::ScalarRange,
+};
 
 /// Market status enumeration
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Enum)]
@@ -16,6 +30,7 @@ pub enum MarketStatus {
     Active,
     Resolved,
     Expired,
+    Cancelled,
 }
 
 /// Represents a single outcome in a market
@@ -24,7 +39,7 @@ pub enum MarketStatus {
 pub struct Outcome {
     pub id: String,
     pub name: String,
-    pub total_staked: u64,
+    pub total_staked: Amount,
 }
 
 /// Represents a prediction market
@@ -34,22 +49,125 @@ pub struct Market {
     pub id: String,
     pub question: String,
     pub outcomes: Vec<Outcome>,
-    pub total_staked: u64,
+    pub total_staked: Amount,
     pub status: MarketStatus,
     pub expiry_time: u64,
     pub winning_outcome_id: Option<String>,
     pub parent_id: Option<String>,
     pub category: MarketCategory,
+    pub collateral: Collateral,
+    /// How long winners have to claim after resolution, if limited
+    pub claim_window: Option<u64>,
+    /// When winners can no longer claim, set on resolution
+    pub claim_deadline: Option<u64>,
+    /// The market maker pricing the market, unless it is a parimutuel pool
+    pub amm: Option<AmmState>,
+    /// Whether shares are traded through a limit order book
+    pub order_book: bool,
+    /// The bookmaker's odds and liabilities, for fixed-odds markets
+    pub fixed_odds: Option<FixedOddsBook>,
+    /// The range of the value a scalar market is about
+    pub scalar: Option<ScalarRange>,
+    /// The buckets of a numeric market, one per outcome
+    pub buckets: Option<Buckets>,
+    /// Who receives the dust of the pool when it is split between winners
+    pub dust_policy: DustPolicy,
 }
 
 impl Market {
+    /// Whether positions are shares of outcomes rather than parimutuel bets
+    pub fn trades_shares(&self) -> bool {
+        self.amm.is_some() || self.order_book
+    }
+
+    /// Whether the market is resolved with a numeric value rather than an
+    /// outcome
+    pub fn resolves_by_value(&self) -> bool {
+        self.scalar.is_some() || self.buckets.is_some()
+    }
+
+    /// The outcome a scalar or bucketed market resolves to for a value. The
+    /// long side of a scalar market wins from the middle of the range up.
+    pub fn value_outcome(&self, value: i64) -> Option<String> {
+        let index = match (&self.scalar, &self.buckets) {
+            (Some(range), _) => {
+                let position = i128::from(value) - i128::from(range.lower);
+                let width = i128::from(range.upper) - i128::from(range.lower);
+                if 2 * position >= width {
+                    ScalarRange::LONG
+                } else {
+                    ScalarRange::SHORT
+                }
+            }
+            (None, Some(buckets)) => buckets.bucket_of(value),
+            (None, None) => return None,
+        };
+        self.outcomes.get(index).map(|outcome| outcome.id.clone())
+    }
+
+    /// Take `stake` out of an outcome when it is cashed out. The whole stake
+    /// leaves the outcome, but its `penalty` stays in the pool. Returns what
+    /// leaves the pool, or `None` if the outcome does not hold the stake.
+    pub fn cash_out(&mut self, outcome_id: &str, stake: Amount, penalty: Amount) -> Option<Amount> {
+        let payout = stake.try_sub(penalty).ok()?;
+        let outcome = self.outcomes.iter_mut().find(|o| o.id == outcome_id)?;
+        let outcome_staked = outcome.total_staked.try_sub(stake).ok()?;
+        let total_staked = self.total_staked.try_sub(payout).ok()?;
+        outcome.total_staked = outcome_staked;
+        self.total_staked = total_staked;
+        Some(payout)
+    }
+
+    /// Fees collected by the market maker, if it charges any
+    pub fn amm_fees(&self) -> Amount {
+        match &self.amm {
+            Some(AmmState::Cpmm(pool)) => pool.fees_collected,
+            _ => Amount::ZERO,
+        }
+    }
+
+    /// What the bets on an outcome of a resolved market share between them:
+    /// the whole pool for the winning outcome, or a side's part of it in
+    /// scalar markets. A scalar side that nobody backed leaves its part to
+    /// the other side.
+    pub fn payout_pool(&self, outcome_id: &str) -> Amount {
+        if let Some(range) = &self.scalar {
+            let Some(side) = self.outcomes.iter().position(|o| o.id == outcome_id) else {
+                return Amount::ZERO;
+            };
+            let backed = |side: usize| self.outcomes[side].total_staked > Amount::ZERO;
+            if range.value.is_none() || !backed(side) {
+                return Amount::ZERO;
+            }
+            if !(0..self.outcomes.len()).any(|other| other != side && backed(other)) {
+                return self.total_staked;
+            }
+            return range.side_pool(self.total_staked, side);
+        }
+        if self.winning_outcome_id.as_deref() == Some(outcome_id) {
+            self.total_staked
+        } else {
+            Amount::ZERO
+        }
+    }
+
+    /// Whether the bets on an outcome of a resolved market are paid
+    pub fn pays_outcome(&self, outcome_id: &str) -> bool {
+        self.payout_pool(outcome_id) > Amount::ZERO
+    }
+
+    /// Whether winners can no longer claim at the given time
+    pub fn claim_deadline_passed(&self, current_time: u64) -> bool {
+        self.claim_deadline.is_some_and(|deadline| current_time >= deadline)
+    }
+
     /// Calculate odds for display (odds = total_staked / outcome_staked)
     pub fn calculate_odds(&self, outcome_id: &str) -> f64 {
         if let Some(outcome) = self.outcomes.iter().find(|o| o.id == outcome_id) {
-            if outcome.total_staked == 0 {
+            if outcome.total_staked == Amount::ZERO {
                 return 0.0;
             }
-            self.total_staked as f64 / outcome.total_staked as f64
+            u128::from(self.total_staked) as f64 / u128::from(outcome.total_staked) as f64
         } else {
             0.0
         }
@@ -61,13 +179,521 @@ impl Market {
 #[graphql(name = "Bet")]
 pub struct Bet {
     pub id: String,
-    pub owner: Owner,
+    pub owner: AccountOwner,
     pub market_id: String,
     pub outcome_id: String,
-    pub amount: u64,
+    pub amount: Amount,
+    pub claimed: bool,
+    /// The odds locked in when a bet of a fixed-odds market was placed
+    pub odds_bps: Option<u32>,
+}
+
+/// The shares an owner holds in a market priced by a market maker
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+pub struct SharePosition {
+    pub id: String,
+    pub owner: AccountOwner,
+    pub market_id: String,
+    /// Shares of each outcome, in the order of the market's outcomes
+    pub shares: Vec<Amount>,
+    /// What the owner paid for their shares, minus what they sold them for
+    pub cost_basis: Amount,
+    /// Whether the winning shares were paid out
     pub claimed: bool,
 }
 
+/// The LP shares an owner holds in a market's constant-product pool
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+pub struct LiquidityPosition {
+    pub owner: AccountOwner,
+    pub market_id: String,
+    pub lp_shares: Amount,
+}
+
+/// A position offered for sale at a fixed price. The listed stake or shares
+/// are held by the application until the listing is filled or cancelled.
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+pub struct Listing {
+    pub id: String,
+    pub seller: AccountOwner,
+    pub market_id: String,
+    pub outcome_id: String,
+    /// The stake or the shares offered
+    pub amount: Amount,
+    /// What a buyer pays for the whole position
+    pub price: Amount,
+    /// The seller's cost basis of listed shares, restored on cancellation
+    pub cost_basis: Amount,
+    pub created_at: u64,
+}
+
+/// The kind of cross-chain request that was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Enum)]
+pub enum RejectedRequest {
+    Bet,
+    Claim,
+    CashOut,
+    Buy,
+    Sell,
+    AddLiquidity,
+    RemoveLiquidity,
+    Order,
+    CancelOrder,
+    Mint,
+    Redeem,
+    Transfer,
+    List,
+    Fill,
+    CancelListing,
+    SetOdds,
+}
+
+/// A bet or claim from a user's chain that the application chain rejected
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+#[graphql(name = "Rejection")]
+pub struct Rejection {
+    pub market_id: String,
+    pub request: RejectedRequest,
+    /// Stake returned to the user's chain
+    pub refunded: Amount,
+    pub reason: String,
+}
+
+/// Width of the time buckets odds snapshots are downsampled into (15 minutes)
+pub const HISTORY_BUCKET_MICROS: u64 = 15 * 60 * 1_000_000;
+
+/// Stakes of a market at a point in time, the last bet of a history bucket
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+#[graphql(name = "OddsSnapshot")]
+pub struct OddsSnapshot {
+    pub timestamp: u64,
+    /// Stake on each outcome, in the order of `Market.outcomes`
+    pub outcome_stakes: Vec<Amount>,
+    pub total_staked: Amount,
+}
+
+impl OddsSnapshot {
+    pub fn of_market(market: &Market, timestamp: u64) -> Self {
+        OddsSnapshot {
+            timestamp,
+            outcome_stakes: market.outcomes.iter().map(|o| o.total_staked).collect(),
+            total_staked: market.total_staked,
+        }
+    }
+
+    /// The history bucket this snapshot falls into for the given resolution
+    pub fn bucket(&self, resolution: u64) -> u64 {
+        self.timestamp / resolution
+    }
+}
+
+/// Per-user betting statistics, updated incrementally as bets are placed,
+/// markets are resolved and winnings are claimed
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+#[graphql(name = "UserStats", complex)]
+pub struct UserStats {
+    pub owner: AccountOwner,
+    /// Total amount staked across all bets
+    pub volume: Amount,
+    /// Number of bets placed
+    pub bet_count: u64,
+    /// Number of resolved markets where the user held a winning position
+    pub markets_won: u64,
+    /// Number of resolved markets where the user held only losing positions
+    pub markets_lost: u64,
+    /// Sum of payouts received in excess of the winning stake
+    pub realized_gains: Amount,
+    /// Sum of stakes lost on resolved markets
+    pub realized_losses: Amount,
+    /// Sum of stakes returned because their market was cancelled
+    pub refunded: Amount,
+    /// Sum of stakes taken back before resolution, penalties included
+    pub cashed_out: Amount,
+}
+
+#[ComplexObject]
+impl UserStats {
+    /// Realized profit (gains minus losses), as a signed decimal amount
+    async fn realized_profit(&self) -> String {
+        if self.realized_gains >= self.realized_losses {
+            self.realized_gains.saturating_sub(self.realized_losses).to_string()
+        } else {
+            format!("-{}", self.realized_losses.saturating_sub(self.realized_gains))
+        }
+    }
+
+    /// Fraction of settled markets that were won, between 0 and 1
+    async fn win_rate(&self) -> f64 {
+        let settled = self.markets_won + self.markets_lost;
+        if settled == 0 {
+            return 0.0;
+        }
+        self.markets_won as f64 / settled as f64
+    }
+}
+
+impl UserStats {
+    pub fn new(owner: AccountOwner) -> Self {
+        UserStats {
+            owner,
+            volume: Amount::ZERO,
+            bet_count: 0,
+            markets_won: 0,
+            markets_lost: 0,
+            realized_gains: Amount::ZERO,
+            realized_losses: Amount::ZERO,
+            refunded: Amount::ZERO,
+            cashed_out: Amount::ZERO,
+        }
+    }
+
+    /// Count a bet or a trade of `volume`
+    pub fn record_trade(&mut self, volume: Amount) -> Result<(), ArithmeticError> {
+        self.volume.try_add_assign(volume)?;
+        self.bet_count = self.bet_count.checked_add(1).ok_or(ArithmeticError::Overflow)?;
+        Ok(())
+    }
+
+    /// Count a resolved market the user held a position in, and the stake
+    /// they lost in it
+    pub fn record_result(&mut self, won: bool, lost: Amount) -> Result<(), ArithmeticError> {
+        let count = if won {
+            &mut self.markets_won
+        } else {
+            &mut self.markets_lost
+        };
+        *count = count.checked_add(1).ok_or(ArithmeticError::Overflow)?;
+        self.realized_losses.try_add_assign(lost)
+    }
+
+    /// Realized profit in attos, as a signed value
+    pub fn profit(&self) -> i128 {
+        u128::from(self.realized_gains) as i128 - u128::from(self.realized_losses) as i128
+    }
+
+    /// The sort key of this user for the given leaderboard metric
+    pub fn score(&self, metric: LeaderboardMetric) -> i128 {
+        match metric {
+            LeaderboardMetric::Profit => self.profit(),
+            LeaderboardMetric::Volume => u128::from(self.volume) as i128,
+            LeaderboardMetric::MarketsWon => self.markets_won as i128,
+            LeaderboardMetric::WinRate => {
+                // Basis points, so that the index can be kept in integers
+                let settled = self.markets_won + self.markets_lost;
+                (self.markets_won * 10_000).checked_div(settled).unwrap_or(0) as i128
+            }
+        }
+    }
+}
+
+/// Metrics the leaderboard can be sorted by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Enum)]
+pub enum LeaderboardMetric {
+    Profit,
+    Volume,
+    WinRate,
+    MarketsWon,
+}
+
+impl LeaderboardMetric {
+    pub const ALL: [LeaderboardMetric; 4] = [
+        LeaderboardMetric::Profit,
+        LeaderboardMetric::Volume,
+        LeaderboardMetric::WinRate,
+        LeaderboardMetric::MarketsWon,
+    ];
+}
+
+/// An entry of a leaderboard index. Entries are stored as keys, and their
+/// serialized form sorts by descending score, then by owner.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LeaderboardEntry {
+    /// The score as big-endian bytes, inverted so that the best comes first
+    rank: [u8; 16],
+    pub owner: AccountOwner,
+}
+
+impl LeaderboardEntry {
+    pub fn new(owner: AccountOwner, score: i128) -> Self {
+        // Flipping the sign bit orders signed scores like unsigned ones
+        let rank = !((score as u128) ^ (1 << 127));
+        LeaderboardEntry {
+            rank: rank.to_be_bytes(),
+            owner,
+        }
+    }
+
+    pub fn score(&self) -> i128 {
+        (!u128::from_be_bytes(self.rank) ^ (1 << 127)) as i128
+    }
+}
+
+/// Protocol-wide totals, maintained incrementally by every operation
+#[derive(Debug, Clone, Default, Serialize, Deserialize, SimpleObject)]
+#[graphql(name = "ProtocolStats")]
+pub struct ProtocolStats {
+    pub active_markets: u64,
+    pub resolved_markets: u64,
+    pub expired_markets: u64,
+    pub cancelled_markets: u64,
+    /// Total amount ever staked
+    pub total_volume: Amount,
+    /// Stake currently held by the application
+    pub total_escrowed: Amount,
+    /// Trading fees of market makers and cash-out penalties collected
+    pub fees_accrued: Amount,
+    /// Unclaimed winnings moved to the treasury after their claim deadline
+    pub total_swept: Amount,
+    /// Number of distinct owners that have placed a bet
+    pub unique_bettors: u64,
+    /// Winnings of resolved markets and refunds of cancelled markets that
+    /// have not been claimed yet
+    pub unclaimed_winnings: Amount,
+}
+
+impl ProtocolStats {
+    /// Move one market from its previous status (if any) to a new one
+    pub fn record_status_change(
+        &mut self,
+        from: Option<MarketStatus>,
+        to: MarketStatus,
+    ) -> Result<(), ArithmeticError> {
+        if let Some(from) = from {
+            let count = self.market_count_mut(from);
+            *count = count.checked_sub(1).ok_or(ArithmeticError::Underflow)?;
+        }
+        let count = self.market_count_mut(to);
+        *count = count.checked_add(1).ok_or(ArithmeticError::Overflow)?;
+        Ok(())
+    }
+
+    /// Count a bet or a trade of `volume`, and its owner if it is their first
+    pub fn record_trade(
+        &mut self,
+        volume: Amount,
+        first_trade: bool,
+    ) -> Result<(), ArithmeticError> {
+        self.total_volume.try_add_assign(volume)?;
+        if first_trade {
+            self.unique_bettors =
+                self.unique_bettors.checked_add(1).ok_or(ArithmeticError::Overflow)?;
+        }
+        Ok(())
+    }
+
+    fn market_count_mut(&mut self, status: MarketStatus) -> &mut u64 {
+        match status {
+            MarketStatus::Active => &mut self.active_markets,
+            MarketStatus::Resolved => &mut self.resolved_markets,
+            MarketStatus::Expired => &mut self.expired_markets,
+            MarketStatus::Cancelled => &mut self.cancelled_markets,
+        }
+    }
+}
+
+/// Who receives the dust left over when a pool is split between winners,
+/// as every share is rounded down to the atto
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Enum)]
+pub enum DustPolicy {
+    /// The dust is added to the payout of the last winning bet to be paid
+    #[default]
+    LastClaimer,
+    /// The dust is not owed to the winners, and is swept to the treasury
+    Treasury,
+    /// The bets whose shares were rounded down the most are paid an atto
+    /// more each, until the dust is used up
+    LargestRemainder,
+}
+
+/// How the pool paid to an outcome of a resolved market is split between
+/// the bets on it. Every share is rounded down to the atto, and the dust
+/// left over goes where the market's `DustPolicy` says.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, SimpleObject)]
+pub struct PoolSplit {
+    pub outcome_id: String,
+    /// The pool split between the bets on the outcome
+    pub pool: Amount,
+    /// The sum of their stakes
+    pub winning_stake: Amount,
+    /// The stakes of the bets not paid yet
+    pub unpaid_stake: Amount,
+    /// The payouts made so far, dust included
+    pub paid_out: Amount,
+    /// What is left of the pool after rounding every share down
+    pub dust: Amount,
+    pub dust_policy: DustPolicy,
+    /// The bets paid an atto beyond their share, under `LargestRemainder`
+    #[graphql(skip)]
+    pub rounded_up: Vec<String>,
+}
+
+impl PoolSplit {
+    /// Split `pool` between bets, given by ID and stake
+    pub fn new(
+        outcome_id: String,
+        pool: Amount,
+        bets: &[(String, Amount)],
+        dust_policy: DustPolicy,
+    ) -> Option<Self> {
+        let winning_stake = bets
+            .iter()
+            .try_fold(Amount::ZERO, |total, (_, stake)| total.try_add(*stake).ok())?;
+        let mut dust = pool;
+        let mut remainders = Vec::with_capacity(bets.len());
+        for (index, (_, stake)) in bets.iter().enumerate() {
+            let share = mul_div(*stake, pool, winning_stake)?;
+            dust = dust.try_sub(share).ok()?;
+            // `stake * pool - share * winning_stake` is below `winning_stake`,
+            // so it is exact even though both products wrap around
+            let remainder = u128::from(*stake)
+                .wrapping_mul(pool.into())
+                .wrapping_sub(u128::from(share).wrapping_mul(winning_stake.into()));
+            remainders.push((remainder, index));
+        }
+
+        let mut rounded_up = Vec::new();
+        if dust_policy == DustPolicy::LargestRemainder {
+            // Each share lost less than an atto, so there are fewer attos of
+            // dust than bets. Ties go to the earlier bet.
+            remainders.sort_by_key(|(remainder, index)| (std::cmp::Reverse(*remainder), *index));
+            let count = usize::try_from(u128::from(dust)).ok()?;
+            rounded_up = remainders
+                .iter()
+                .take(count)
+                .map(|(_, index)| bets[*index].0.clone())
+                .collect();
+        }
+        Some(Self {
+            outcome_id,
+            pool,
+            winning_stake,
+            unpaid_stake: winning_stake,
+            paid_out: Amount::ZERO,
+            dust,
+            dust_policy,
+            rounded_up,
+        })
+    }
+
+    /// What the bets on the outcome are paid in total
+    pub fn owed(&self) -> Amount {
+        match self.dust_policy {
+            DustPolicy::Treasury => self.pool.saturating_sub(self.dust),
+            DustPolicy::LastClaimer | DustPolicy::LargestRemainder => self.pool,
+        }
+    }
+
+    /// Pay a bet of `stake` its share of the pool, along with its part of the
+    /// dust. Returns `None` if the stake is not part of what is left unpaid.
+    pub fn pay(&mut self, bet_id: &str, stake: Amount) -> Option<Amount> {
+        let share = mul_div(stake, self.pool, self.winning_stake)?;
+        self.unpaid_stake = self.unpaid_stake.try_sub(stake).ok()?;
+        let payout = match self.dust_policy {
+            DustPolicy::LastClaimer if self.unpaid_stake == Amount::ZERO => {
+                self.pool.try_sub(self.paid_out).ok()?
+            }
+            DustPolicy::LargestRemainder if self.rounded_up.iter().any(|id| id == bet_id) => {
+                share.try_add(Amount::from_attos(1)).ok()?
+            }
+            _ => share,
+        };
+        self.paid_out = self.paid_out.try_add(payout).ok()?;
+        Some(payout)
+    }
+}
+
+/// How the pool of a resolved market is split between its winners
+#[derive(Debug, Clone, SimpleObject)]
+pub struct Settlement {
+    pub market_id: String,
+    pub dust_policy: DustPolicy,
+    /// The split of each outcome the market pays
+    pub splits: Vec<PoolSplit>,
+    /// When winners can no longer claim, if limited
+    pub claim_deadline: Option<u64>,
+    /// What winners have not claimed yet, or had not when it was swept
+    pub unclaimed: Amount,
+    /// What was moved to the treasury after the claim deadline
+    pub swept: Amount,
+}
+
+/// What the application holds in escrow for stakes, and what it owes for them
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, SimpleObject)]
+pub struct Ledger {
+    /// Stakes received, minus the payouts and refunds made
+    pub escrowed: Amount,
+    /// Payouts and refunds that are claimable or will become claimable
+    pub liability: Amount,
+}
+
+impl Ledger {
+    /// A stake was received, and is owed back as a payout or a refund
+    pub fn deposit(&mut self, amount: Amount) -> Result<(), ArithmeticError> {
+        self.escrowed.try_add_assign(amount)?;
+        self.liability.try_add_assign(amount)
+    }
+
+    /// A payout or refund was made
+    pub fn withdraw(&mut self, amount: Amount) -> Result<(), ArithmeticError> {
+        self.escrowed.try_sub_assign(amount)?;
+        self.liability.try_sub_assign(amount)
+    }
+
+    /// Part of the liability is no longer owed to anyone, e.g. the pool of a
+    /// market that nobody won
+    pub fn forgive(&mut self, amount: Amount) -> Result<(), ArithmeticError> {
+        self.liability.try_sub_assign(amount)
+    }
+}
+
+/// The escrow and liability of a market, and how much of it is missing
+#[derive(Debug, Clone, SimpleObject)]
+pub struct MarketSolvency {
+    pub market_id: String,
+    pub collateral: Collateral,
+    pub escrowed: Amount,
+    pub liability: Amount,
+    pub shortfall: Amount,
+}
+
+/// The totals of one collateral over the markets hosted on this chain
+#[derive(Debug, Clone, SimpleObject)]
+pub struct CollateralSolvency {
+    pub collateral: Collateral,
+    pub escrowed: Amount,
+    pub liability: Amount,
+    /// The balance actually held by the chain, for native collateral
+    pub balance: Option<Amount>,
+    /// Liability not covered by the balance, or by the escrow when the
+    /// balance is unknown
+    pub shortfall: Amount,
+}
+
+/// Result of the `auditSolvency` query
+#[derive(Debug, Clone, SimpleObject)]
+pub struct SolvencyReport {
+    pub solvent: bool,
+    pub collaterals: Vec<CollateralSolvency>,
+    /// Markets whose escrow does not cover their liability
+    pub markets: Vec<MarketSolvency>,
+}
+
+/// What cashing out part of a bet would pay, from the `cashOutQuote` query
+#[derive(Debug, Clone, SimpleObject)]
+pub struct CashOutQuote {
+    /// The part of the stake cashed out
+    pub stake: Amount,
+    pub penalty_bps: u16,
+    /// What stays in the pool for the winners
+    pub penalty: Amount,
+    pub payout: Amount,
+}
+
+/// The native balance of the chain, which holds the native escrow, provided
+/// to queries by the service
+#[derive(Debug, Clone, Copy)]
+pub struct ChainBalance(pub Amount);
+
 /// Root application state
 #[derive(RootView)]
 #[view(context = "ViewStorageContext")]
@@ -81,11 +707,91 @@ pub struct CascadeProtocol {
     /// All markets indexed by market ID
     pub markets: MapView<ViewStorageContext, String, Market>,
     
-    /// All bets indexed by owner, then by bet ID
-    pub bets_by_owner: MapView<ViewStorageContext, Owner, Vec<Bet>>,
+    /// Read-model of markets held by other chains, kept up to date by the
+    /// updates sent to this chain's subscriptions
+    pub market_replicas: MapView<ViewStorageContext, String, Market>,
+    
+    /// All bets on markets hosted on this chain, by bet ID
+    pub bets: MapView<ViewStorageContext, String, Bet>,
+    
+    /// The IDs of each owner's bets
+    pub bets_by_owner: MapView<ViewStorageContext, AccountOwner, Vec<String>>,
+    
+    /// The IDs of each market's bets, in the order they were placed. Bets
+    /// removed since are skipped when reading them.
+    pub bets_by_market: CollectionView<ViewStorageContext, String, LogView<ViewStorageContext, String>>,
+    
+    /// Bets placed from this chain on markets hosted by other chains, by
+    /// owner, as reported by the chains hosting them
+    pub mirrored_bets: MapView<ViewStorageContext, AccountOwner, Vec<Bet>>,
+    
+    /// Shares held in markets priced by a market maker, by market ID
+    pub share_positions: MapView<ViewStorageContext, String, Vec<SharePosition>>,
+    
+    /// LP shares of constant-product pools, by market ID
+    pub liquidity_positions: MapView<ViewStorageContext, String, Vec<LiquidityPosition>>,
+    
+    /// Resting limit orders by side of each market's book, best price first
+    /// and then in time priority
+    pub orders: CollectionView<ViewStorageContext, BookSide, MapView<ViewStorageContext, OrderKey, Order>>,
     
-    /// All bets indexed by market ID for efficient lookups
-    pub bets_by_market: MapView<ViewStorageContext, String, Vec<Bet>>,
+    /// Where each resting order is kept in its market's book, by order ID
+    pub order_keys: MapView<ViewStorageContext, String, (BookSide, OrderKey)>,
+    
+    /// Orders placed on this chain, numbering them for time priority
+    pub order_count: RegisterView<ViewStorageContext, u64>,
+    /// Open listings of positions by market ID, then by listing ID
+    pub listings: CollectionView<ViewStorageContext, String, MapView<ViewStorageContext, String, Listing>>,
+    
+    /// Betting statistics indexed by owner
+    pub user_stats: MapView<ViewStorageContext, AccountOwner, UserStats>,
+    
+    /// Leaderboard indexes, each iterated by descending score
+    pub leaderboard: CollectionView<ViewStorageContext, LeaderboardMetric, SetView<ViewStorageContext, LeaderboardEntry>>,
+    
+    /// Closed odds history buckets of each market, in chronological order
+    pub odds_history: CollectionView<ViewStorageContext, String, LogView<ViewStorageContext, OddsSnapshot>>,
+    
+    /// The latest snapshot of each market, for the bucket that is still open
+    pub odds_history_head: MapView<ViewStorageContext, String, OddsSnapshot>,
+    
+    /// Protocol-wide statistics
+    pub protocol_stats: RegisterView<ViewStorageContext, ProtocolStats>,
+    
+    /// Append-only log of everything that happened, for indexers
+    pub events: LogView<ViewStorageContext, Event>,
+    
+    /// Chains to notify about each market or category
+    pub subscribers: MapView<ViewStorageContext, SubscriptionTarget, Vec<ChainId>>,
+    
+    /// On the application chain, the chain hosting each market that does not
+    /// live here. Bets, statistics and history of those markets are kept there.
+    pub market_chains: MapView<ViewStorageContext, String, ChainId>,
+    
+    /// Applications allowed to create markets through cross-application calls
+    pub allowed_creators: SetView<ViewStorageContext, ApplicationId>,
+    
+    /// Requests sent from this chain that the application chain rejected, by owner
+    pub rejections: MapView<ViewStorageContext, AccountOwner, Vec<Rejection>>,
+    
+    /// Escrow and liability of each market whose stakes are held on this chain
+    pub market_ledgers: MapView<ViewStorageContext, String, Ledger>,
+    
+    /// Escrow and liability of all markets on this chain, by collateral
+    pub collateral_ledgers: MapView<ViewStorageContext, Collateral, Ledger>,
+    
+    /// The chain each owner last bet from, where distributed winnings are paid
+    pub payout_chains: MapView<ViewStorageContext, AccountOwner, ChainId>,
+    
+    /// What was swept to the treasury from each market
+    pub swept: MapView<ViewStorageContext, String, Amount>,
+    
+    /// How many bets of each market `DistributeWinnings` has walked so far
+    pub distribution_cursors: MapView<ViewStorageContext, String, u64>,
+    
+    /// How the pool of each resolved market is split between the bets on
+    /// each outcome it pays
+    pub pool_splits: MapView<ViewStorageContext, String, Vec<PoolSplit>>,
 }
 
 #[Object]
@@ -105,20 +811,373 @@ impl CascadeProtocol {
         self.markets.get(&id).await
     }
     
+    /// Get this chain's read-model of the markets it subscribed to
+    async fn market_replicas(&self) -> Result<Vec<Market>, ViewError> {
+        let mut result = Vec::new();
+        self.market_replicas.for_each_index_value(|_, market| {
+            result.push(market.clone());
+            Ok(())
+        }).await?;
+        Ok(result)
+    }
+    
+    /// Get the chain hosting a market, or nothing if it is hosted on this chain
+    async fn market_chain(&self, id: String) -> Result<Option<ChainId>, ViewError> {
+        self.market_chains.get(&id).await
+    }
+    
     /// Get all bets for a specific owner
-    async fn bets_for_owner(&self, owner: Owner) -> Result<Vec<Bet>, ViewError> {
-        match self.bets_by_owner.get(&owner).await? {
-            Some(bets) => Ok(bets),
-            None => Ok(Vec::new()),
-        }
+    async fn bets_for_owner(&self, owner: AccountOwner) -> Result<Vec<Bet>, ViewError> {
+        let mut bets = self.owner_bets(&owner).await?;
+        bets.extend(self.mirrored_bets.get(&owner).await?.unwrap_or_default());
+        Ok(bets)
     }
     
     /// Get all bets for a specific market
     async fn bets_for_market(&self, market_id: String) -> Result<Vec<Bet>, ViewError> {
-        match self.bets_by_market.get(&market_id).await? {
-            Some(bets) => Ok(bets),
-            None => Ok(Vec::new()),
+        self.market_bets(&market_id, 0..usize::MAX).await
+    }
+    
+    /// Get the share positions in a market priced by a market maker,
+    /// optionally only the one of an owner
+    async fn share_positions(
+        &self,
+        market_id: String,
+        owner: Option<AccountOwner>,
+    ) -> Result<Vec<SharePosition>, ViewError> {
+        let positions = self.share_positions.get(&market_id).await?.unwrap_or_default();
+        Ok(positions
+            .into_iter()
+            .filter(|position| owner.map_or(true, |owner| position.owner == owner))
+            .collect())
+    }
+    
+    /// Get the reserves and fee of a market's constant-product pool
+    async fn pool(&self, market_id: String) -> Result<Option<CpmmPool>, ViewError> {
+        Ok(match self.markets.get(&market_id).await?.and_then(|market| market.amm) {
+            Some(AmmState::Cpmm(pool)) => Some(pool),
+            _ => None,
+        })
+    }
+    
+    /// Get the LP positions in a market's constant-product pool, optionally
+    /// only the one of an owner
+    async fn liquidity_positions(
+        &self,
+        market_id: String,
+        owner: Option<AccountOwner>,
+    ) -> Result<Vec<LiquidityPosition>, ViewError> {
+        let positions = self.liquidity_positions.get(&market_id).await?.unwrap_or_default();
+        Ok(positions
+            .into_iter()
+            .filter(|position| owner.map_or(true, |owner| position.owner == owner))
+            .collect())
+    }
+    
+    /// Get the bids and asks for an outcome of a market's order book,
+    /// aggregated by price
+    async fn order_book(
+        &self,
+        market_id: String,
+        outcome_id: String,
+    ) -> Result<OrderBookDepth, ViewError> {
+        let book = self.market_orders(&market_id).await?;
+        Ok(OrderBookDepth::of_outcome(&book, &outcome_id))
+    }
+    
+    /// Get the open listings of a market, optionally for a single outcome
+    async fn listings(
+        &self,
+        market_id: String,
+        outcome_id: Option<String>,
+    ) -> Result<Vec<Listing>, ViewError> {
+        let mut listings: Vec<Listing> = self
+            .market_listings(&market_id)
+            .await?
+            .into_iter()
+            .filter(|listing| outcome_id.as_ref().map_or(true, |id| listing.outcome_id == *id))
+            .collect();
+        listings.sort_by_key(|listing| listing.created_at);
+        Ok(listings)
+    }
+    
+    /// Get the resting orders of an owner, optionally in a single market
+    async fn open_orders(
+        &self,
+        owner: AccountOwner,
+        market_id: Option<String>,
+    ) -> Result<Vec<Order>, ViewError> {
+        let mut orders = Vec::new();
+        for side in self.orders.indices().await? {
+            if market_id.as_ref().map_or(false, |market_id| *market_id != side.market_id) {
+                continue;
+            }
+            let Some(book) = self.orders.try_load_entry(&side).await? else {
+                continue;
+            };
+            book.for_each_index_value(|_, order| {
+                if order.owner == owner {
+                    orders.push(order.clone());
+                }
+                Ok(())
+            })
+            .await?;
+        }
+        Ok(orders)
+    }
+    
+    /// Get the bets and claims of an owner that the application chain rejected
+    async fn rejections(&self, owner: AccountOwner) -> Result<Vec<Rejection>, ViewError> {
+        Ok(self.rejections.get(&owner).await?.unwrap_or_default())
+    }
+    
+    /// Get the statistics of a specific user
+    async fn user_stats(&self, owner: AccountOwner) -> Result<Option<UserStats>, ViewError> {
+        self.user_stats.get(&owner).await
+    }
+    
+    /// Get a page of the leaderboard for the given metric, best first
+    async fn leaderboard(
+        &self,
+        metric: LeaderboardMetric,
+        #[graphql(default = 50)] limit: usize,
+        #[graphql(default = 0)] offset: usize,
+    ) -> Result<Vec<UserStats>, ViewError> {
+        let mut owners = Vec::new();
+        if let Some(entries) = self.leaderboard.try_load_entry(&metric).await? {
+            let mut skipped = 0;
+            entries
+                .for_each_index_while(|entry| {
+                    if skipped < offset {
+                        skipped += 1;
+                    } else if owners.len() < limit {
+                        owners.push(entry.owner);
+                    }
+                    Ok(owners.len() < limit)
+                })
+                .await?;
+        }
+        let mut result = Vec::new();
+        for owner in owners {
+            if let Some(stats) = self.user_stats.get(&owner).await? {
+                result.push(stats);
+            }
         }
+        Ok(result)
+    }
+    
+    /// Get the odds history of a market between `from` and `to` (inclusive),
+    /// keeping the last snapshot of every `resolution`-wide bucket
+    async fn history(
+        &self,
+        market_id: String,
+        #[graphql(default = 0)] from: u64,
+        to: Option<u64>,
+        resolution: Option<u64>,
+    ) -> Result<Vec<OddsSnapshot>, ViewError> {
+        let to = to.unwrap_or(u64::MAX);
+        // Buckets can only be coarsened, never refined
+        let resolution = resolution
+            .unwrap_or(HISTORY_BUCKET_MICROS)
+            .max(HISTORY_BUCKET_MICROS);
+        let mut snapshots = Vec::new();
+        
+        if let Some(log) = self.odds_history.try_load_entry(&market_id).await? {
+            // Binary search for the first snapshot at or after `from`
+            let (mut low, mut high) = (0, log.count());
+            while low < high {
+                let mid = (low + high) / 2;
+                match log.get(mid).await? {
+                    Some(snapshot) if snapshot.timestamp < from => low = mid + 1,
+                    _ => high = mid,
+                }
+            }
+            for snapshot in log.read(low..log.count()).await? {
+                if snapshot.timestamp > to {
+                    break;
+                }
+                snapshots.push(snapshot);
+            }
+        }
+        if let Some(head) = self.odds_history_head.get(&market_id).await? {
+            if head.timestamp >= from && head.timestamp <= to {
+                snapshots.push(head);
+            }
+        }
+        
+        let mut result: Vec<OddsSnapshot> = Vec::new();
+        for snapshot in snapshots {
+            match result.last_mut() {
+                Some(last) if last.bucket(resolution) == snapshot.bucket(resolution) => *last = snapshot,
+                _ => result.push(snapshot),
+            }
+        }
+        Ok(result)
+    }
+    
+    /// Get up to `limit` events matching `filter`, starting after sequence number `after`.
+    /// Each call scans a bounded part of the log; continue from the returned cursor.
+    async fn events(
+        &self,
+        after: Option<u64>,
+        #[graphql(default = 100)] limit: usize,
+        filter: Option<EventFilter>,
+    ) -> Result<EventPage, ViewError> {
+        const PAGE_SIZE: usize = 100;
+        // Events scanned at most by one query, whatever the filter matches
+        const MAX_SCANNED: usize = 1_000;
+        let filter = filter.unwrap_or_default();
+        let count = self.events.count();
+        let start = match after {
+            None => 0,
+            Some(seq) => match usize::try_from(seq).ok().and_then(|seq| seq.checked_add(1)) {
+                Some(start) => start,
+                // No event can follow such a cursor
+                None => {
+                    return Ok(EventPage { events: Vec::new(), cursor: after, has_more: false });
+                }
+            },
+        };
+        let scan_end = count.min(start.saturating_add(MAX_SCANNED));
+        let mut next = start;
+        let mut events = Vec::new();
+        while next < scan_end && events.len() < limit {
+            let end = (next + PAGE_SIZE).min(scan_end);
+            for event in self.events.read(next..end).await? {
+                next += 1;
+                if filter.matches(&event) {
+                    events.push(event);
+                    if events.len() == limit {
+                        break;
+                    }
+                }
+            }
+        }
+        let cursor = if next > start { Some((next - 1) as u64) } else { after };
+        Ok(EventPage {
+            events,
+            cursor,
+            has_more: next < count,
+        })
+    }
+    
+    /// Get the protocol-wide statistics
+    async fn stats(&self) -> Result<ProtocolStats, ViewError> {
+        Ok(self.protocol_stats.get().clone())
+    }
+    
+    /// Get how the pool of a resolved market is split between the bets on
+    /// each outcome it pays, and where its dust goes
+    async fn settlement(&self, market_id: String) -> Result<Option<Settlement>, ViewError> {
+        let Some(market) = self.markets.get(&market_id).await? else {
+            return Ok(None);
+        };
+        let Some(splits) = self.pool_splits.get(&market_id).await? else {
+            return Ok(None);
+        };
+        let unclaimed = splits.iter().fold(Amount::ZERO, |unclaimed, split| {
+            unclaimed.saturating_add(split.owed().saturating_sub(split.paid_out))
+        });
+        let swept = self.swept.get(&market_id).await?.unwrap_or_default();
+        Ok(Some(Settlement {
+            dust_policy: market.dust_policy,
+            splits,
+            claim_deadline: market.claim_deadline,
+            unclaimed,
+            swept,
+            market_id,
+        }))
+    }
+    
+    /// Quote cashing out `amount` of a bet's stake, or all of it, under the
+    /// penalty set by the application parameters
+    async fn cash_out_quote(
+        &self,
+        ctx: &Context<'_>,
+        market_id: String,
+        bet_id: String,
+        amount: Option<Amount>,
+    ) -> Result<Option<CashOutQuote>, ViewError> {
+        let Some(parameters) = ctx.data_opt::<Parameters>() else {
+            return Ok(None);
+        };
+        let Some(bet) = self.bets.get(&bet_id).await? else {
+            return Ok(None);
+        };
+        if bet.market_id != market_id || bet.claimed {
+            return Ok(None);
+        }
+        // Fixed-odds bets cannot be cashed out
+        if bet.odds_bps.is_some() {
+            return Ok(None);
+        }
+        let stake = amount.unwrap_or(bet.amount);
+        if stake > bet.amount {
+            return Ok(None);
+        }
+        Ok(parameters.cash_out_penalty(stake).map(|penalty| CashOutQuote {
+            stake,
+            penalty_bps: parameters.cash_out_penalty_bps,
+            penalty,
+            payout: stake.saturating_sub(penalty),
+        }))
+    }
+    
+    /// Compare what the application owes against what it holds, in total and
+    /// for every market hosted on this chain
+    async fn audit_solvency(&self, ctx: &Context<'_>) -> Result<SolvencyReport, ViewError> {
+        let native_balance = ctx.data_opt::<ChainBalance>().map(|balance| balance.0);
+        
+        let mut collaterals = Vec::new();
+        self.collateral_ledgers.for_each_index_value(|collateral, ledger| {
+            let balance = match collateral {
+                Collateral::Native => native_balance,
+                Collateral::Token(_) => None,
+            };
+            let held = balance.unwrap_or(ledger.escrowed);
+            collaterals.push(CollateralSolvency {
+                collateral,
+                escrowed: ledger.escrowed,
+                liability: ledger.liability,
+                balance,
+                shortfall: ledger.liability.saturating_sub(held),
+            });
+            Ok(())
+        }).await?;
+        
+        let mut markets = Vec::new();
+        for market_id in self.market_ledgers.indices().await? {
+            let Some(ledger) = self.market_ledgers.get(&market_id).await? else {
+                continue;
+            };
+            let shortfall = ledger.liability.saturating_sub(ledger.escrowed);
+            if shortfall == Amount::ZERO {
+                continue;
+            }
+            let Some(market) = self.markets.get(&market_id).await? else {
+                continue;
+            };
+            markets.push(MarketSolvency {
+                market_id,
+                collateral: market.collateral,
+                escrowed: ledger.escrowed,
+                liability: ledger.liability,
+                shortfall,
+            });
+        }
+        
+        let solvent = markets.is_empty()
+            && collaterals.iter().all(|totals| totals.shortfall == Amount::ZERO);
+        Ok(SolvencyReport {
+            solvent,
+            collaterals,
+            markets,
+        })
+    }
+    
+    /// Get the applications allowed to create markets
+    async fn allowed_creators(&self) -> Result<Vec<ApplicationId>, ViewError> {
+        self.allowed_creators.indices().await
     }
     
     /// Get the current admin
@@ -152,35 +1211,578 @@ impl CascadeProtocol {
     pub async fn add_bet(&mut self, bet: Bet) -> Result<(), ViewError> {
         // Add to owner's bets
         let mut owner_bets = self.bets_by_owner.get(&bet.owner).await?.unwrap_or_default();
-        owner_bets.push(bet.clone());
+        owner_bets.push(bet.id.clone());
         self.bets_by_owner.insert(&bet.owner, owner_bets)?;
         
         // Add to market's bets
-        let mut market_bets = self.bets_by_market.get(&bet.market_id).await?.unwrap_or_default();
-        market_bets.push(bet.clone());
-        self.bets_by_market.insert(&bet.market_id, market_bets)?;
+        self.bets_by_market.load_entry_mut(&bet.market_id).await?.push(bet.id.clone());
+        self.bets.insert(&bet.id.clone(), bet)?;
         
         Ok(())
     }
     
+    /// Remove a bet from storage, e.g. once its whole stake was transferred.
+    /// Its ID stays in the market's log, where it is skipped.
+    pub async fn remove_bet(&mut self, bet: &Bet) -> Result<(), ViewError> {
+        if let Some(mut owner_bets) = self.bets_by_owner.get(&bet.owner).await? {
+            owner_bets.retain(|id| *id != bet.id);
+            self.bets_by_owner.insert(&bet.owner, owner_bets)?;
+        }
+        self.bets.remove(&bet.id)?;
+        Ok(())
+    }
+    
+    /// Get the bets of an owner on markets hosted on this chain
+    pub async fn owner_bets(&self, owner: &AccountOwner) -> Result<Vec<Bet>, ViewError> {
+        let ids = self.bets_by_owner.get(owner).await?.unwrap_or_default();
+        let mut bets = Vec::with_capacity(ids.len());
+        for id in ids {
+            bets.extend(self.bets.get(&id).await?);
+        }
+        Ok(bets)
+    }
+    
+    /// Get the bets at the given positions of a market's log, skipping those
+    /// removed since
+    pub async fn market_bets(&self, market_id: &str, range: Range<usize>) -> Result<Vec<Bet>, ViewError> {
+        let Some(log) = self.bets_by_market.try_load_entry(market_id).await? else {
+            return Ok(Vec::new());
+        };
+        let end = range.end.min(log.count());
+        let start = range.start.min(end);
+        let mut bets = Vec::new();
+        for id in log.read(start..end).await? {
+            bets.extend(self.bets.get(&id).await?);
+        }
+        Ok(bets)
+    }
+    
+    /// The number of bets ever placed on a market, including those removed
+    /// since
+    pub async fn market_bet_count(&self, market_id: &str) -> Result<usize, ViewError> {
+        Ok(self
+            .bets_by_market
+            .try_load_entry(market_id)
+            .await?
+            .map_or(0, |log| log.count()))
+    }
+    
+    /// Apply an update to the bets an owner placed from this chain on markets
+    /// hosted elsewhere
+    pub async fn update_mirrored_bets(
+        &mut self,
+        owner: AccountOwner,
+        update: impl FnOnce(&mut Vec<Bet>),
+    ) -> Result<(), ViewError> {
+        let mut bets = self.mirrored_bets.get(&owner).await?.unwrap_or_default();
+        update(&mut bets);
+        self.mirrored_bets.insert(&owner, bets)?;
+        Ok(())
+    }
+    
     /// Update a bet (e.g., mark as claimed)
     pub async fn update_bet(&mut self, bet: Bet) -> Result<(), ViewError> {
-        // Update in owner's bets
-        if let Some(mut owner_bets) = self.bets_by_owner.get(&bet.owner).await? {
-            if let Some(pos) = owner_bets.iter().position(|b| b.id == bet.id) {
-                owner_bets[pos] = bet.clone();
-                self.bets_by_owner.insert(&bet.owner, owner_bets)?;
+        self.bets.insert(&bet.id.clone(), bet)
+    }
+    
+    /// Record the stakes of a market after a bet, replacing the previous
+    /// snapshot if it falls into the same history bucket
+    pub async fn record_odds_snapshot(&mut self, market: &Market, timestamp: u64) -> Result<(), ViewError> {
+        let snapshot = OddsSnapshot::of_market(market, timestamp);
+        if let Some(head) = self.odds_history_head.get(&market.id).await? {
+            if head.bucket(HISTORY_BUCKET_MICROS) != snapshot.bucket(HISTORY_BUCKET_MICROS) {
+                // The previous bucket is closed, archive its last snapshot
+                self.odds_history.load_entry_mut(&market.id).await?.push(head);
             }
         }
+        self.odds_history_head.insert(&market.id, snapshot)?;
+        Ok(())
+    }
+    
+    /// Get an open listing of a market
+    pub async fn listing(
+        &self,
+        market_id: &str,
+        listing_id: &str,
+    ) -> Result<Option<Listing>, ViewError> {
+        match self.listings.try_load_entry(market_id).await? {
+            Some(listings) => listings.get(listing_id).await,
+            None => Ok(None),
+        }
+    }
+    
+    /// Get every open listing of a market
+    pub async fn market_listings(&self, market_id: &str) -> Result<Vec<Listing>, ViewError> {
+        let mut result = Vec::new();
+        if let Some(listings) = self.listings.try_load_entry(market_id).await? {
+            listings
+                .for_each_index_value(|_, listing| {
+                    result.push(listing.clone());
+                    Ok(())
+                })
+                .await?;
+        }
+        Ok(result)
+    }
+    
+    /// Add a resting order to its market's book, behind the orders placed
+    /// before it at the same price
+    pub async fn add_order(&mut self, order: Order) -> Result<(), ViewError> {
+        let seq = *self.order_count.get();
+        self.order_count.set(seq + 1);
+        let side = BookSide::of(&order);
+        let key = OrderKey::new(order.side, order.price_bps, seq);
+        self.order_keys.insert(&order.id, (side.clone(), key))?;
+        self.orders.load_entry_mut(&side).await?.insert(&key, order)?;
+        Ok(())
+    }
+    
+    /// Get a resting order by ID
+    pub async fn resting_order(&self, order_id: &str) -> Result<Option<Order>, ViewError> {
+        let Some((side, key)) = self.order_keys.get(order_id).await? else {
+            return Ok(None);
+        };
+        match self.orders.try_load_entry(&side).await? {
+            Some(book) => book.get(&key).await,
+            None => Ok(None),
+        }
+    }
+    
+    /// Update a resting order after a fill, removing it once it is filled
+    pub async fn update_order(&mut self, order: Order) -> Result<(), ViewError> {
+        if order.remaining == Amount::ZERO {
+            return self.remove_order(&order.id).await;
+        }
+        if let Some((side, key)) = self.order_keys.get(&order.id).await? {
+            self.orders.load_entry_mut(&side).await?.insert(&key, order)?;
+        }
+        Ok(())
+    }
+    
+    /// Remove a resting order from its market's book
+    pub async fn remove_order(&mut self, order_id: &str) -> Result<(), ViewError> {
+        if let Some((side, key)) = self.order_keys.get(order_id).await? {
+            self.orders.load_entry_mut(&side).await?.remove(&key)?;
+            self.order_keys.remove(order_id)?;
+        }
+        Ok(())
+    }
+    
+    /// Get the resting orders on one side of a market's book whose priority
+    /// is at most `bound`, best first
+    pub async fn crossing_orders(
+        &self,
+        side: &BookSide,
+        bound: u16,
+    ) -> Result<Vec<(OrderKey, Order)>, ViewError> {
+        let mut orders = Vec::new();
+        if let Some(book) = self.orders.try_load_entry(side).await? {
+            book.for_each_index_value_while(|key, order| {
+                if key.priority() > bound {
+                    return Ok(false);
+                }
+                orders.push((key, order.clone()));
+                Ok(true)
+            })
+            .await?;
+        }
+        Ok(orders)
+    }
+    
+    /// Get every resting order of a market's book
+    pub async fn market_orders(&self, market_id: &str) -> Result<Vec<Order>, ViewError> {
+        let mut orders = Vec::new();
+        for side in [OrderSide::Buy, OrderSide::Sell] {
+            let side = BookSide {
+                market_id: market_id.to_string(),
+                side,
+            };
+            let book = self.crossing_orders(&side, u16::MAX).await?;
+            orders.extend(book.into_iter().map(|(_, order)| order));
+        }
+        Ok(orders)
+    }
+    
+    /// Remove every resting order of a market's book, and return them
+    pub async fn clear_orders(&mut self, market_id: &str) -> Result<Vec<Order>, ViewError> {
+        let orders = self.market_orders(market_id).await?;
+        for order in &orders {
+            self.order_keys.remove(&order.id)?;
+        }
+        for side in [OrderSide::Buy, OrderSide::Sell] {
+            self.orders.remove_entry(&BookSide {
+                market_id: market_id.to_string(),
+                side,
+            })?;
+        }
+        Ok(orders)
+    }
+    
+    /// Total stake of an owner on an outcome of a market
+    pub async fn position(&self, owner: AccountOwner, market_id: &str, outcome_id: &str) -> Result<Amount, ViewError> {
+        let owner_bets = self.owner_bets(&owner).await?;
+        Ok(owner_bets
+            .iter()
+            .filter(|bet| bet.market_id == market_id && bet.outcome_id == outcome_id)
+            .fold(Amount::ZERO, |total, bet| total.saturating_add(bet.amount)))
+    }
+    
+    /// Record a rejected request of an owner
+    pub async fn add_rejection(&mut self, owner: AccountOwner, rejection: Rejection) -> Result<(), ViewError> {
+        let mut rejections = self.rejections.get(&owner).await?.unwrap_or_default();
+        rejections.push(rejection);
+        self.rejections.insert(&owner, rejections)?;
+        Ok(())
+    }
+    
+    /// Register a chain as a subscriber of a target
+    pub async fn add_subscriber(&mut self, target: SubscriptionTarget, chain_id: ChainId) -> Result<(), ViewError> {
+        let mut chains = self.subscribers.get(&target).await?.unwrap_or_default();
+        if !chains.contains(&chain_id) {
+            chains.push(chain_id);
+            self.subscribers.insert(&target, chains)?;
+        }
+        Ok(())
+    }
+    
+    /// Remove a chain from the subscribers of a target
+    pub async fn remove_subscriber(&mut self, target: SubscriptionTarget, chain_id: ChainId) -> Result<(), ViewError> {
+        let mut chains = self.subscribers.get(&target).await?.unwrap_or_default();
+        chains.retain(|chain| *chain != chain_id);
+        if chains.is_empty() {
+            self.subscribers.remove(&target)?;
+        } else {
+            self.subscribers.insert(&target, chains)?;
+        }
+        Ok(())
+    }
+    
+    /// Chains subscribed to a market, either directly or through its category
+    pub async fn subscribers_of(&self, market: &Market) -> Result<Vec<ChainId>, ViewError> {
+        let mut chains = self
+            .subscribers
+            .get(&SubscriptionTarget::Market(market.id.clone()))
+            .await?
+            .unwrap_or_default();
+        let category_chains = self
+            .subscribers
+            .get(&SubscriptionTarget::Category(market.category))
+            .await?
+            .unwrap_or_default();
+        for chain_id in category_chains {
+            if !chains.contains(&chain_id) {
+                chains.push(chain_id);
+            }
+        }
+        Ok(chains)
+    }
+    
+    /// Append an event to the log, assigning it the next sequence number
+    pub fn append_event(&mut self, timestamp: u64, payload: EventPayload) {
+        let seq = self.events.count() as u64;
+        self.events.push(Event { seq, timestamp, payload });
+    }
+    
+    /// Apply an update to the protocol-wide statistics, and return its result
+    pub fn update_protocol_stats<T>(&mut self, update: impl FnOnce(&mut ProtocolStats) -> T) -> T {
+        update(self.protocol_stats.get_mut())
+    }
+    
+    /// Apply an update to a user's statistics and refresh the leaderboard
+    /// indexes, and return the update's result
+    pub async fn update_user_stats<T>(
+        &mut self,
+        owner: AccountOwner,
+        update: impl FnOnce(&mut UserStats) -> T,
+    ) -> Result<T, ViewError> {
+        let previous = self.user_stats.get(&owner).await?;
+        let mut stats = previous.clone().unwrap_or_else(|| UserStats::new(owner));
+        let result = update(&mut stats);
         
-        // Update in market's bets
-        if let Some(mut market_bets) = self.bets_by_market.get(&bet.market_id).await? {
-            if let Some(pos) = market_bets.iter().position(|b| b.id == bet.id) {
-                market_bets[pos] = bet.clone();
-                self.bets_by_market.insert(&bet.market_id, market_bets)?;
+        // Only the entries whose score changed are moved
+        for metric in LeaderboardMetric::ALL {
+            let entry = LeaderboardEntry::new(owner, stats.score(metric));
+            let index = self.leaderboard.load_entry_mut(&metric).await?;
+            if let Some(previous) = &previous {
+                let old_entry = LeaderboardEntry::new(owner, previous.score(metric));
+                if old_entry == entry {
+                    continue;
+                }
+                index.remove(&old_entry)?;
             }
+            index.insert(&entry)?;
         }
         
-        Ok(())
+        self.user_stats.insert(&owner, stats)?;
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{event::AdminChangedEvent, msg::MarketMechanism};
+    use async_graphql::{EmptyMutation, EmptySubscription, Request, Schema, Variables};
+    use linera_sdk::{
+        base::CryptoHash,
+        util::BlockingWait,
+        views::{KeyValueStore, View},
+    };
+
+    fn owner(n: u64) -> AccountOwner {
+        AccountOwner::User(Owner(CryptoHash::from([n; 4])))
+    }
+
+    /// The state saved in a store, or an empty one
+    fn load(store: &KeyValueStore) -> CascadeProtocol {
+        let context = ViewStorageContext::new_unsafe(store.clone(), Vec::new(), ());
+        CascadeProtocol::load(context).blocking_wait().expect("Failed to load state")
+    }
+
+    /// An active market of 10 tokens, 6 of them staked on its first outcome
+    /// and 4 on its second: "yes" and "no", or "long" and "short" for a
+    /// scalar market. Markets are priced as pools of bets.
+    fn market(mechanism: MarketMechanism) -> Market {
+        let (names, scalar) = match mechanism {
+            MarketMechanism::Scalar { lower, upper } => {
+                (["long", "short"], Some(ScalarRange::new(lower, upper)))
+            }
+            _ => (["yes", "no"], None),
+        };
+        let outcome = |id: &str, staked: u128| Outcome {
+            id: id.to_string(),
+            name: id.to_string(),
+            total_staked: Amount::from_tokens(staked),
+        };
+        Market {
+            id: "market_1".to_string(),
+            question: "Will it rain?".to_string(),
+            outcomes: vec![outcome(names[0], 6), outcome(names[1], 4)],
+            total_staked: Amount::from_tokens(10),
+            status: MarketStatus::Active,
+            expiry_time: 1,
+            winning_outcome_id: None,
+            parent_id: None,
+            category: MarketCategory::Other,
+            collateral: Collateral::Native,
+            claim_window: None,
+            claim_deadline: None,
+            amm: None,
+            order_book: false,
+            fixed_odds: None,
+            scalar,
+            buckets: None,
+            dust_policy: DustPolicy::LastClaimer,
+        }
+    }
+
+    /// The order of an index walk, which is that of the serialized keys
+    fn ranked(mut entries: Vec<LeaderboardEntry>) -> Vec<(AccountOwner, i128)> {
+        entries.sort_by_key(|entry| bcs::to_bytes(entry).unwrap());
+        entries.iter().map(|entry| (entry.owner, entry.score())).collect()
+    }
+
+    #[test]
+    fn test_leaderboard_ranks_by_descending_score() {
+        let scores = [0, -5, 7, i128::MIN, i128::MAX, 1 << 70, -(1 << 70), 7];
+        let entries = scores
+            .iter()
+            .enumerate()
+            .map(|(i, score)| LeaderboardEntry::new(owner(i as u64), *score))
+            .collect();
+        let ranking = ranked(entries);
+
+        let mut expected: Vec<_> = scores
+            .iter()
+            .enumerate()
+            .map(|(i, score)| (owner(i as u64), *score))
+            .collect();
+        expected.sort_by(|a, b| {
+            let key = |(owner, _): &(AccountOwner, i128)| bcs::to_bytes(owner).unwrap();
+            b.1.cmp(&a.1).then_with(|| key(a).cmp(&key(b)))
+        });
+        assert_eq!(ranking, expected);
+    }
+
+    #[test]
+    fn test_stats_updates_move_entries() {
+        let store = KeyValueStore::mock().to_mut();
+        let mut state = load(&store);
+        let mut update = |owner, update: &dyn Fn(&mut UserStats)| {
+            state.update_user_stats(owner, update).blocking_wait().unwrap();
+            state.save().blocking_wait().unwrap();
+        };
+        // The owners returned by a `leaderboard` query on the saved state
+        let ranking = |metric: &str, limit: usize, offset: usize| {
+            let schema = Schema::new(load(&store), EmptyMutation, EmptySubscription);
+            let query = format!(
+                "{{ leaderboard(metric: {metric}, limit: {limit}, offset: {offset}) {{ owner }} }}"
+            );
+            let response = schema.execute(query).blocking_wait();
+            assert!(response.errors.is_empty(), "{:?}", response.errors);
+            let data = response.data.into_json().unwrap();
+            data["leaderboard"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|entry| serde_json::from_value(entry["owner"].clone()).unwrap())
+                .collect::<Vec<AccountOwner>>()
+        };
+
+        update(owner(1), &|stats| stats.realized_gains = Amount::from_tokens(3));
+        update(owner(2), &|stats| stats.realized_losses = Amount::from_tokens(1));
+        assert_eq!(ranking("PROFIT", 10, 0), [owner(1), owner(2)]);
+
+        // Bob wins big: his old entry is replaced and he moves up
+        update(owner(2), &|stats| stats.realized_gains = Amount::from_tokens(10));
+        assert_eq!(ranking("PROFIT", 10, 0), [owner(2), owner(1)]);
+        assert_eq!(ranking("PROFIT", 1, 1), [owner(1)]);
+
+        // Win rate is kept in basis points
+        update(owner(2), &|stats| {
+            stats.markets_won = 1;
+            stats.markets_lost = 2;
+        });
+        assert_eq!(ranking("WIN_RATE", 10, 0), [owner(2), owner(1)]);
+        let bob = load(&store).user_stats.get(&owner(2)).blocking_wait().unwrap().unwrap();
+        assert_eq!(bob.score(LeaderboardMetric::WinRate), 3_333);
+    }
+
+    #[test]
+    fn test_events_page_from_any_cursor() {
+        let store = KeyValueStore::mock().to_mut();
+        let mut state = load(&store);
+        for n in 1..=3 {
+            let new_admin = Owner(CryptoHash::from([n; 4]));
+            state.append_event(n, EventPayload::AdminChanged(AdminChangedEvent {
+                previous_admin: None,
+                new_admin,
+            }));
+        }
+        state.save().blocking_wait().unwrap();
+        // The sequence numbers, cursor and `hasMore` flag of an `events` query
+        let page = |after: Option<u64>, limit: usize| {
+            let schema = Schema::new(load(&store), EmptyMutation, EmptySubscription);
+            let query = "query($after: Int, $limit: Int!) { \
+                events(after: $after, limit: $limit) { events { seq } cursor hasMore } }";
+            let variables = serde_json::json!({ "after": after, "limit": limit });
+            let request = Request::new(query).variables(Variables::from_json(variables));
+            let response = schema.execute(request).blocking_wait();
+            assert!(response.errors.is_empty(), "{:?}", response.errors);
+            let data = response.data.into_json().unwrap();
+            serde_json::from_value::<(Vec<u64>, Option<u64>, bool)>(serde_json::json!([
+                data["events"]["events"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|event| event["seq"].clone())
+                    .collect::<Vec<_>>(),
+                data["events"]["cursor"],
+                data["events"]["hasMore"],
+            ]))
+            .unwrap()
+        };
+
+        assert_eq!(page(None, 2), (vec![0, 1], Some(1), true));
+        assert_eq!(page(Some(1), 2), (vec![2], Some(2), false));
+        assert_eq!(page(Some(2), 2), (vec![], Some(2), false));
+        // A cursor past the end of the log gives an empty page (GraphQL integers stop at i64)
+        let far = i64::MAX as u64;
+        assert_eq!(page(Some(far), 2), (vec![], Some(far), false));
+    }
+
+    #[test]
+    fn test_dust_policies_pay_out_the_whole_pool() {
+        // Winning stakes of 7 attos, and a cash-out penalty of 3 attos that
+        // stayed in the pool, so that no share divides evenly
+        let bets: Vec<(String, Amount)> = [("a", 2), ("b", 2), ("c", 2), ("d", 1)]
+            .iter()
+            .map(|(id, stake)| (id.to_string(), Amount::from_attos(*stake)))
+            .collect();
+        let penalty = Amount::from_attos(3);
+        let pool = Amount::from_attos(7).try_add(penalty).unwrap();
+        // No protocol fee is taken from the pool
+        let fees = Amount::ZERO;
+        // Shares are 20/7 and 10/7 attos, rounded down, which leaves 3 attos
+        // of dust. The last bet paid receives all of it, or the treasury
+        // does, or the stakes of 2 attos, whose shares lost 6/7 of an atto
+        // where the other lost 3/7.
+        let cases = [
+            (DustPolicy::LastClaimer, [2, 2, 2, 4], 0),
+            (DustPolicy::Treasury, [2, 2, 2, 1], 3),
+            (DustPolicy::LargestRemainder, [3, 3, 3, 1], 0),
+        ];
+        for (policy, expected, to_treasury) in cases {
+            let mut split = PoolSplit::new("yes".to_string(), pool, &bets, policy).unwrap();
+            assert_eq!(split.dust, Amount::from_attos(3));
+            let payouts: Vec<Amount> = bets
+                .iter()
+                .map(|(id, stake)| split.pay(id, *stake).unwrap())
+                .collect();
+            assert_eq!(payouts, expected.map(Amount::from_attos), "{policy:?}");
+
+            let dust = split.pool.try_sub(split.owed()).unwrap();
+            assert_eq!(dust, Amount::from_attos(to_treasury));
+            let total = split.paid_out.try_add(fees).unwrap().try_add(dust).unwrap();
+            assert_eq!(total, pool, "{policy:?}");
+            assert_eq!(split.unpaid_stake, Amount::ZERO);
+
+            // Nothing is left to pay
+            assert_eq!(split.pay("a", Amount::from_attos(1)), None);
+        }
+    }
+
+    #[test]
+    fn test_cash_out_penalty_stays_in_the_pool() {
+        let parameters = Parameters {
+            default_collateral: Collateral::Native,
+            allowed_tokens: Vec::new(),
+            treasury: None,
+            claim_window: None,
+            cash_out_penalty_bps: 250,
+        };
+        // 2.5% of 999 attos is 24.975, rounded down
+        assert_eq!(
+            parameters.cash_out_penalty(Amount::from_attos(999)),
+            Some(Amount::from_attos(24))
+        );
+        assert_eq!(
+            parameters.cash_out_penalty(Amount::from_tokens(4)),
+            Some(Amount::from_millis(100))
+        );
+
+        let mut market = market(MarketMechanism::Parimutuel);
+        let stake = Amount::from_tokens(4);
+        let penalty = parameters.cash_out_penalty(stake).unwrap();
+        let payout = market.cash_out("yes", stake, penalty).unwrap();
+        assert_eq!(payout, Amount::from_millis(3_900));
+        // The whole stake leaves the outcome, only the payout leaves the pool
+        assert_eq!(market.outcomes[0].total_staked, Amount::from_tokens(2));
+        assert_eq!(market.total_staked, Amount::from_millis(6_100));
+        // The penalty is left over the stakes, for the winners
+        let stakes = Amount::from_tokens(2).try_add(Amount::from_tokens(4)).unwrap();
+        assert_eq!(market.total_staked.try_sub(stakes).unwrap(), penalty);
+
+        // An outcome cannot give back more than it holds
+        assert_eq!(market.cash_out("yes", Amount::from_tokens(3), Amount::ZERO), None);
+        assert_eq!(market.outcomes[0].total_staked, Amount::from_tokens(2));
+    }
+
+    #[test]
+    fn test_unbacked_scalar_side_leaves_its_part_to_the_other() {
+        let mut market = market(MarketMechanism::Scalar { lower: 0, upper: 100 });
+        market.status = MarketStatus::Resolved;
+        market.winning_outcome_id = Some("short".to_string());
+        if let Some(range) = market.scalar.as_mut() {
+            range.value = Some(25);
+        }
+        // Both sides are paid their part, whichever outcome is the winner
+        assert_eq!(market.payout_pool("long"), Amount::from_millis(2_500));
+        assert_eq!(market.payout_pool("short"), Amount::from_millis(7_500));
+
+        market.outcomes[1].total_staked = Amount::ZERO;
+        market.total_staked = Amount::from_tokens(6);
+        assert_eq!(market.payout_pool("long"), Amount::from_tokens(6));
+        assert_eq!(market.payout_pool("short"), Amount::ZERO);
     }
 }
@@ -0,0 +1,54 @@
+use linera_sdk::{
+    abi::{ContractAbi, ServiceAbi},
+    base::{AccountOwner, Amount, ChainId},
+};
+use serde::{Deserialize, Serialize};
+
+/// The ABI of Linera's fungible token application, mirrored here so that
+/// markets can hold stakes in any token that implements it. Only the shape of
+/// operations and responses matters, as they are exchanged in BCS.
+pub struct FungibleTokenAbi;
+
+impl ContractAbi for FungibleTokenAbi {
+    type Operation = FungibleOperation;
+    type Response = FungibleResponse;
+}
+
+impl ServiceAbi for FungibleTokenAbi {
+    type Query = async_graphql::Request;
+    type QueryResponse = async_graphql::Response;
+}
+
+/// An account of a fungible token
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FungibleAccount {
+    pub chain_id: ChainId,
+    pub owner: AccountOwner,
+}
+
+/// Operations of the fungible token application
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum FungibleOperation {
+    Balance {
+        owner: AccountOwner,
+    },
+    TickerSymbol,
+    Transfer {
+        owner: AccountOwner,
+        amount: Amount,
+        target_account: FungibleAccount,
+    },
+    Claim {
+        source_account: FungibleAccount,
+        amount: Amount,
+        target_account: FungibleAccount,
+    },
+}
+
+/// Responses of the fungible token application
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum FungibleResponse {
+    Ok,
+    Balance(Amount),
+    TickerSymbol(String),
+}